@@ -0,0 +1,360 @@
+//! Fuzz targets for `chip8::Vm`.
+//!
+//! Every target takes an arbitrary byte string, builds a VM from it and runs it for up to
+//! `MAX_CYCLES` instructions. A target fails by panicking:
+//!
+//! - `vm` only runs the VM, so it fails if the VM panics,
+//! - `diff` runs `reference::Reference` side by side with the VM and fails on the first
+//!   difference in registers, `I`, `PC`, stack, timers, memory, screen, random source or
+//!   faults.
+//!
+//! Inputs are decoded as follows, missing bytes are zeroes:
+//!
+//! | Offset   | Size | Description                                                   |
+//! |----------|------|---------------------------------------------------------------|
+//! | 0        | 1    | Quirks in bits 0-6 as in `Quirks::to_bits`, bit 7 selects the |
+//! |          |      | `vip` random source                                           |
+//! | 1        | 1    | Instructions per timer tick, 1 + value % 32                   |
+//! | 2        | 16   | `V0`-`VF`                                                     |
+//! | 18       | 2    | `I`, big-endian                                               |
+//! | 20       | 1    | `DT`                                                          |
+//! | 21       | 1    | `ST`                                                          |
+//! | 22       | 1    | Number of keypad states `K`, value % 8                        |
+//! | 23       | 2 K  | Keypad states as big-endian bit masks, one per frame in turn  |
+//! | 23 + 2 K |      | ROM loaded at `0x200`, truncated to fit in memory             |
+
+extern crate chip8;
+
+pub mod reference;
+
+use std::collections::HashSet;
+use std::fmt;
+
+use chip8::display::Framebuffer;
+use chip8::instruction::Reg;
+use chip8::random;
+use chip8::{Env, Error, Quirks, Vm};
+
+use reference::Reference;
+
+pub const MAX_CYCLES: u64 = 2000;
+
+const HEADER_LEN: usize = 23;
+const RANDOM_SEED: u32 = 2281;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Target {
+    Vm,
+    Diff,
+}
+
+impl Target {
+    pub fn from_name(name: &str) -> Option<Target> {
+        match name {
+            "vm" => Some(Target::Vm),
+            "diff" => Some(Target::Diff),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Target::Vm => "vm",
+            Target::Diff => "diff",
+        }
+    }
+
+    /// Runs the target on `data`, returning coverage of the run.
+    pub fn run(self, data: &[u8]) -> Coverage {
+        run(&Input::parse(data), self == Target::Diff)
+    }
+}
+
+/// Features of a run used to tell whether an input is interesting: for every executed
+/// instruction opcode, whether it fell through, changed the control flow or faulted,
+/// and whether an unrecognized instruction was met.
+pub type Coverage = HashSet<u32>;
+
+pub struct Input<'a> {
+    quirks: Quirks,
+    random: &'static str,
+    cycles_per_frame: u64,
+    regs: [u8; 16],
+    i: u16,
+    dt: u8,
+    st: u8,
+    keypad_states: Vec<u16>,
+    rom: &'a [u8],
+}
+
+impl<'a> Input<'a> {
+    pub fn parse(data: &'a [u8]) -> Input<'a> {
+        let byte = |offset: usize| data.get(offset).cloned().unwrap_or(0);
+        let word = |offset: usize| (byte(offset) as u16) << 8 | byte(offset + 1) as u16;
+
+        let bits = byte(0);
+        let quirks = Quirks::from_bits(bits);
+        let random = if bits & 0x80 != 0 { "vip" } else { "xorshift" };
+        let mut regs = [0; 16];
+        for (index, reg) in regs.iter_mut().enumerate() {
+            *reg = byte(2 + index);
+        }
+        let keypad_state_count = byte(22) as usize % 8;
+        let keypad_states = (0..keypad_state_count)
+            .map(|index| word(HEADER_LEN + 2 * index))
+            .collect();
+
+        Input {
+            quirks,
+            random,
+            cycles_per_frame: 1 + byte(1) as u64 % 32,
+            regs,
+            i: word(18),
+            dt: byte(20),
+            st: byte(21),
+            keypad_states,
+            rom: data.get(Input::rom_offset(data)..).unwrap_or(&[]),
+        }
+    }
+
+    /// Returns offset of the ROM in `data`.
+    pub fn rom_offset(data: &[u8]) -> usize {
+        let keypad_state_count = data.get(22).cloned().unwrap_or(0) as usize % 8;
+        HEADER_LEN + 2 * keypad_state_count
+    }
+
+    /// Creates a VM in the state described by the input.
+    pub fn vm(&self) -> Vm {
+        // ROMs which don't fit are rejected by the VM, then run with what fits.
+        let mut vm = match Vm::with_rom(self.rom, self.quirks) {
+            Ok(vm) => vm,
+            Err(Error::RomTooLarge { len, max }) => {
+                assert!(len > max && len == self.rom.len());
+                Vm::with_rom(&self.rom[..max], self.quirks).expect("ROM fits into memory")
+            }
+            Err(e) => panic!("unexpected error: {}", e),
+        };
+        for (index, &value) in self.regs.iter().enumerate() {
+            vm.set_reg(Reg::from_index(index as u8), value);
+        }
+        vm.set_i(self.i);
+        vm.set_delay_timer(self.dt);
+        vm.set_sound_timer(self.st);
+        vm.set_random(random::from_name(self.random, RANDOM_SEED).expect("built-in random source"));
+        vm
+    }
+
+    /// Returns the keypad state during `frame`.
+    pub fn keyboard_at(&self, frame: u64) -> [u8; 16] {
+        let mut keyboard = [0; 16];
+        if !self.keypad_states.is_empty() {
+            let state = self.keypad_states[(frame % self.keypad_states.len() as u64) as usize];
+            for (key, pressed) in keyboard.iter_mut().enumerate() {
+                *pressed = (state >> key & 1) as u8;
+            }
+        }
+        keyboard
+    }
+}
+
+/// Returns the bits of an instruction word which select the instruction, e.g. `0x8004` for `8xy4`.
+pub fn opcode(word: u16) -> u16 {
+    match word & 0xF000 {
+        0x0000 => {
+            match word {
+                0x00E0 | 0x00EE | 0x00FB..=0x00FF => word,
+                _ if word & 0xFFE0 == 0x00C0 => word & 0xFFF0,
+                _ => 0x0000,
+            }
+        }
+        0x5000 | 0x8000 | 0x9000 => word & 0xF00F,
+        0xE000 => word & 0xF0FF,
+        0xF000 => {
+            match word & 0xFF {
+                0x00 | 0x02 => word,
+                0x01 => 0xF001,
+                _ => word & 0xF0FF,
+            }
+        }
+        _ => word & 0xF000,
+    }
+}
+
+fn run(input: &Input, differential: bool) -> Coverage {
+    let mut vm = input.vm();
+    let mut reference = if differential { Some(Reference::new(&vm)) } else { None };
+    let mut env = Env {
+        display: Framebuffer::new(),
+        keyboard: [0; 16],
+    };
+    let mut reference_display = Framebuffer::new();
+    let mut coverage = Coverage::new();
+
+    for cycle in 0..MAX_CYCLES {
+        if cycle > 0 && cycle % input.cycles_per_frame == 0 {
+            vm.update_timers(1);
+            if let Some(reference) = reference.as_mut() {
+                reference.tick();
+            }
+        }
+        env.keyboard = input.keyboard_at(cycle / input.cycles_per_frame);
+
+        let pc = vm.pc();
+        let word = vm.memory.get(pc as usize..pc as usize + 2).map(|bytes| {
+            (bytes[0] as u16) << 8 | bytes[1] as u16
+        });
+        let executes = !vm.is_halted() && !vm.is_waiting_for_key() && !vm.is_waiting_for_vblank();
+
+        let result = vm.cycle(&mut env);
+
+        if let (true, Some(word)) = (executes, word) {
+            let outcome = match result {
+                Ok(()) if vm.pc() == pc.wrapping_add(2) => 0,
+                Ok(()) => 1,
+                Err(Error::UnrecognizedInstruction(_)) => 3,
+                Err(_) => 2,
+            };
+            let opcode = if outcome == 3 { 0 } else { opcode(word) };
+            coverage.insert((opcode as u32) << 2 | outcome);
+        }
+
+        if let Some(reference) = reference.as_mut() {
+            let expected = reference.step(&mut reference_display, &env.keyboard);
+            let differences = match (&result, &expected) {
+                (&Ok(()), &Ok(())) => {
+                    compare(&vm, &env.display, reference, &reference_display)
+                }
+                _ => {
+                    let (result, expected) = (format!("{:?}", result), format!("{:?}", expected));
+                    if result == expected {
+                        Vec::new()
+                    } else {
+                        vec![format!("result: {}, expected {}", result, expected)]
+                    }
+                }
+            };
+            if !differences.is_empty() {
+                let instruction = match word {
+                    Some(word) => format!("{:04X}", word),
+                    None => "nothing".to_string(),
+                };
+                panic!(
+                    "VM differs from the reference after executing {} at {:04X} (cycle {}):\n  {}",
+                    instruction,
+                    pc,
+                    cycle,
+                    differences.join("\n  ")
+                );
+            }
+        }
+
+        if result.is_err() || vm.is_halted() {
+            break;
+        }
+    }
+    coverage
+}
+
+/// Describes differences between states of the VM and the reference.
+fn compare(vm: &Vm, display: &Framebuffer, reference: &Reference, reference_display: &Framebuffer) -> Vec<String> {
+    let mut differences = Vec::new();
+
+    for (index, &expected) in reference.v.iter().enumerate() {
+        let actual = vm.reg(Reg::from_index(index as u8));
+        if actual != expected {
+            differences.push(format!("V{:X}: {:02X}, expected {:02X}", index, actual, expected));
+        }
+    }
+    if vm.i() != reference.i {
+        differences.push(format!("I: {:04X}, expected {:04X}", vm.i(), reference.i));
+    }
+    if vm.pc() != reference.pc {
+        differences.push(format!("PC: {:04X}, expected {:04X}", vm.pc(), reference.pc));
+    }
+    if vm.stack() != &reference.stack[..] {
+        differences.push(format!("stack: {:04X?}, expected {:04X?}", vm.stack(), reference.stack));
+    }
+    {
+        let mut check = |name: &str, actual: &dyn fmt::Debug, expected: &dyn fmt::Debug, equal: bool| {
+            if !equal {
+                differences.push(format!("{}: {:?}, expected {:?}", name, actual, expected));
+            }
+        };
+        check("DT", &vm.delay_timer(), &reference.dt, vm.delay_timer() == reference.dt);
+        check("ST", &vm.sound_timer(), &reference.st, vm.sound_timer() == reference.st);
+        check("halted", &vm.is_halted(), &reference.halted, vm.is_halted() == reference.halted);
+        let waiting = reference.wait_key.is_some();
+        check("waiting for key", &vm.is_waiting_for_key(), &waiting, vm.is_waiting_for_key() == waiting);
+        check("cycles", &vm.cycles(), &reference.cycles, vm.cycles() == reference.cycles);
+        check(
+            "audio pattern",
+            &vm.audio_pattern(),
+            &reference.audio_pattern,
+            vm.audio_pattern() == reference.audio_pattern,
+        );
+    }
+    if vm.random().state() != reference.random.state() {
+        differences.push(format!(
+            "random state: {:02X?}, expected {:02X?}",
+            vm.random().state(),
+            reference.random.state()
+        ));
+    }
+    if vm.memory != reference.memory {
+        let addr = (0..vm.memory.len())
+            .find(|&addr| vm.memory.get(addr) != reference.memory.get(addr))
+            .unwrap_or(vm.memory.len());
+        differences.push(format!(
+            "memory at {:04X}: {:02X?}, expected {:02X?}",
+            addr,
+            vm.memory.get(addr),
+            reference.memory.get(addr)
+        ));
+    }
+    if display != reference_display {
+        differences.push(format!("screen:\n{}\nexpected:\n{}", display.to_text(), reference_display.to_text()));
+    }
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn decodes_missing_bytes_as_zeroes() {
+        let input = Input::parse(&[0x41, 7, 1, 2, 3]);
+        assert!(input.quirks.extended_memory);
+        assert!(input.quirks.shift_uses_vy);
+        assert_eq!(input.cycles_per_frame, 8);
+        assert_eq!(&input.regs[..4], &[1, 2, 3, 0]);
+        assert_eq!(input.keyboard_at(0), [0; 16]);
+        assert!(input.rom.is_empty());
+    }
+
+    #[test]
+    fn cycles_keypad_states() {
+        let mut data = vec![0; HEADER_LEN];
+        data[22] = 2;
+        data.extend_from_slice(&[0x00, 0x01, 0x80, 0x00, 0x12, 0x00]);
+        let input = Input::parse(&data);
+        assert_eq!(input.keyboard_at(0)[0], 1);
+        assert_eq!(input.keyboard_at(1)[15], 1);
+        assert_eq!(input.keyboard_at(2)[0], 1);
+        assert_eq!(input.rom, &[0x12, 0x00]);
+    }
+
+    /// Runs the committed corpus, which includes inputs of fixed failures.
+    #[test]
+    fn corpus_passes() {
+        for target in &[Target::Vm, Target::Diff] {
+            let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("corpus").join(target.name());
+            for entry in fs::read_dir(dir).unwrap() {
+                let data = fs::read(entry.unwrap().path()).unwrap();
+                target.run(&data);
+            }
+        }
+    }
+}
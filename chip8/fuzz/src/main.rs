@@ -0,0 +1,260 @@
+//! Fuzzes `chip8::Vm` without external tools, see the crate documentation for the targets.
+//!
+//! Usage: chip8-fuzz TARGET [--runs N] [--seed N] [--max-len N] [INPUT...]
+//!
+//! Works like `cargo fuzz run`: inputs from `corpus/TARGET` are run first, then ones made by
+//! mutating them. Inputs covering new instruction outcomes are added to the corpus. The first
+//! failing input is written to `artifacts/TARGET` and stops the run with exit code 1.
+//!
+//! Given INPUT files or directories, only runs those, e.g. to reproduce a failure.
+
+extern crate chip8;
+extern crate chip8_fuzz;
+extern crate rand;
+
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use chip8::instruction::{Instruction, InstructionWord};
+use chip8_fuzz::{Coverage, Input, Target};
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+const USAGE: &str = "Usage: chip8-fuzz TARGET [--runs N] [--seed N] [--max-len N] [INPUT...]
+
+Targets:
+    vm      Fails if the VM panics
+    diff    Fails if the VM differs from the reference interpreter";
+
+struct Options {
+    target: Target,
+    runs: Option<u64>,
+    seed: u32,
+    max_len: usize,
+    inputs: Vec<PathBuf>,
+}
+
+fn parse_options() -> Result<Options, String> {
+    let mut args = env::args().skip(1);
+    let target = args.next().ok_or_else(|| "missing TARGET".to_string())?;
+    let target = Target::from_name(&target).ok_or_else(|| format!("unknown target '{}'", target))?;
+    let mut options = Options {
+        target,
+        runs: None,
+        seed: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.subsec_nanos())
+            .unwrap_or(0),
+        max_len: 4096,
+        inputs: Vec::new(),
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .and_then(|value| value.parse::<u64>().ok())
+                .ok_or_else(|| format!("{} expects a number", name))
+        };
+        match arg.as_str() {
+            "--runs" => options.runs = Some(value("--runs")?),
+            "--seed" => options.seed = value("--seed")? as u32,
+            "--max-len" => options.max_len = value("--max-len")? as usize,
+            _ if arg.starts_with('-') => return Err(format!("unknown option '{}'", arg)),
+            _ => options.inputs.push(PathBuf::from(arg)),
+        }
+    }
+    Ok(options)
+}
+
+/// Returns contents of `path`, or of all files in it if it's a directory.
+fn read_inputs(path: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+    let mut paths = match fs::read_dir(path) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect(),
+        Err(_) => vec![path.to_path_buf()],
+    };
+    paths.sort();
+    paths
+        .into_iter()
+        .filter_map(|path| fs::read(&path).ok().map(|data| (path, data)))
+        .collect()
+}
+
+fn file_name(data: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// Runs `target`, returning the panic message if it fails.
+fn run(target: Target, data: &[u8]) -> Result<Coverage, String> {
+    panic::catch_unwind(AssertUnwindSafe(|| target.run(data))).map_err(|payload| {
+        if let Some(message) = payload.downcast_ref::<String>() {
+            message.clone()
+        } else if let Some(message) = payload.downcast_ref::<&str>() {
+            message.to_string()
+        } else {
+            "panicked".to_string()
+        }
+    })
+}
+
+fn fail(options: &Options, data: &[u8], message: &str) -> ! {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("artifacts").join(options.target.name());
+    let path = dir.join(format!("crash-{}", file_name(data)));
+    if let Err(e) = fs::create_dir_all(&dir).and_then(|_| fs::write(&path, data)) {
+        eprintln!("Can't write {}: {}", path.display(), e);
+    }
+    println!("{}", message);
+    println!();
+    println!("Failing input written to {}", path.display());
+    println!("Reproduce with: chip8-fuzz {} {}", options.target.name(), path.display());
+    process::exit(1);
+}
+
+/// Returns a random instruction word the VM recognizes.
+fn random_instruction(rng: &mut XorShiftRng) -> u16 {
+    loop {
+        let word = rng.gen::<u16>();
+        if Instruction::decode(InstructionWord(word)).is_ok() {
+            return word;
+        }
+    }
+}
+
+fn mutate(rng: &mut XorShiftRng, data: &mut Vec<u8>, corpus: &[Vec<u8>], max_len: usize) {
+    for _ in 0..rng.gen_range(1, 5) {
+        let len = data.len();
+        let rom_offset = Input::rom_offset(data).min(len);
+        match rng.gen_range(0, 8) {
+            0 if len > 0 => {
+                let index = rng.gen_range(0, len);
+                data[index] ^= 1 << rng.gen_range(0, 8);
+            }
+            1 if len > 0 => {
+                let index = rng.gen_range(0, len);
+                data[index] = rng.gen();
+            }
+            2 => {
+                let index = rng.gen_range(0, len + 1);
+                data.insert(index, rng.gen());
+            }
+            3 if len > 0 => {
+                let index = rng.gen_range(0, len);
+                data.remove(index);
+            }
+            // Instructions are aligned to the ROM start unless something jumps to an odd address.
+            4 if len >= rom_offset + 2 => {
+                let index = rom_offset + rng.gen_range(0, (len - rom_offset) / 2) * 2;
+                let word = random_instruction(rng);
+                data[index] = (word >> 8) as u8;
+                data[index + 1] = word as u8;
+            }
+            5 => {
+                let index = rom_offset + rng.gen_range(0, (len - rom_offset) / 2 + 1) * 2;
+                let word = random_instruction(rng);
+                data.splice(index..index, vec![(word >> 8) as u8, word as u8]);
+            }
+            6 if !corpus.is_empty() => {
+                let other = &corpus[rng.gen_range(0, corpus.len())];
+                let index = rng.gen_range(0, len + 1);
+                let other_index = rng.gen_range(0, other.len() + 1);
+                data.truncate(index);
+                data.extend_from_slice(&other[other_index..]);
+            }
+            7 if len > 0 => {
+                let start = rng.gen_range(0, len);
+                let end = rng.gen_range(start, len) + 1;
+                let chunk = data[start..end].to_vec();
+                let index = rng.gen_range(0, len + 1);
+                data.splice(index..index, chunk);
+            }
+            _ => {}
+        }
+    }
+    data.truncate(max_len);
+}
+
+fn main() {
+    let options = parse_options().unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, USAGE);
+        process::exit(2);
+    });
+    let target = options.target;
+
+    // Failures are reported with the failing input, not by the default panic hook.
+    panic::set_hook(Box::new(|_| {}));
+
+    if !options.inputs.is_empty() {
+        for path in &options.inputs {
+            for (path, data) in read_inputs(path) {
+                if let Err(message) = run(target, &data) {
+                    println!("{}: {}", path.display(), message);
+                    process::exit(1);
+                }
+                println!("{}: ok", path.display());
+            }
+        }
+        return;
+    }
+
+    let corpus_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("corpus").join(target.name());
+    let mut corpus = Vec::new();
+    let mut coverage = Coverage::new();
+    for (_, data) in read_inputs(&corpus_dir) {
+        match run(target, &data) {
+            Ok(features) => coverage.extend(features),
+            Err(message) => fail(&options, &data, &message),
+        }
+        corpus.push(data);
+    }
+    if corpus.is_empty() {
+        corpus.push(Vec::new());
+    }
+    println!(
+        "Loaded {} inputs from {}, {} features, seed {}",
+        corpus.len(),
+        corpus_dir.display(),
+        coverage.len(),
+        options.seed
+    );
+
+    let mut rng = XorShiftRng::from_seed([options.seed, 0x9E37_79B9, 0x85EB_CA6B, 0xC2B2_AE35]);
+    let start = Instant::now();
+    let mut runs = 0;
+    while options.runs.is_none_or(|max_runs| runs < max_runs) {
+        let mut data = corpus[rng.gen_range(0, corpus.len())].clone();
+        mutate(&mut rng, &mut data, &corpus, options.max_len);
+
+        let features = match run(target, &data) {
+            Ok(features) => features,
+            Err(message) => fail(&options, &data, &message),
+        };
+        runs += 1;
+
+        if !features.is_subset(&coverage) {
+            coverage.extend(features);
+            if let Err(e) = fs::create_dir_all(&corpus_dir)
+                .and_then(|_| fs::write(corpus_dir.join(file_name(&data)), &data))
+            {
+                eprintln!("Can't add to the corpus: {}", e);
+            }
+            corpus.push(data);
+        }
+        if runs.is_power_of_two() || runs % 100_000 == 0 {
+            let seconds = start.elapsed().as_secs().max(1);
+            println!(
+                "#{}\tcorpus: {}\tfeatures: {}\texec/s: {}",
+                runs,
+                corpus.len(),
+                coverage.len(),
+                runs / seconds
+            );
+        }
+    }
+    println!("Done {} runs without failures", runs);
+}
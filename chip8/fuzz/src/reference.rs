@@ -0,0 +1,353 @@
+//! Reference interpreter the VM is compared with by the `diff` target.
+//!
+//! It's written from the instruction descriptions rather than from `chip8::Vm`, favouring
+//! obviousness over speed: opcodes are matched nibble by nibble and every instruction is
+//! executed in place, so a mistake in one instruction can't hide in shared helpers.
+//! Only the display and the random source are shared with the VM.
+
+use chip8::Error;
+use chip8::Quirks;
+use chip8::Vm;
+use chip8::display::Display;
+use chip8::instruction::{InstructionWord, Reg};
+use chip8::random::Random;
+
+const FONT_ADDR: u16 = 0;
+const BIG_FONT_ADDR: u16 = 80;
+const STACK_DEPTH: usize = 16;
+
+pub struct Reference {
+    pub memory: Vec<u8>,
+    pub v: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub stack: Vec<u16>,
+    pub dt: u8,
+    pub st: u8,
+    pub flags: [u8; 16],
+    /// Register `Fx0A` stores the key into and the key pressed so far, if any.
+    pub wait_key: Option<(usize, Option<u8>)>,
+    pub vblank: bool,
+    pub halted: bool,
+    pub planes: u8,
+    pub audio_pattern: Option<[u8; 16]>,
+    pub cycles: u64,
+    pub quirks: Quirks,
+    pub random: Box<dyn Random>,
+}
+
+impl Reference {
+    /// Creates an interpreter in the same state as a freshly loaded `vm`.
+    pub fn new(vm: &Vm) -> Reference {
+        let mut v = [0; 16];
+        for (index, value) in v.iter_mut().enumerate() {
+            *value = vm.reg(Reg::from_index(index as u8));
+        }
+        Reference {
+            memory: vm.memory.clone(),
+            v,
+            i: vm.i(),
+            pc: vm.pc(),
+            stack: vm.stack().to_vec(),
+            dt: vm.delay_timer(),
+            st: vm.sound_timer(),
+            flags: [0; 16],
+            wait_key: None,
+            vblank: false,
+            halted: false,
+            planes: 1,
+            audio_pattern: None,
+            cycles: 0,
+            quirks: vm.quirks(),
+            random: vm.random().box_clone(),
+        }
+    }
+
+    /// Counts down the timers, same as `Vm::update_timers(1)`.
+    pub fn tick(&mut self) {
+        self.dt = self.dt.saturating_sub(1);
+        self.st = self.st.saturating_sub(1);
+        self.random.tick();
+        self.vblank = true;
+    }
+
+    /// Executes a single instruction, same as `Vm::cycle`.
+    pub fn step<D: Display>(&mut self, display: &mut D, keyboard: &[u8; 16]) -> chip8::Result<()> {
+        if self.halted {
+            return Ok(());
+        }
+
+        // `Fx0A` completes once a key is pressed and then released.
+        if let Some((x, pressed)) = self.wait_key {
+            match pressed {
+                None => {
+                    if let Some(key) = (0..16).find(|&key| keyboard[key] == 1) {
+                        self.wait_key = Some((x, Some(key as u8)));
+                    }
+                }
+                Some(key) => {
+                    if keyboard[key as usize] != 1 {
+                        self.v[x] = key;
+                        self.wait_key = None;
+                    }
+                }
+            }
+            return Ok(());
+        }
+
+        let pc = self.pc;
+        let word = match self.word_at(pc) {
+            Some(word) => word,
+            None => return Err(Error::PcOutOfBounds(pc)),
+        };
+        let op = word >> 12;
+        let x = (word >> 8 & 0xF) as usize;
+        let y = (word >> 4 & 0xF) as usize;
+        let n = word & 0xF;
+        let nnn = word & 0xFFF;
+        let kk = word as u8;
+
+        let mut next = pc.wrapping_add(2);
+        match (op, x, y, n) {
+            (0x0, 0x0, 0xE, 0x0) => display.clear(),
+            (0x0, 0x0, 0xE, 0xE) => {
+                next = self.stack.pop().ok_or(Error::StackUnderflow { pc })?;
+            }
+            (0x0, 0x0, 0xC, n) => display.scroll_down(n as usize),
+            (0x0, 0x0, 0xD, n) => display.scroll_up(n as usize),
+            (0x0, 0x0, 0xF, 0xB) => display.scroll_right(),
+            (0x0, 0x0, 0xF, 0xC) => display.scroll_left(),
+            (0x0, 0x0, 0xF, 0xD) => {
+                self.halted = true;
+                next = pc;
+            }
+            (0x0, 0x0, 0xF, 0xE) => display.set_high_res(false),
+            (0x0, 0x0, 0xF, 0xF) => display.set_high_res(true),
+            // Machine code routines aren't emulated.
+            (0x0, _, _, _) => {}
+            (0x1, _, _, _) => next = nnn,
+            (0x2, _, _, _) => {
+                if self.stack.len() == STACK_DEPTH {
+                    return Err(Error::StackOverflow { pc });
+                }
+                self.stack.push(next);
+                next = nnn;
+            }
+            (0x3, _, _, _) => {
+                if self.v[x] == kk {
+                    next = self.skip(next);
+                }
+            }
+            (0x4, _, _, _) => {
+                if self.v[x] != kk {
+                    next = self.skip(next);
+                }
+            }
+            (0x5, _, _, 0x0) => {
+                if self.v[x] == self.v[y] {
+                    next = self.skip(next);
+                }
+            }
+            (0x5, _, _, 0x2) => {
+                let regs = register_range(x, y);
+                self.check_memory(pc, self.i as usize, regs.len())?;
+                for (offset, &reg) in regs.iter().enumerate() {
+                    self.memory[self.i as usize + offset] = self.v[reg];
+                }
+            }
+            (0x5, _, _, 0x3) => {
+                let regs = register_range(x, y);
+                self.check_memory(pc, self.i as usize, regs.len())?;
+                for (offset, &reg) in regs.iter().enumerate() {
+                    self.v[reg] = self.memory[self.i as usize + offset];
+                }
+            }
+            (0x6, _, _, _) => self.v[x] = kk,
+            (0x7, _, _, _) => self.v[x] = self.v[x].wrapping_add(kk),
+            (0x8, _, _, 0x0) => self.v[x] = self.v[y],
+            (0x8, _, _, 0x1) => {
+                self.v[x] |= self.v[y];
+                if self.quirks.logic_resets_vf {
+                    self.v[0xF] = 0;
+                }
+            }
+            (0x8, _, _, 0x2) => {
+                self.v[x] &= self.v[y];
+                if self.quirks.logic_resets_vf {
+                    self.v[0xF] = 0;
+                }
+            }
+            (0x8, _, _, 0x3) => {
+                self.v[x] ^= self.v[y];
+                if self.quirks.logic_resets_vf {
+                    self.v[0xF] = 0;
+                }
+            }
+            // Flags are computed from the operands before the result is stored, and stored
+            // last, so `VF` holds the flag when it's also the destination.
+            (0x8, _, _, 0x4) => {
+                let sum = self.v[x] as u16 + self.v[y] as u16;
+                self.v[x] = sum as u8;
+                self.v[0xF] = (sum > 0xFF) as u8;
+            }
+            (0x8, _, _, 0x5) => {
+                let no_borrow = self.v[x] >= self.v[y];
+                self.v[x] = self.v[x].wrapping_sub(self.v[y]);
+                self.v[0xF] = no_borrow as u8;
+            }
+            (0x8, _, _, 0x6) => {
+                let source = if self.quirks.shift_uses_vy { self.v[y] } else { self.v[x] };
+                self.v[x] = source >> 1;
+                self.v[0xF] = source & 1;
+            }
+            (0x8, _, _, 0x7) => {
+                let no_borrow = self.v[y] >= self.v[x];
+                self.v[x] = self.v[y].wrapping_sub(self.v[x]);
+                self.v[0xF] = no_borrow as u8;
+            }
+            (0x8, _, _, 0xE) => {
+                let source = if self.quirks.shift_uses_vy { self.v[y] } else { self.v[x] };
+                self.v[x] = source << 1;
+                self.v[0xF] = source >> 7;
+            }
+            (0x9, _, _, 0x0) => {
+                if self.v[x] != self.v[y] {
+                    next = self.skip(next);
+                }
+            }
+            (0xA, _, _, _) => self.i = nnn,
+            (0xB, _, _, _) => {
+                let offset = if self.quirks.jump_uses_vx { self.v[x] } else { self.v[0] };
+                next = nnn + offset as u16;
+            }
+            (0xC, _, _, _) => self.v[x] = self.random.next_byte(&self.memory) & kk,
+            (0xD, _, _, _) if self.quirks.display_wait && !self.vblank => {
+                // Nothing is executed or counted until the next timer tick.
+                return Ok(());
+            }
+            (0xD, _, _, n) => {
+                self.vblank = false;
+                let planes = self.planes.count_ones() as usize;
+                let (bytes, wide) = if n == 0 { (32 * planes, true) } else { (n as usize * planes, false) };
+                let start = self.i as usize;
+                self.check_memory(pc, start, bytes)?;
+                let sprite = &self.memory[start..start + bytes];
+                let (sx, sy) = (self.v[x] as usize, self.v[y] as usize);
+                let clip = self.quirks.clip_sprites;
+                let collision = if wide {
+                    display.draw_wide(sx, sy, sprite, clip)
+                } else {
+                    display.draw(sx, sy, sprite, clip)
+                };
+                self.v[0xF] = collision as u8;
+            }
+            // Only the low nibble of `Vx` selects a key.
+            (0xE, _, 0x9, 0xE) => {
+                if keyboard[(self.v[x] & 0xF) as usize] == 1 {
+                    next = self.skip(next);
+                }
+            }
+            (0xE, _, 0xA, 0x1) => {
+                if keyboard[(self.v[x] & 0xF) as usize] != 1 {
+                    next = self.skip(next);
+                }
+            }
+            (0xF, 0x0, 0x0, 0x0) => {
+                let operand = pc.wrapping_add(2);
+                self.i = self.word_at(operand).ok_or(Error::MemoryOutOfBounds {
+                    pc,
+                    addr: operand as usize,
+                    width: 2,
+                })?;
+                next = pc.wrapping_add(4);
+            }
+            (0xF, _, 0x0, 0x1) => {
+                self.planes = x as u8 & 0x3;
+                display.select_planes(self.planes);
+            }
+            (0xF, 0x0, 0x0, 0x2) => {
+                let start = self.i as usize;
+                self.check_memory(pc, start, 16)?;
+                let mut pattern = [0; 16];
+                pattern.copy_from_slice(&self.memory[start..start + 16]);
+                self.audio_pattern = Some(pattern);
+            }
+            (0xF, _, 0x0, 0x7) => self.v[x] = self.dt,
+            (0xF, _, 0x0, 0xA) => self.wait_key = Some((x, None)),
+            (0xF, _, 0x1, 0x5) => self.dt = self.v[x],
+            (0xF, _, 0x1, 0x8) => self.st = self.v[x],
+            (0xF, _, 0x1, 0xE) => self.i = self.i.wrapping_add(self.v[x] as u16),
+            // Only the low nibble of `Vx` selects a digit.
+            (0xF, _, 0x2, 0x9) => self.i = FONT_ADDR + (self.v[x] & 0xF) as u16 * 5,
+            (0xF, _, 0x3, 0x0) => self.i = BIG_FONT_ADDR + (self.v[x] & 0xF) as u16 * 10,
+            (0xF, _, 0x3, 0x3) => {
+                let start = self.i as usize;
+                self.check_memory(pc, start, 3)?;
+                let value = self.v[x];
+                self.memory[start] = value / 100;
+                self.memory[start + 1] = value / 10 % 10;
+                self.memory[start + 2] = value % 10;
+            }
+            // Pitch only affects sound, which isn't compared.
+            (0xF, _, 0x3, 0xA) => {}
+            (0xF, _, 0x5, 0x5) => {
+                let start = self.i as usize;
+                self.check_memory(pc, start, x + 1)?;
+                self.memory[start..start + x + 1].copy_from_slice(&self.v[..x + 1]);
+                if self.quirks.load_store_increments_i {
+                    self.i = self.i.wrapping_add(x as u16 + 1);
+                }
+            }
+            (0xF, _, 0x6, 0x5) => {
+                let start = self.i as usize;
+                self.check_memory(pc, start, x + 1)?;
+                self.v[..x + 1].copy_from_slice(&self.memory[start..start + x + 1]);
+                if self.quirks.load_store_increments_i {
+                    self.i = self.i.wrapping_add(x as u16 + 1);
+                }
+            }
+            (0xF, _, 0x7, 0x5) => self.flags[..x + 1].copy_from_slice(&self.v[..x + 1]),
+            (0xF, _, 0x8, 0x5) => self.v[..x + 1].copy_from_slice(&self.flags[..x + 1]),
+            _ => return Err(Error::UnrecognizedInstruction(InstructionWord(word))),
+        }
+
+        self.cycles += 1;
+        self.pc = next;
+        Ok(())
+    }
+
+    fn word_at(&self, addr: u16) -> Option<u16> {
+        let addr = addr as usize;
+        if addr + 1 < self.memory.len() {
+            Some((self.memory[addr] as u16) << 8 | self.memory[addr + 1] as u16)
+        } else {
+            None
+        }
+    }
+
+    /// Returns address of the instruction after the one at `addr`, which is
+    /// 4 bytes long if it's `F000 nnnn`.
+    fn skip(&self, addr: u16) -> u16 {
+        if self.word_at(addr) == Some(0xF000) {
+            addr.wrapping_add(4)
+        } else {
+            addr.wrapping_add(2)
+        }
+    }
+
+    fn check_memory(&self, pc: u16, addr: usize, width: usize) -> chip8::Result<()> {
+        if addr + width > self.memory.len() {
+            return Err(Error::MemoryOutOfBounds { pc, addr, width });
+        }
+        Ok(())
+    }
+}
+
+/// Returns registers from `x` to `y` inclusive, in descending order if `x > y`.
+fn register_range(x: usize, y: usize) -> Vec<usize> {
+    if x <= y {
+        (x..y + 1).collect()
+    } else {
+        (y..x + 1).rev().collect()
+    }
+}
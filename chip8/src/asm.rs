@@ -0,0 +1,785 @@
+//! Assembler producing ROMs from CHIP-8 assembly.
+//!
+//! The syntax follows the mnemonics printed by the disassembler, one statement per line:
+//!
+//! ```text
+//! ; Comments start with a semicolon.
+//! include "sprites.asm"   ; Inserts another file, relative to the including one.
+//! SPEED EQU 2             ; Defines a constant.
+//!
+//! start:                  ; Defines a label at the current address.
+//!     LD I, ball
+//!     LD V0, SPEED + 1
+//!     DRW V0, V1, 1
+//!     JP start
+//! ball:
+//!     DB 0x80             ; Emits bytes.
+//!     DW 0x1234, start    ; Emits big-endian words.
+//! ```
+//!
+//! Mnemonics and register names are case-insensitive, labels and constants are not.
+//! Numbers can be written in decimal, hexadecimal (`0x1F` or `#1F`) or binary (`0b101`),
+//! and combined with `+` and `-`. Labels can be referenced before they are defined,
+//! constants have to be defined before they are used.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use instruction::{Instruction, Addr, Imm, Imm4, Reg, Fun};
+
+/// Address at which ROMs are loaded.
+const ROM_START: i64 = 0x200;
+
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Assembly error, pointing to the 1-based line and column of the offending statement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.file, self.line, self.column, self.message)
+    }
+}
+
+/// Assembles `source`. Included files are resolved relative to the current directory.
+pub fn assemble(source: &str) -> Result<Vec<u8>, Error> {
+    let mut lines = Vec::new();
+    load_lines(&mut lines, source, "<input>", Path::new(""), 0)?;
+    Assembler::new(lines).assemble()
+}
+
+/// Assembles a source file. Included files are resolved relative to the including file.
+pub fn assemble_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, Error> {
+    let path = path.as_ref();
+    let file_name = path.display().to_string();
+    let source = read_source(path).map_err(|message| {
+        Error {
+            file: file_name.clone(),
+            line: 0,
+            column: 0,
+            message,
+        }
+    })?;
+
+    let mut lines = Vec::new();
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    load_lines(&mut lines, &source, &file_name, dir, 0)?;
+    Assembler::new(lines).assemble()
+}
+
+fn read_source(path: &Path) -> Result<String, String> {
+    let mut source = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut source))
+        .map_err(|e| format!("can't read {}: {}", path.display(), e))?;
+    Ok(source)
+}
+
+struct SourceLine {
+    file: String,
+    line: usize,
+    text: String,
+}
+
+/// Collects lines of `source`, replacing `include` statements with lines of included files.
+fn load_lines(
+    lines: &mut Vec<SourceLine>,
+    source: &str,
+    file_name: &str,
+    dir: &Path,
+    depth: usize,
+) -> Result<(), Error> {
+    for (index, text) in source.lines().enumerate() {
+        let error = |column, message: String| {
+            Error {
+                file: file_name.to_string(),
+                line: index + 1,
+                column,
+                message,
+            }
+        };
+
+        let code = strip_comment(text);
+        let trimmed = code.trim_start();
+        let is_include = trimmed
+            .split_whitespace()
+            .next()
+            .is_some_and(|word| word.eq_ignore_ascii_case("include"));
+        if !is_include {
+            lines.push(SourceLine {
+                file: file_name.to_string(),
+                line: index + 1,
+                text: code.to_string(),
+            });
+            continue;
+        }
+
+        let column = code.len() - trimmed.len() + 1;
+        if depth >= MAX_INCLUDE_DEPTH {
+            return Err(error(column, "includes are nested too deeply".to_string()));
+        }
+        let argument = trimmed["include".len()..].trim();
+        if argument.len() < 2 || !argument.starts_with('"') || !argument.ends_with('"') {
+            return Err(error(column, "expected quoted file name".to_string()));
+        }
+        let path = dir.join(&argument[1..argument.len() - 1]);
+        let included = read_source(&path).map_err(|message| error(column, message))?;
+        let included_dir = path.parent().unwrap_or_else(|| Path::new(""));
+        load_lines(
+            lines,
+            &included,
+            &path.display().to_string(),
+            included_dir,
+            depth + 1,
+        )?;
+    }
+    Ok(())
+}
+
+fn strip_comment(text: &str) -> &str {
+    match text.find(';') {
+        Some(index) => &text[..index],
+        None => text,
+    }
+}
+
+/// Piece of a statement together with its 1-based column.
+#[derive(Clone)]
+struct Token {
+    text: String,
+    column: usize,
+}
+
+struct Statement {
+    label: Option<Token>,
+    /// Constant defined by `NAME EQU value`, the value is the only operand.
+    constant: Option<Token>,
+    mnemonic: Option<Token>,
+    operands: Vec<Token>,
+}
+
+/// Splits a line (with the comment already stripped) into a statement.
+fn parse_statement(text: &str) -> Statement {
+    let mut rest = text;
+    let mut offset = 0;
+
+    // Takes the next whitespace delimited word.
+    let next_word = |rest: &mut &str, offset: &mut usize| -> Option<Token> {
+        let trimmed = rest.trim_start();
+        *offset += rest.len() - trimmed.len();
+        if trimmed.is_empty() {
+            *rest = trimmed;
+            return None;
+        }
+        let len = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+        let token = Token {
+            text: trimmed[..len].to_string(),
+            column: *offset + 1,
+        };
+        *rest = &trimmed[len..];
+        *offset += len;
+        Some(token)
+    };
+
+    let mut statement = Statement {
+        label: None,
+        constant: None,
+        mnemonic: None,
+        operands: Vec::new(),
+    };
+
+    let mut word = next_word(&mut rest, &mut offset);
+    if let Some(token) = word.clone() {
+        if token.text.ends_with(':') {
+            statement.label = Some(Token {
+                text: token.text[..token.text.len() - 1].to_string(),
+                column: token.column,
+            });
+            word = next_word(&mut rest, &mut offset);
+        }
+    }
+
+    let is_constant = {
+        let lookahead = rest.split_whitespace().next();
+        lookahead.is_some_and(|w| w.eq_ignore_ascii_case("EQU"))
+    };
+    if is_constant {
+        statement.constant = word.take();
+        word = next_word(&mut rest, &mut offset);
+    }
+    statement.mnemonic = word;
+
+    if !rest.trim().is_empty() {
+        for operand in rest.split(',') {
+            let trimmed = operand.trim_start();
+            statement.operands.push(Token {
+                text: trimmed.trim_end().to_string(),
+                column: offset + operand.len() - trimmed.len() + 1,
+            });
+            offset += operand.len() + 1;
+        }
+    }
+
+    statement
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_reg(text: &str) -> Option<Reg> {
+    let bytes = text.as_bytes();
+    if bytes.len() != 2 || (bytes[0] != b'V' && bytes[0] != b'v') {
+        return None;
+    }
+    (bytes[1] as char).to_digit(16).map(|index| Reg::from_index(index as u8))
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let lower = text.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(hex) = lower.strip_prefix('#') {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+struct Assembler {
+    lines: Vec<SourceLine>,
+    symbols: HashMap<String, i64>,
+    output: Vec<u8>,
+}
+
+impl Assembler {
+    fn new(lines: Vec<SourceLine>) -> Assembler {
+        Assembler {
+            lines,
+            symbols: HashMap::new(),
+            output: Vec::new(),
+        }
+    }
+
+    fn assemble(mut self) -> Result<Vec<u8>, Error> {
+        let lines = ::std::mem::take(&mut self.lines);
+        let statements: Vec<_> = lines
+            .iter()
+            .map(|line| parse_statement(&line.text))
+            .collect();
+
+        // The first pass assigns addresses to labels.
+        let mut addr = ROM_START;
+        for (line, statement) in lines.iter().zip(&statements) {
+            if let Some(ref label) = statement.label {
+                self.define(line, label, addr)?;
+            }
+            if let Some(ref name) = statement.constant {
+                let value = match statement.operands.first() {
+                    Some(value) if statement.operands.len() == 1 => self.eval(line, value)?,
+                    _ => return Err(error(line, name.column, "expected EQU value")),
+                };
+                self.define(line, name, value)?;
+                continue;
+            }
+            addr += statement_size(statement);
+        }
+
+        // The second pass emits the code.
+        for (line, statement) in lines.iter().zip(&statements) {
+            if statement.constant.is_none() {
+                if let Some(ref mnemonic) = statement.mnemonic {
+                    self.emit(line, mnemonic, &statement.operands)?;
+                }
+            }
+        }
+
+        Ok(self.output)
+    }
+
+    fn define(&mut self, line: &SourceLine, name: &Token, value: i64) -> Result<(), Error> {
+        if !is_identifier(&name.text) || parse_reg(&name.text).is_some() {
+            return Err(error(
+                line,
+                name.column,
+                &format!("invalid name '{}'", name.text),
+            ));
+        }
+        if self.symbols.insert(name.text.clone(), value).is_some() {
+            return Err(error(
+                line,
+                name.column,
+                &format!("'{}' is already defined", name.text),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Evaluates a sum of numbers and symbols.
+    fn eval(&self, line: &SourceLine, operand: &Token) -> Result<i64, Error> {
+        let mut total = 0i64;
+        let mut sign = 1i64;
+        let mut term_start = 0;
+        let text = &operand.text;
+
+        let mut terms = Vec::new();
+        for (index, c) in text.char_indices() {
+            if c == '+' || c == '-' {
+                terms.push((sign, term_start, &text[term_start..index]));
+                sign = if c == '+' { 1 } else { -1 };
+                term_start = index + 1;
+            }
+        }
+        terms.push((sign, term_start, &text[term_start..]));
+
+        for (sign, start, term) in terms {
+            let trimmed = term.trim();
+            let column = operand.column + start + (term.len() - term.trim_start().len());
+            if trimmed.is_empty() {
+                // Allow a leading sign, as in `-1`.
+                if start == 0 {
+                    continue;
+                }
+                return Err(error(line, column, "expected value"));
+            }
+            let value = match parse_number(trimmed) {
+                Some(value) => value,
+                None => {
+                    match self.symbols.get(trimmed) {
+                        Some(&value) => value,
+                        None if is_identifier(trimmed) => {
+                            return Err(error(
+                                line,
+                                column,
+                                &format!("undefined symbol '{}'", trimmed),
+                            ))
+                        }
+                        None => {
+                            return Err(error(
+                                line,
+                                column,
+                                &format!("invalid value '{}'", trimmed),
+                            ))
+                        }
+                    }
+                }
+            };
+            total = sign
+                .checked_mul(value)
+                .and_then(|value| total.checked_add(value))
+                .ok_or_else(|| error(line, column, "value out of range"))?;
+        }
+        Ok(total)
+    }
+
+    fn value_in_range(
+        &self,
+        line: &SourceLine,
+        operand: &Token,
+        min: i64,
+        max: i64,
+    ) -> Result<i64, Error> {
+        let value = self.eval(line, operand)?;
+        if value < min || value > max {
+            return Err(error(
+                line,
+                operand.column,
+                &format!("value {} is out of range [{}, {}]", value, min, max),
+            ));
+        }
+        Ok(value)
+    }
+
+    fn emit(&mut self, line: &SourceLine, mnemonic: &Token, operands: &[Token]) -> Result<(), Error> {
+        use instruction::Instruction::*;
+
+        let name = mnemonic.text.to_ascii_uppercase();
+        let ops = Operands {
+            asm: self,
+            line,
+            mnemonic,
+            operands,
+        };
+
+        let instruction = match name.as_str() {
+            "DB" => {
+                let bytes = operands
+                    .iter()
+                    .map(|op| ops.asm.value_in_range(line, op, -128, 0xFF).map(|v| v as u8))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.output.extend_from_slice(&bytes);
+                return Ok(());
+            }
+            "DW" => {
+                let words = operands
+                    .iter()
+                    .map(|op| ops.asm.value_in_range(line, op, -0x8000, 0xFFFF).map(|v| v as u16))
+                    .collect::<Result<Vec<_>, _>>()?;
+                for word in words {
+                    self.output.extend_from_slice(&[(word >> 8) as u8, word as u8]);
+                }
+                return Ok(());
+            }
+            "CLS" => ops.none(ClearScreen)?,
+            "RET" => ops.none(Ret)?,
+            "SCR" => ops.none(ScrollRight)?,
+            "SCL" => ops.none(ScrollLeft)?,
+            "EXIT" => ops.none(Exit)?,
+            "LOW" => ops.none(LowRes)?,
+            "HIGH" => ops.none(HighRes)?,
+            "AUDIO" => ops.none(LoadAudio)?,
+            "SYS" => Sys(ops.count(1).and_then(|_| ops.addr(0))?),
+            "SCD" => ScrollDown(ops.count(1).and_then(|_| ops.nibble(0))?),
+            "SCU" => ScrollUp(ops.count(1).and_then(|_| ops.nibble(0))?),
+            "PLANE" => SelectPlanes(ops.count(1).and_then(|_| ops.nibble(0))?),
+            "CALL" => Call(ops.count(1).and_then(|_| ops.addr(0))?),
+            "JP" => {
+                if operands.len() == 2 {
+                    if ops.reg(0)? != Reg::V0 {
+                        return Err(error(line, operands[0].column, "expected V0"));
+                    }
+                    JumpPlusV0(ops.addr(1)?)
+                } else {
+                    Jump(ops.count(1).and_then(|_| ops.addr(0))?)
+                }
+            }
+            "SE" | "SNE" => {
+                ops.count(2)?;
+                let vx = ops.reg(0)?;
+                let inv = name == "SNE";
+                match parse_reg(&operands[1].text) {
+                    Some(vy) => SkipEqReg { vx, vy, inv },
+                    None => SkipEqImm {
+                        vx,
+                        imm: ops.byte(1)?,
+                        inv,
+                    },
+                }
+            }
+            "SAVE" | "LOAD" => {
+                ops.count(1)?;
+                let range = &operands[0];
+                let (vx, vy) = match range.text.find('-') {
+                    Some(dash) => {
+                        let first = range.text[..dash].trim();
+                        let second = range.text[dash + 1..].trim();
+                        (parse_reg(first), parse_reg(second))
+                    }
+                    None => (None, None),
+                };
+                match (vx, vy) {
+                    (Some(vx), Some(vy)) if name == "SAVE" => StoreRange { vx, vy },
+                    (Some(vx), Some(vy)) => LoadRange { vx, vy },
+                    _ => return Err(error(line, range.column, "expected register range")),
+                }
+            }
+            "ADD" => {
+                ops.count(2)?;
+                if operands[0].text.eq_ignore_ascii_case("I") {
+                    AddI(ops.reg(1)?)
+                } else {
+                    let vx = ops.reg(0)?;
+                    match parse_reg(&operands[1].text) {
+                        Some(vy) => Apply {
+                            vx,
+                            vy,
+                            f: Fun::Add,
+                        },
+                        None => AddImm {
+                            vx,
+                            imm: ops.byte(1)?,
+                        },
+                    }
+                }
+            }
+            "OR" | "AND" | "XOR" | "SUB" | "SUBN" => {
+                ops.count(2)?;
+                let f = match name.as_str() {
+                    "OR" => Fun::Or,
+                    "AND" => Fun::And,
+                    "XOR" => Fun::Xor,
+                    "SUB" => Fun::Subtract,
+                    _ => Fun::SubtractInv,
+                };
+                Apply {
+                    vx: ops.reg(0)?,
+                    vy: ops.reg(1)?,
+                    f,
+                }
+            }
+            "SHR" | "SHL" => {
+                let f = if name == "SHR" {
+                    Fun::ShiftRight
+                } else {
+                    Fun::ShiftLeft
+                };
+                let vx = ops.reg(0)?;
+                let vy = if operands.len() == 2 {
+                    ops.reg(1)?
+                } else {
+                    ops.count(1)?;
+                    vx
+                };
+                Apply { vx, vy, f }
+            }
+            "RND" => {
+                ops.count(2)?;
+                Randomize {
+                    vx: ops.reg(0)?,
+                    imm: ops.byte(1)?,
+                }
+            }
+            "DRW" => {
+                ops.count(3)?;
+                Draw {
+                    vx: ops.reg(0)?,
+                    vy: ops.reg(1)?,
+                    n: ops.nibble(2)?,
+                }
+            }
+            "SKP" => SkipPressed {
+                vx: ops.count(1).and_then(|_| ops.reg(0))?,
+                inv: false,
+            },
+            "SKNP" => SkipPressed {
+                vx: ops.count(1).and_then(|_| ops.reg(0))?,
+                inv: true,
+            },
+            "PITCH" => SetPitch(ops.count(1).and_then(|_| ops.reg(0))?),
+            "LD" => {
+                ops.count(2)?;
+                let dst = operands[0].text.to_ascii_uppercase();
+                let src = operands[1].text.to_ascii_uppercase();
+                match (dst.as_str(), src.as_str()) {
+                    ("I", _) if src.starts_with("LONG ") => {
+                        let addr = Token {
+                            text: operands[1].text[5..].to_string(),
+                            column: operands[1].column + 5,
+                        };
+                        let value = ops.asm.value_in_range(line, &addr, 0, 0xFFFF)? as u16;
+                        self.output.extend_from_slice(
+                            &[0xF0, 0x00, (value >> 8) as u8, value as u8],
+                        );
+                        return Ok(());
+                    }
+                    ("I", _) => SetI(ops.addr(1)?),
+                    ("DT", _) => SetDT(ops.reg(1)?),
+                    ("ST", _) => SetST(ops.reg(1)?),
+                    ("F", _) => LoadGlyph(ops.reg(1)?),
+                    ("HF", _) => LoadBigGlyph(ops.reg(1)?),
+                    ("B", _) => StoreBCD(ops.reg(1)?),
+                    ("[I]", _) => StoreRegs(ops.reg(1)?),
+                    ("R", _) => StoreFlags(ops.reg(1)?),
+                    (_, "DT") => GetDT(ops.reg(0)?),
+                    (_, "K") => WaitKey(ops.reg(0)?),
+                    (_, "[I]") => LoadRegs(ops.reg(0)?),
+                    (_, "R") => LoadFlags(ops.reg(0)?),
+                    _ => {
+                        let vx = ops.reg(0)?;
+                        match parse_reg(&operands[1].text) {
+                            Some(vy) => Apply {
+                                vx,
+                                vy,
+                                f: Fun::Id,
+                            },
+                            None => PutImm {
+                                vx,
+                                imm: ops.byte(1)?,
+                            },
+                        }
+                    }
+                }
+            }
+            _ => {
+                return Err(error(
+                    line,
+                    mnemonic.column,
+                    &format!("unknown instruction '{}'", mnemonic.text),
+                ))
+            }
+        };
+
+        let word = instruction.encode().0;
+        self.output.extend_from_slice(&[(word >> 8) as u8, word as u8]);
+        Ok(())
+    }
+}
+
+/// Operands of a single instruction being assembled.
+struct Operands<'a> {
+    asm: &'a Assembler,
+    line: &'a SourceLine,
+    mnemonic: &'a Token,
+    operands: &'a [Token],
+}
+
+impl<'a> Operands<'a> {
+    fn count(&self, expected: usize) -> Result<(), Error> {
+        if self.operands.len() != expected {
+            return Err(error(
+                self.line,
+                self.mnemonic.column,
+                &format!(
+                    "{} expects {} operand(s), found {}",
+                    self.mnemonic.text,
+                    expected,
+                    self.operands.len()
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    fn none(&self, instruction: Instruction) -> Result<Instruction, Error> {
+        self.count(0)?;
+        Ok(instruction)
+    }
+
+    fn operand(&self, index: usize) -> Result<&'a Token, Error> {
+        self.operands.get(index).ok_or_else(|| {
+            error(self.line, self.mnemonic.column, "missing operand")
+        })
+    }
+
+    fn reg(&self, index: usize) -> Result<Reg, Error> {
+        let operand = self.operand(index)?;
+        parse_reg(&operand.text).ok_or_else(|| {
+            error(self.line, operand.column, "expected register")
+        })
+    }
+
+    fn addr(&self, index: usize) -> Result<Addr, Error> {
+        let operand = self.operand(index)?;
+        let value = self.asm.value_in_range(self.line, operand, 0, 0xFFF)?;
+        Addr::new(value as u16).ok_or_else(|| error(self.line, operand.column, "bad address"))
+    }
+
+    fn byte(&self, index: usize) -> Result<Imm, Error> {
+        let value = self.asm.value_in_range(self.line, self.operand(index)?, -128, 0xFF)?;
+        Ok(Imm(value as u8))
+    }
+
+    fn nibble(&self, index: usize) -> Result<Imm4, Error> {
+        let operand = self.operand(index)?;
+        let value = self.asm.value_in_range(self.line, operand, 0, 0xF)?;
+        Imm4::new(value as u8).ok_or_else(|| error(self.line, operand.column, "bad nibble"))
+    }
+}
+
+/// Returns how many bytes the statement occupies in the ROM.
+fn statement_size(statement: &Statement) -> i64 {
+    let mnemonic = match statement.mnemonic {
+        Some(ref mnemonic) => mnemonic.text.to_ascii_uppercase(),
+        None => return 0,
+    };
+    match mnemonic.as_str() {
+        "DB" => statement.operands.len() as i64,
+        "DW" => 2 * statement.operands.len() as i64,
+        "LD" if statement.operands.len() == 2 &&
+                    statement.operands[0].text.eq_ignore_ascii_case("I") &&
+                    statement.operands[1].text.to_ascii_uppercase().starts_with("LONG ") => 4,
+        _ => 2,
+    }
+}
+
+fn error(line: &SourceLine, column: usize, message: &str) -> Error {
+    Error {
+        file: line.file.clone(),
+        line: line.line,
+        column,
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assembles_instructions() {
+        let source = "
+            CLS
+            LD V3, 0x12
+            LD I, #2A4
+            DRW V0, V1, 5
+            SHR VA
+            ld vb, k
+            SAVE V1 - V2
+            LD I, LONG 0x1234
+        ";
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![
+                0x00, 0xE0, 0x63, 0x12, 0xA2, 0xA4, 0xD0, 0x15, 0x8A, 0xA6, 0xFB, 0x0A, 0x51,
+                0x22, 0xF0, 0x00, 0x12, 0x34,
+            ]
+        );
+    }
+
+    #[test]
+    fn resolves_forward_references() {
+        let source = "
+            TWO EQU 2
+            start: JP end       ; forward reference
+                   DB 1, TWO, end - start
+            end:   JP start
+        ";
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![0x12, 0x05, 0x01, 0x02, 0x05, 0x12, 0x00]
+        );
+    }
+
+    #[test]
+    fn reports_errors_with_position() {
+        let err = assemble("CLS\n  LD V0, missing\n").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 10));
+        assert_eq!(err.message, "undefined symbol 'missing'");
+
+        let err = assemble("  DRW V0, V1, 16").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 15));
+
+        let err = assemble("\n\n FOO V1").unwrap_err();
+        assert_eq!((err.line, err.column), (3, 2));
+
+        let err = assemble("LD V0, 9223372036854775807 + 1").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 30));
+        assert_eq!(err.message, "value out of range");
+
+        let err = assemble("MIN EQU -9223372036854775807 - 1\nLD V0, -MIN").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 9));
+    }
+
+    #[test]
+    fn round_trips_disassembly() {
+        use disasm::{disassemble, Item};
+
+        let rom = [0x6A, 0x02, 0xA2, 0x0A, 0xDA, 0xB6, 0x22, 0x08, 0x00, 0xEE];
+        let source = disassemble(&rom)
+            .items
+            .iter()
+            .filter_map(|item| match *item {
+                Item::Code { ref instruction, .. } => Some(instruction.to_string()),
+                Item::Data { .. } => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(assemble(&source).unwrap(), rom.to_vec());
+    }
+}
@@ -0,0 +1,40 @@
+//! Assembles CHIP-8 assembly source into a ROM.
+//!
+//! Usage: chip8-asm SOURCE_FILE [ROM_FILE]
+//!
+//! If ROM_FILE is omitted the ROM is written next to the source with the `.ch8` extension.
+
+extern crate chip8;
+
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process;
+
+fn main() {
+    let source_file_name = match env::args().nth(1) {
+        Some(source_file_name) => PathBuf::from(source_file_name),
+        None => {
+            eprintln!("Usage: chip8-asm SOURCE_FILE [ROM_FILE]");
+            process::exit(2);
+        }
+    };
+    let rom_file_name = env::args()
+        .nth(2)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| source_file_name.with_extension("ch8"));
+
+    let rom = match chip8::asm::assemble_file(&source_file_name) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    if let Err(e) = File::create(&rom_file_name).and_then(|mut f| f.write_all(&rom)) {
+        eprintln!("Can't write {}: {}", rom_file_name.display(), e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,28 @@
+//! Prints disassembly of a CHIP-8 ROM.
+//!
+//! Usage: chip8-disasm ROM_FILE
+
+extern crate chip8;
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::process;
+
+fn main() {
+    let rom_file_name = match env::args().nth(1) {
+        Some(rom_file_name) => rom_file_name,
+        None => {
+            eprintln!("Usage: chip8-disasm ROM_FILE");
+            process::exit(2);
+        }
+    };
+
+    let mut rom = Vec::new();
+    if let Err(e) = File::open(&rom_file_name).and_then(|mut f| f.read_to_end(&mut rom)) {
+        eprintln!("Can't read {}: {}", rom_file_name, e);
+        process::exit(1);
+    }
+
+    print!("{}", chip8::disasm::disassemble(&rom));
+}
@@ -0,0 +1,328 @@
+//! Runs a CHIP-8 ROM without a display and dumps the final machine state.
+//!
+//! Usage: chip8-run [OPTIONS] ROM_FILE
+//!
+//! Options:
+//!
+//! - `--frames N`: number of frames to run, 600 by default.
+//! - `--cycles N`: stop after N instructions, even if frames are left.
+//! - `--cycles-per-frame N`: instructions executed between timer ticks, 8 by default.
+//! - `--random xorshift|vip`: source of random numbers, see `chip8::random`. xorshift by
+//!   default.
+//! - `--seed N`: seed of the random source, 0 by default.
+//! - `--quirks default|vip|chip48|schip|xochip`: quirks of the emulated interpreter, see
+//!   `Quirks::from_name`.
+//! - `--input FILE`: keypad script, see `chip8::input`.
+//! - `--movie FILE`: plays a movie recorded by chipster, see `chip8::movie`. Quirks, random
+//!   source, seed, instructions per frame and input are taken from the movie, which runs to
+//!   its end by default.
+//! - `--screen FILE`: writes the screen as PNG if the name ends with `.png`,
+//!   otherwise as text with a character per pixel.
+//! - `--memory FILE`: writes the raw contents of memory.
+//! - `--trace FILE` and `--trace-format text|binary`: writes an execution trace.
+//! - `--strict-alignment`: faults on instructions fetched from odd addresses.
+//!
+//! Registers are printed to stdout at exit. Exits with 1 if the VM faults or the movie
+//! desyncs, the requested dumps are still written in that case.
+
+extern crate chip8;
+
+use std::env;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::process;
+
+use chip8::display::{Framebuffer, DEFAULT_PALETTE};
+use chip8::input::InputScript;
+use chip8::instruction::Reg;
+use chip8::movie::Movie;
+use chip8::random;
+use chip8::trace::{Format, TraceWriter};
+use chip8::{Env, Quirks, Vm};
+
+const USAGE: &str = "Usage: chip8-run [--frames N] [--cycles N] [--cycles-per-frame N] \
+                     [--random xorshift|vip] [--seed N] [--quirks default|vip|chip48|schip|xochip] \
+                     [--input FILE] [--movie FILE] [--screen FILE] [--memory FILE] \
+                     [--trace FILE] [--trace-format text|binary] [--strict-alignment] ROM_FILE";
+
+fn screen_png(framebuffer: &Framebuffer) -> Vec<u8> {
+    let rgb: Vec<u8> = framebuffer
+        .pixels()
+        .iter()
+        .flat_map(|&pixel| {
+            let (r, g, b) = DEFAULT_PALETTE[pixel as usize & 3];
+            vec![r, g, b]
+        })
+        .collect();
+    chip8::png::encode_rgb(framebuffer.width(), framebuffer.height(), &rgb)
+}
+
+struct Options {
+    rom_file_name: String,
+    frames: Option<u64>,
+    cycles: Option<u64>,
+    cycles_per_frame: u64,
+    random: String,
+    seed: u32,
+    quirks: Quirks,
+    input_file_name: Option<String>,
+    movie_file_name: Option<String>,
+    screen_file_name: Option<String>,
+    memory_file_name: Option<String>,
+    trace_file_name: Option<String>,
+    trace_format: Format,
+    strict_alignment: bool,
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn parse_number<T: std::str::FromStr>(option: &str, value: &str) -> T {
+    value
+        .parse()
+        .unwrap_or_else(|_| usage_error(&format!("Invalid value of {}: {}", option, value)))
+}
+
+fn parse_options() -> Options {
+    let mut options = Options {
+        rom_file_name: String::new(),
+        frames: None,
+        cycles: None,
+        cycles_per_frame: 8,
+        random: "xorshift".to_string(),
+        seed: 0,
+        quirks: Quirks::default(),
+        input_file_name: None,
+        movie_file_name: None,
+        screen_file_name: None,
+        memory_file_name: None,
+        trace_file_name: None,
+        trace_format: Format::Text,
+        strict_alignment: false,
+    };
+    let mut rom_file_name = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--help" || arg == "-h" {
+            println!("{}", USAGE);
+            process::exit(0);
+        }
+        if arg == "--strict-alignment" {
+            options.strict_alignment = true;
+            continue;
+        }
+        if !arg.starts_with("--") {
+            if rom_file_name.is_some() {
+                usage_error(&format!("Unexpected argument: {}", arg));
+            }
+            rom_file_name = Some(arg);
+            continue;
+        }
+
+        let value = args
+            .next()
+            .unwrap_or_else(|| usage_error(&format!("Missing value of {}", arg)));
+        match arg.as_str() {
+            "--frames" => options.frames = Some(parse_number(&arg, &value)),
+            "--cycles" => options.cycles = Some(parse_number(&arg, &value)),
+            "--cycles-per-frame" => options.cycles_per_frame = parse_number(&arg, &value),
+            "--random" => {
+                if !random::NAMES.contains(&value.as_str()) {
+                    usage_error(&format!("Unknown random source: {}", value));
+                }
+                options.random = value;
+            }
+            "--seed" => options.seed = parse_number(&arg, &value),
+            "--quirks" => {
+                options.quirks = match Quirks::from_name(&value) {
+                    Some(quirks) => quirks,
+                    None => usage_error(&format!("Unknown quirks: {}", value)),
+                }
+            }
+            "--input" => options.input_file_name = Some(value),
+            "--movie" => options.movie_file_name = Some(value),
+            "--screen" => options.screen_file_name = Some(value),
+            "--memory" => options.memory_file_name = Some(value),
+            "--trace" => options.trace_file_name = Some(value),
+            "--trace-format" => {
+                options.trace_format = match value.as_str() {
+                    "text" => Format::Text,
+                    "binary" => Format::Binary,
+                    _ => usage_error(&format!("Unknown trace format: {}", value)),
+                }
+            }
+            _ => usage_error(&format!("Unknown option: {}", arg)),
+        }
+    }
+
+    options.rom_file_name = rom_file_name.unwrap_or_else(|| usage_error("Missing ROM_FILE"));
+    if options.input_file_name.is_some() && options.movie_file_name.is_some() {
+        usage_error("--input and --movie can't be used together");
+    }
+    if options.frames.is_none() && options.cycles.is_none() && options.movie_file_name.is_none() {
+        options.frames = Some(600);
+    }
+    options
+}
+
+fn read_file(file_name: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    if let Err(e) = File::open(file_name).and_then(|mut f| f.read_to_end(&mut data)) {
+        eprintln!("Can't read {}: {}", file_name, e);
+        process::exit(2);
+    }
+    data
+}
+
+fn write_file(file_name: &str, data: &[u8]) {
+    if let Err(e) = File::create(file_name).and_then(|mut f| f.write_all(data)) {
+        eprintln!("Can't write {}: {}", file_name, e);
+        process::exit(2);
+    }
+}
+
+fn write_trace<W: Write>(trace: &mut TraceWriter<W>, vm: &mut Vm) {
+    for record in vm.take_trace() {
+        if let Err(e) = trace.write(&record) {
+            eprintln!("Can't write trace: {}", e);
+            process::exit(2);
+        }
+    }
+}
+
+fn print_state(vm: &Vm) {
+    print!("PC={:04X} I={:04X}", vm.pc(), vm.i());
+    for index in 0..16 {
+        print!(" V{:X}={:02X}", index, vm.reg(Reg::from_index(index)));
+    }
+    println!();
+    println!("DT={} ST={}", vm.delay_timer(), vm.sound_timer());
+    let stack: Vec<String> = vm.stack().iter().map(|addr| format!("{:04X}", addr)).collect();
+    println!("Stack=[{}]", stack.join(" "));
+    println!("Cycles={}", vm.cycles());
+}
+
+fn main() {
+    let options = parse_options();
+
+    let rom = read_file(&options.rom_file_name);
+    let input = match options.input_file_name {
+        Some(ref file_name) => {
+            let source = String::from_utf8_lossy(&read_file(file_name)).into_owned();
+            InputScript::parse(&source).unwrap_or_else(|e| {
+                eprintln!("{}: {}", file_name, e);
+                process::exit(2);
+            })
+        }
+        None => InputScript::default(),
+    };
+    let movie = options.movie_file_name.as_ref().map(|file_name| {
+        let source = String::from_utf8_lossy(&read_file(file_name)).into_owned();
+        let movie = Movie::parse(&source).unwrap_or_else(|e| {
+            eprintln!("{}: {}", file_name, e);
+            process::exit(2);
+        });
+        if !movie.matches_rom(&rom) {
+            eprintln!("{}: recorded with a different ROM", file_name);
+            process::exit(2);
+        }
+        movie
+    });
+    let mut trace = options.trace_file_name.as_ref().map(|file_name| {
+        File::create(file_name)
+            .and_then(|file| TraceWriter::new(BufWriter::new(file), options.trace_format))
+            .unwrap_or_else(|e| {
+                eprintln!("Can't write {}: {}", file_name, e);
+                process::exit(2);
+            })
+    });
+
+    let vm = match movie {
+        Some(ref movie) => movie.vm(&rom),
+        None => {
+            Vm::with_rom(&rom, options.quirks).map(|mut vm| {
+                let random = random::from_name(&options.random, options.seed);
+                vm.set_random(random.expect("checked by parse_options"));
+                vm
+            })
+        }
+    };
+    let mut vm = vm.unwrap_or_else(|e| {
+        eprintln!("Can't load {}: {}", options.rom_file_name, e);
+        process::exit(2);
+    });
+    vm.set_tracing(trace.is_some());
+    vm.set_strict_alignment(options.strict_alignment);
+    let frames = options.frames.or_else(|| movie.as_ref().map(|movie| movie.frames));
+    let cycles_per_frame = movie
+        .as_ref()
+        .map_or(options.cycles_per_frame, |movie| movie.cycles_per_frame);
+    let mut env = Env {
+        display: Framebuffer::new(),
+        keyboard: [0; 16],
+    };
+
+    let mut fault = None;
+    let mut desync = None;
+    let mut frame = 0;
+    'frames: while frames.is_none_or(|frames| frame < frames) {
+        env.keyboard = match movie {
+            Some(ref movie) => {
+                if !movie.is_in_sync(frame, &vm, &env.display.display_state()) {
+                    desync = Some(frame);
+                    break;
+                }
+                movie.keyboard_at(frame)
+            }
+            None => input.keyboard_at(frame),
+        };
+        for _ in 0..cycles_per_frame {
+            if options.cycles.is_some_and(|cycles| vm.cycles() >= cycles) || vm.is_halted() {
+                break 'frames;
+            }
+            if let Err(e) = vm.cycle(&mut env) {
+                fault = Some(e);
+                break 'frames;
+            }
+        }
+        vm.update_timers(1);
+        frame += 1;
+
+        if let Some(ref mut trace) = trace {
+            write_trace(trace, &mut vm);
+        }
+    }
+
+    if let Some(ref mut trace) = trace {
+        write_trace(trace, &mut vm);
+        if let Err(e) = trace.flush() {
+            eprintln!("Can't write trace: {}", e);
+            process::exit(2);
+        }
+    }
+    if let Some(ref file_name) = options.screen_file_name {
+        if file_name.ends_with(".png") {
+            write_file(file_name, &screen_png(&env.display));
+        } else {
+            write_file(file_name, env.display.to_text().as_bytes());
+        }
+    }
+    if let Some(ref file_name) = options.memory_file_name {
+        write_file(file_name, &vm.memory);
+    }
+
+    println!("Frames={}", frame);
+    print_state(&vm);
+    if let Some(e) = fault {
+        eprintln!("Error = {}", e);
+        process::exit(1);
+    }
+    if let Some(frame) = desync {
+        eprintln!("Movie desynced at frame {}", frame);
+        process::exit(1);
+    }
+}
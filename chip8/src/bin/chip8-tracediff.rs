@@ -0,0 +1,89 @@
+//! Finds the first divergence between two execution traces.
+//!
+//! Usage: chip8-tracediff TRACE_FILE TRACE_FILE
+//!
+//! Traces can be in either text or binary format. Exits with 0 if the traces are identical,
+//! with 1 if they diverge.
+
+extern crate chip8;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::process;
+
+use chip8::trace::{TraceReader, TraceRecord};
+
+fn open(file_name: &str) -> io::Result<TraceReader<BufReader<File>>> {
+    TraceReader::new(BufReader::new(File::open(file_name)?))
+}
+
+fn next(
+    reader: &mut TraceReader<BufReader<File>>,
+    file_name: &str,
+) -> Option<TraceRecord> {
+    match reader.next() {
+        Some(Ok(record)) => Some(record),
+        Some(Err(e)) => {
+            eprintln!("Can't read {}: {}", file_name, e);
+            process::exit(2);
+        }
+        None => None,
+    }
+}
+
+fn main() {
+    let file_names: Vec<String> = env::args().skip(1).collect();
+    if file_names.len() != 2 {
+        eprintln!("Usage: chip8-tracediff TRACE_FILE TRACE_FILE");
+        process::exit(2);
+    }
+
+    let mut readers = Vec::new();
+    for file_name in &file_names {
+        match open(file_name) {
+            Ok(reader) => readers.push(reader),
+            Err(e) => {
+                eprintln!("Can't read {}: {}", file_name, e);
+                process::exit(2);
+            }
+        }
+    }
+    let mut b = readers.pop().unwrap();
+    let mut a = readers.pop().unwrap();
+
+    let mut count = 0u64;
+    loop {
+        let records = (next(&mut a, &file_names[0]), next(&mut b, &file_names[1]));
+        match records {
+            (None, None) => {
+                println!("Traces are identical, {} records", count);
+                return;
+            }
+            (Some(record), None) | (None, Some(record)) => {
+                let (ended, other) = if records.0.is_none() {
+                    (&file_names[0], &file_names[1])
+                } else {
+                    (&file_names[1], &file_names[0])
+                };
+                println!("{} ends after {} records, {} continues:", ended, count, other);
+                println!("  {}", record);
+                process::exit(1);
+            }
+            (Some(record_a), Some(record_b)) => {
+                let fields = record_a.diff(&record_b);
+                if !fields.is_empty() {
+                    println!(
+                        "Traces diverge at record {}, differing {}:",
+                        count,
+                        fields.join(", ")
+                    );
+                    println!("  {}: {}", file_names[0], record_a);
+                    println!("  {}: {}", file_names[1], record_b);
+                    process::exit(1);
+                }
+            }
+        }
+        count += 1;
+    }
+}
@@ -0,0 +1,283 @@
+//! Debugger controlling execution of a `Vm`.
+//!
+//! The host calls `Debugger::cycle` instead of `Vm::cycle`. While the debugger is paused
+//! no instructions are executed, and the state of the machine can be inspected and modified
+//! through the accessors of `Vm`.
+
+use std::collections::BTreeSet;
+
+use display::Display;
+use instruction::{Instruction, InstructionWord};
+use vm::{Vm, Env, WatchHit};
+
+/// Reason why the debugger paused execution.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Stop {
+    /// Execution reached a breakpoint at the given address.
+    Breakpoint(u16),
+
+    /// Requested step has been completed.
+    Step,
+
+    /// An instruction accessed memory covered by a watchpoint. If there were several
+    /// such accesses during the cycle, this is the first one.
+    Watchpoint(WatchHit),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Mode {
+    Paused,
+    Running,
+    StepInto,
+    /// Runs until execution returns to `pc` with `depth` frames on the stack.
+    StepOver { pc: u16, depth: usize },
+    /// Runs until there are less than `depth` frames on the stack.
+    StepOut { depth: usize },
+}
+
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    mode: Mode,
+    /// Set when execution continues, so a breakpoint at the current PC doesn't stop it again.
+    leaving_breakpoint: bool,
+}
+
+impl Debugger {
+    /// Creates a debugger without breakpoints, which lets the VM run.
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            mode: Mode::Running,
+            leaving_breakpoint: false,
+        }
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<u16> {
+        &self.breakpoints
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    /// Returns `false` if there was no breakpoint at `addr`.
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn toggle_breakpoint(&mut self, addr: u16) {
+        if !self.remove_breakpoint(addr) {
+            self.add_breakpoint(addr);
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.mode == Mode::Paused
+    }
+
+    pub fn pause(&mut self) {
+        self.mode = Mode::Paused;
+    }
+
+    /// Continues execution until the next breakpoint.
+    pub fn resume(&mut self) {
+        self.continue_with(Mode::Running);
+    }
+
+    /// Executes a single instruction.
+    pub fn step_into(&mut self) {
+        self.continue_with(Mode::StepInto);
+    }
+
+    /// Executes a single instruction, running a subroutine to completion
+    /// if the instruction is `CALL`.
+    pub fn step_over(&mut self, vm: &Vm) {
+        let pc = vm.pc() as usize;
+        let word = vm.memory.get(pc..pc + 2).map(|bytes| {
+            InstructionWord((bytes[0] as u16) << 8 | bytes[1] as u16)
+        });
+        let mode = match word.map(Instruction::decode) {
+            Some(Ok(Instruction::Call(_))) => {
+                Mode::StepOver {
+                    pc: vm.pc().wrapping_add(2),
+                    depth: vm.stack().len(),
+                }
+            }
+            _ => Mode::StepInto,
+        };
+        self.continue_with(mode);
+    }
+
+    /// Runs until the current subroutine returns.
+    pub fn step_out(&mut self, vm: &Vm) {
+        self.continue_with(Mode::StepOut { depth: vm.stack().len() });
+    }
+
+    fn continue_with(&mut self, mode: Mode) {
+        self.mode = mode;
+        self.leaving_breakpoint = true;
+    }
+
+    /// Executes a cycle of the VM, unless the debugger is paused.
+    ///
+    /// Returns the reason if the debugger paused during this cycle. Breakpoints are checked
+    /// before the instruction at them executes, except for the first instruction after
+    /// execution continues. The debugger also pauses if the VM returns an error. Watchpoint
+    /// hits of the VM are consumed.
+    pub fn cycle<D: Display>(
+        &mut self,
+        vm: &mut Vm,
+        env: &mut Env<D>,
+    ) -> ::Result<Option<Stop>> {
+        if self.mode == Mode::Paused {
+            return Ok(None);
+        }
+
+        // Cycles spent waiting for a key or a timer tick, or after halting, don't execute
+        // anything, so they shouldn't complete steps or hit breakpoints.
+        let executes = !vm.is_halted() && !vm.is_waiting_for_key() && !vm.is_waiting_for_vblank();
+        if executes && !self.leaving_breakpoint && self.breakpoints.contains(&vm.pc()) {
+            self.mode = Mode::Paused;
+            return Ok(Some(Stop::Breakpoint(vm.pc())));
+        }
+        if let Err(e) = vm.cycle(env) {
+            self.mode = Mode::Paused;
+            return Err(e);
+        }
+        if !executes {
+            return Ok(None);
+        }
+        self.leaving_breakpoint = false;
+
+        let stop = if let Some(hit) = vm.take_watch_hits().into_iter().next() {
+            Some(Stop::Watchpoint(hit))
+        } else {
+            match self.mode {
+                Mode::StepInto => Some(Stop::Step),
+                Mode::StepOver { pc, depth } if vm.pc() == pc && vm.stack().len() == depth => {
+                    Some(Stop::Step)
+                }
+                Mode::StepOut { depth } if vm.stack().len() < depth => Some(Stop::Step),
+                _ => None,
+            }
+        };
+        if stop.is_some() {
+            self.mode = Mode::Paused;
+        }
+        Ok(stop)
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Debugger {
+        Debugger::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quirks::Quirks;
+    use vm::tests::env;
+
+    // 0x200: CALL 0x206
+    // 0x202: LD V1, 0x01
+    // 0x204: JP 0x204
+    // 0x206: LD V2, 0x02
+    // 0x208: RET
+    const ROM: [u8; 10] = [0x22, 0x06, 0x61, 0x01, 0x12, 0x04, 0x62, 0x02, 0x00, 0xEE];
+
+    fn run(debugger: &mut Debugger, vm: &mut Vm) -> Option<Stop> {
+        let mut env = env([0; 16]);
+        for _ in 0..100 {
+            if let Some(stop) = debugger.cycle(vm, &mut env).unwrap() {
+                return Some(stop);
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn stops_at_breakpoint() {
+        let mut vm = Vm::with_rom(&ROM, Quirks::default()).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x208);
+        assert_eq!(run(&mut debugger, &mut vm), Some(Stop::Breakpoint(0x208)));
+        assert_eq!(vm.pc(), 0x208);
+        assert_eq!(vm.stack(), &[0x202]);
+
+        // Nothing is executed while paused.
+        assert_eq!(run(&mut debugger, &mut vm), None);
+        assert_eq!(vm.pc(), 0x208);
+    }
+
+    #[test]
+    fn stops_at_first_instruction_and_resumes_from_breakpoint() {
+        let mut vm = Vm::with_rom(&ROM, Quirks::default()).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x200);
+        assert_eq!(run(&mut debugger, &mut vm), Some(Stop::Breakpoint(0x200)));
+        assert_eq!(vm.pc(), 0x200);
+        assert!(vm.stack().is_empty());
+
+        debugger.resume();
+        assert_eq!(run(&mut debugger, &mut vm), None);
+        assert_eq!(vm.pc(), 0x204);
+    }
+
+    #[test]
+    fn stops_at_watchpoint() {
+        use vm::{Access, Watchpoint};
+
+        // 0x200: LD I, 0x300
+        // 0x202: LD V0, 0x7B
+        // 0x204: LD B, V0
+        let rom = [0xA3, 0x00, 0x60, 0x7B, 0xF0, 0x33];
+        let mut vm = Vm::with_rom(&rom, Quirks::default()).unwrap();
+        vm.add_watchpoint(Watchpoint {
+            range: 0x301..0x302,
+            read: false,
+            write: true,
+            execute: false,
+        });
+        let mut debugger = Debugger::new();
+        assert_eq!(
+            run(&mut debugger, &mut vm),
+            Some(Stop::Watchpoint(WatchHit {
+                access: Access::Write,
+                addr: 0x301,
+                old: 0,
+                new: 2,
+                pc: 0x204,
+            }))
+        );
+    }
+
+    #[test]
+    fn steps_over_call() {
+        let mut vm = Vm::with_rom(&ROM, Quirks::default()).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.step_over(&vm);
+        assert_eq!(run(&mut debugger, &mut vm), Some(Stop::Step));
+        assert_eq!(vm.pc(), 0x202);
+        assert_eq!(vm.reg(::instruction::Reg::V2), 0x02);
+
+        debugger.step_over(&vm);
+        assert_eq!(run(&mut debugger, &mut vm), Some(Stop::Step));
+        assert_eq!(vm.pc(), 0x204);
+    }
+
+    #[test]
+    fn steps_into_and_out() {
+        let mut vm = Vm::with_rom(&ROM, Quirks::default()).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.step_into();
+        assert_eq!(run(&mut debugger, &mut vm), Some(Stop::Step));
+        assert_eq!(vm.pc(), 0x206);
+
+        debugger.step_out(&vm);
+        assert_eq!(run(&mut debugger, &mut vm), Some(Stop::Step));
+        assert_eq!(vm.pc(), 0x202);
+        assert!(vm.stack().is_empty());
+    }
+}
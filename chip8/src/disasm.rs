@@ -0,0 +1,230 @@
+//! Disassembler separating code from data by tracing control flow.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use instruction::{Instruction, InstructionWord};
+
+/// Address at which ROMs are loaded.
+const ROM_START: u16 = 0x200;
+
+pub enum Item {
+    /// Instruction reachable from the entry point. `operand` holds
+    /// the word following `F000` (`LD I, LONG`).
+    Code {
+        addr: u16,
+        word: InstructionWord,
+        operand: Option<u16>,
+        instruction: Instruction,
+    },
+    /// Byte not reached by tracing, most likely sprite data.
+    Data { addr: u16, byte: u8 },
+}
+
+pub struct Disassembly {
+    pub items: Vec<Item>,
+    /// Labels of jump and call targets.
+    pub labels: BTreeMap<u16, String>,
+}
+
+/// Disassembles a ROM loaded at 0x200.
+///
+/// Instructions are found by recursive descent from the entry point, following
+/// jumps, calls and both outcomes of skips. Targets of `JP V0, addr` can't be known
+/// statically, so code reachable only through them is listed as data.
+pub fn disassemble(rom: &[u8]) -> Disassembly {
+    let rom_end = ROM_START as usize + rom.len();
+    let read_word = |addr: u16| -> Option<u16> {
+        let offset = (addr as usize).checked_sub(ROM_START as usize)?;
+        if offset + 2 > rom.len() {
+            return None;
+        }
+        Some((rom[offset] as u16) << 8 | rom[offset + 1] as u16)
+    };
+
+    let mut code = BTreeMap::new();
+    let mut labels = BTreeMap::new();
+    let mut visited = BTreeSet::new();
+    let mut worklist = vec![ROM_START];
+
+    while let Some(addr) = worklist.pop() {
+        if !visited.insert(addr) {
+            continue;
+        }
+        let word = match read_word(addr) {
+            Some(word) => InstructionWord(word),
+            None => continue,
+        };
+        let instruction = match Instruction::decode(word) {
+            Ok(instruction) => instruction,
+            Err(_) => continue,
+        };
+
+        let mut operand = None;
+        let mut next = addr.wrapping_add(2);
+        let mut successors = Vec::new();
+        match instruction {
+            Instruction::SetILong => {
+                operand = read_word(next);
+                if operand.is_none() {
+                    continue;
+                }
+                next = next.wrapping_add(2);
+                successors.push(next);
+            }
+            Instruction::Jump(ref target) => {
+                labels.insert(target.get(), format!("L{:03X}", target.get()));
+                successors.push(target.get());
+            }
+            Instruction::Call(ref target) => {
+                labels.insert(target.get(), format!("S{:03X}", target.get()));
+                successors.push(target.get());
+                successors.push(next);
+            }
+            Instruction::Ret |
+            Instruction::Exit |
+            Instruction::JumpPlusV0(_) => {}
+            Instruction::SkipEqImm { .. } |
+            Instruction::SkipEqReg { .. } |
+            Instruction::SkipPressed { .. } => {
+                let skip_len = if read_word(next) == Some(0xF000) { 4 } else { 2 };
+                successors.push(next);
+                successors.push(next.wrapping_add(skip_len));
+            }
+            _ => successors.push(next),
+        }
+
+        code.insert(addr, (word, operand, instruction));
+        worklist.extend(successors);
+    }
+
+    let mut items = Vec::new();
+    let mut listed = BTreeSet::new();
+    let mut addr = ROM_START as usize;
+    while addr < rom_end {
+        match code.remove(&(addr as u16)) {
+            Some((word, operand, instruction)) => {
+                listed.insert(addr as u16);
+                items.push(Item::Code {
+                    addr: addr as u16,
+                    word,
+                    operand,
+                    instruction,
+                });
+                addr += if operand.is_some() { 4 } else { 2 };
+            }
+            None => {
+                items.push(Item::Data {
+                    addr: addr as u16,
+                    byte: rom[addr - ROM_START as usize],
+                });
+                addr += 1;
+            }
+        }
+    }
+
+    // Targets outside the ROM or inside other instructions keep raw addresses.
+    labels.retain(|addr, _| listed.contains(addr));
+
+    Disassembly { items, labels }
+}
+
+impl fmt::Display for Disassembly {
+    /// Formats a listing with address, raw bytes and mnemonic on each line.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for item in &self.items {
+            let addr = match *item {
+                Item::Code { addr, .. } |
+                Item::Data { addr, .. } => addr,
+            };
+            if let Some(label) = self.labels.get(&addr) {
+                writeln!(f, "{}:", label)?;
+            }
+
+            match *item {
+                Item::Code {
+                    addr,
+                    word,
+                    operand: Some(operand),
+                    ..
+                } => {
+                    writeln!(
+                        f,
+                        "0x{:03X}  {:04X} {:04X}  LD I, LONG 0x{:04X}",
+                        addr,
+                        word.0,
+                        operand,
+                        operand
+                    )?
+                }
+                Item::Code {
+                    addr,
+                    word,
+                    ref instruction,
+                    ..
+                } => {
+                    let target_label = match *instruction {
+                        Instruction::Jump(ref target) |
+                        Instruction::Call(ref target) => self.labels.get(&target.get()),
+                        _ => None,
+                    };
+                    let mnemonic = match (instruction, target_label) {
+                        (&Instruction::Jump(_), Some(label)) => format!("JP {}", label),
+                        (&Instruction::Call(_), Some(label)) => format!("CALL {}", label),
+                        _ => instruction.to_string(),
+                    };
+                    writeln!(f, "0x{:03X}  {:04X}       {}", addr, word.0, mnemonic)?
+                }
+                Item::Data { addr, byte } => {
+                    writeln!(f, "0x{:03X}  {:02X}         DB 0x{:02X}", addr, byte, byte)?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn separates_code_from_data() {
+        // 0x200: CALL 0x206
+        // 0x202: JP 0x202
+        // 0x204: DB 0xF0, 0x90
+        // 0x206: LD I, 0x204
+        // 0x208: RET
+        let rom = [0x22, 0x06, 0x12, 0x02, 0xF0, 0x90, 0xA2, 0x04, 0x00, 0xEE];
+        let listing = disassemble(&rom).to_string();
+
+        assert_eq!(
+            listing,
+            "0x200  2206       CALL S206\n\
+             L202:\n\
+             0x202  1202       JP L202\n\
+             0x204  F0         DB 0xF0\n\
+             0x205  90         DB 0x90\n\
+             S206:\n\
+             0x206  A204       LD I, 0x204\n\
+             0x208  00EE       RET\n"
+        );
+    }
+
+    #[test]
+    fn labels_only_decoded_targets() {
+        // 0x200: CALL 0x204
+        // 0x202: JP 0x203, into the middle of itself
+        // 0x204: JP 0x400, out of the ROM
+        let rom = [0x22, 0x04, 0x12, 0x03, 0x14, 0x00];
+        let listing = disassemble(&rom).to_string();
+
+        assert_eq!(
+            listing,
+            "0x200  2204       CALL S204\n\
+             0x202  1203       JP 0x203\n\
+             S204:\n\
+             0x204  1400       JP 0x400\n"
+        );
+    }
+}
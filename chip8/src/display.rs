@@ -1,5 +1,471 @@
+use core::cmp;
+use core::fmt;
+use core::slice::Chunks;
 
 pub trait Display {
+    /// Clears the selected planes.
     fn clear(&mut self);
-    fn draw(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool;
+
+    /// Draws `sprite` by XOR-ing it at (`x`, `y`), returns `true` if any lit pixel was erased.
+    ///
+    /// Starting coordinates wrap around the screen. Parts of the sprite crossing the edge
+    /// are either wrapped around or, if `clip` is set, not drawn at all.
+    ///
+    /// If several XO-CHIP planes are selected, `sprite` holds data for each of them in turn.
+    fn draw(&mut self, x: usize, y: usize, sprite: &[u8], clip: bool) -> bool;
+
+    /// Same as `draw`, but for 16 pixels wide SUPER-CHIP sprites, two bytes per row.
+    fn draw_wide(&mut self, x: usize, y: usize, sprite: &[u8], clip: bool) -> bool;
+
+    /// Scrolls the selected planes down by `n` pixels.
+    fn scroll_down(&mut self, n: usize);
+
+    /// Scrolls the selected planes left by 4 pixels.
+    fn scroll_left(&mut self);
+
+    /// Scrolls the selected planes right by 4 pixels.
+    fn scroll_right(&mut self);
+
+    /// Switches between 64x32 and 128x64 SUPER-CHIP resolution, clearing the screen.
+    fn set_high_res(&mut self, high_res: bool);
+
+    /// Scrolls the selected planes up by `n` pixels.
+    fn scroll_up(&mut self, n: usize);
+
+    /// Selects XO-CHIP bitplanes affected by drawing, clearing and scrolling.
+    ///
+    /// Bit 0 of `planes` selects the first plane, bit 1 selects the second one.
+    fn select_planes(&mut self, planes: u8);
+}
+
+/// Contents of a display, as stored in save states.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisplayState {
+    pub high_res: bool,
+    pub planes: u8,
+    /// Pixels of the current resolution row by row, each holding a bit per XO-CHIP plane.
+    pub pixels: Vec<u8>,
+}
+
+pub const LOW_RES_WIDTH: usize = 64;
+pub const LOW_RES_HEIGHT: usize = 32;
+pub const HIGH_RES_WIDTH: usize = 128;
+pub const HIGH_RES_HEIGHT: usize = 64;
+
+/// Colors of the background and of pixels lit in XO-CHIP plane 1, plane 2 and both planes.
+pub type Palette = [(u8, u8, u8); 4];
+
+/// Colors used by chipster and by PNG screenshots of the tools.
+pub const DEFAULT_PALETTE: Palette = [(250, 242, 219), (5, 31, 38), (214, 93, 14), (120, 120, 120)];
+
+/// Characters of pixels in the text format, indexed by the lit planes.
+const TEXT_PIXELS: [char; 4] = ['.', '#', 'o', '@'];
+
+impl<D: Display + ?Sized> Display for &mut D {
+    fn clear(&mut self) {
+        (**self).clear()
+    }
+
+    fn draw(&mut self, x: usize, y: usize, sprite: &[u8], clip: bool) -> bool {
+        (**self).draw(x, y, sprite, clip)
+    }
+
+    fn draw_wide(&mut self, x: usize, y: usize, sprite: &[u8], clip: bool) -> bool {
+        (**self).draw_wide(x, y, sprite, clip)
+    }
+
+    fn scroll_down(&mut self, n: usize) {
+        (**self).scroll_down(n)
+    }
+
+    fn scroll_left(&mut self) {
+        (**self).scroll_left()
+    }
+
+    fn scroll_right(&mut self) {
+        (**self).scroll_right()
+    }
+
+    fn set_high_res(&mut self, high_res: bool) {
+        (**self).set_high_res(high_res)
+    }
+
+    fn scroll_up(&mut self, n: usize) {
+        (**self).scroll_up(n)
+    }
+
+    fn select_planes(&mut self, planes: u8) {
+        (**self).select_planes(planes)
+    }
+}
+
+/// Rectangular area of the screen, in pixels of the current resolution.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Region {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Region {
+    fn union(self, other: Region) -> Region {
+        let x = cmp::min(self.x, other.x);
+        let y = cmp::min(self.y, other.y);
+        Region {
+            x,
+            y,
+            width: cmp::max(self.x + self.width, other.x + other.width) - x,
+            height: cmp::max(self.y + self.height, other.y + other.height) - y,
+        }
+    }
+}
+
+/// Screen contents, implementing `Display` for frontends which only need to read pixels.
+///
+/// To let the VM draw while keeping the framebuffer, pass `&mut Framebuffer` as the display.
+/// Framebuffers are equal if they have the same resolution and pixels.
+#[derive(Clone)]
+pub struct Framebuffer {
+    high_res: bool,
+    planes: u8,
+    /// Each pixel holds a bit per XO-CHIP plane.
+    pixels: [u8; HIGH_RES_WIDTH * HIGH_RES_HEIGHT],
+    dirty: Option<Region>,
+}
+
+impl Framebuffer {
+    /// Creates a blank low resolution framebuffer with the first plane selected.
+    pub fn new() -> Framebuffer {
+        Framebuffer {
+            high_res: false,
+            planes: 1,
+            pixels: [0; HIGH_RES_WIDTH * HIGH_RES_HEIGHT],
+            dirty: None,
+        }
+    }
+
+    pub fn is_high_res(&self) -> bool {
+        self.high_res
+    }
+
+    pub fn width(&self) -> usize {
+        if self.high_res { HIGH_RES_WIDTH } else { LOW_RES_WIDTH }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.high_res { HIGH_RES_HEIGHT } else { LOW_RES_HEIGHT }
+    }
+
+    /// Returns planes lit at (`x`, `y`), a bit per XO-CHIP plane.
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        assert!(x < self.width() && y < self.height(), "pixel is out of the screen");
+        self.pixels[y * self.width() + x]
+    }
+
+    /// Returns pixels of the current resolution row by row.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels[..self.width() * self.height()]
+    }
+
+    pub fn rows<'a>(&'a self) -> Chunks<'a, u8> {
+        self.pixels().chunks(self.width())
+    }
+
+    /// Formats the screen as text, a line per row and a character per pixel:
+    /// `.` if unlit, `#` or `o` if the first or second plane is lit, `@` if both are.
+    pub fn to_text(&self) -> String {
+        let mut text = String::with_capacity((self.width() + 1) * self.height());
+        for row in self.rows() {
+            text.extend(row.iter().map(|&pixel| TEXT_PIXELS[pixel as usize & 3]));
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Parses a screen formatted by `to_text`.
+    ///
+    /// Returns `None` if the text isn't a screen of either resolution.
+    pub fn from_text(text: &str) -> Option<Framebuffer> {
+        let rows: Vec<&str> = text.lines().collect();
+        let mut framebuffer = Framebuffer::new();
+        framebuffer.high_res = match rows.len() {
+            LOW_RES_HEIGHT => false,
+            HIGH_RES_HEIGHT => true,
+            _ => return None,
+        };
+
+        let width = framebuffer.width();
+        for (y, row) in rows.iter().enumerate() {
+            if row.chars().count() != width {
+                return None;
+            }
+            for (x, c) in row.chars().enumerate() {
+                let pixel = TEXT_PIXELS.iter().position(|&p| p == c)?;
+                framebuffer.pixels[y * width + x] = pixel as u8;
+            }
+        }
+        Some(framebuffer)
+    }
+
+    /// Returns the area changed since the last call, if any.
+    pub fn take_dirty_region(&mut self) -> Option<Region> {
+        self.dirty.take()
+    }
+
+    pub fn display_state(&self) -> DisplayState {
+        DisplayState {
+            high_res: self.high_res,
+            planes: self.planes,
+            pixels: self.pixels().to_vec(),
+        }
+    }
+
+    pub fn restore_display_state(&mut self, state: &DisplayState) {
+        self.high_res = state.high_res;
+        self.planes = state.planes;
+        self.clear_planes(0xFF);
+        let pixel_count = self.width() * self.height();
+        for (pixel, &value) in self.pixels[..pixel_count].iter_mut().zip(&state.pixels) {
+            *pixel = value;
+        }
+    }
+
+    fn mark_dirty(&mut self, region: Region) {
+        self.dirty = Some(match self.dirty {
+            Some(dirty) => dirty.union(region),
+            None => region,
+        });
+    }
+
+    fn mark_all_dirty(&mut self) {
+        let (width, height) = (self.width(), self.height());
+        self.mark_dirty(Region { x: 0, y: 0, width, height });
+    }
+
+    fn clear_planes(&mut self, planes: u8) {
+        for i in self.pixels.iter_mut() {
+            *i &= !planes;
+        }
+        self.mark_all_dirty();
+    }
+
+    /// Draws sprite rows `row_bytes` wide into each selected plane.
+    fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8], row_bytes: usize, clip: bool) -> bool {
+        let planes: Vec<u8> = [1, 2].iter().cloned().filter(|p| self.planes & p != 0).collect();
+        if planes.is_empty() || sprite.is_empty() {
+            return false;
+        }
+
+        let plane_len = sprite.len() / planes.len();
+        let mut collision_bit = false;
+        for (&plane, data) in planes.iter().zip(sprite.chunks(plane_len)) {
+            let rows = data.chunks(row_bytes).map(|row| {
+                row.iter().fold(0u16, |acc, &byte| acc << 8 | byte as u16)
+            });
+            collision_bit |= self.draw_rows(x, y, rows, row_bytes * 8, clip, plane);
+        }
+        collision_bit
+    }
+
+    fn draw_rows<I>(
+        &mut self,
+        x: usize,
+        y: usize,
+        rows: I,
+        sprite_width: usize,
+        clip: bool,
+        plane: u8,
+    ) -> bool
+    where
+        I: Iterator<Item = u16>,
+    {
+        let (width, height) = (self.width(), self.height());
+        let mut collision_bit = false;
+
+        for (sy, row) in rows.enumerate() {
+            let dy = y % height + sy;
+            if clip && dy >= height {
+                break;
+            }
+            let dy = dy % height;
+            for sx in 0..sprite_width {
+                let bit_mask = 1 << (sprite_width - 1 - sx);
+                if (row & bit_mask) != 0 {
+                    let dx = x % width + sx;
+                    if clip && dx >= width {
+                        break;
+                    }
+                    let dx = dx % width;
+                    let index = dy * width + dx;
+
+                    if self.pixels[index] & plane != 0 {
+                        collision_bit = true;
+                    }
+                    self.pixels[index] ^= plane;
+                    self.mark_dirty(Region { x: dx, y: dy, width: 1, height: 1 });
+                }
+            }
+        }
+
+        collision_bit
+    }
+
+    /// Moves every pixel of the selected planes by (`dx`, `dy`),
+    /// pixels shifted in from the edges are unlit.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = (self.width() as isize, self.height() as isize);
+        let planes = self.planes;
+        let old = self.pixels;
+
+        for y in 0..height {
+            for x in 0..width {
+                let (src_x, src_y) = (x - dx, y - dy);
+                let src = if src_x >= 0 && src_x < width && src_y >= 0 && src_y < height {
+                    old[(src_y * width + src_x) as usize]
+                } else {
+                    0
+                };
+                let index = (y * width + x) as usize;
+                self.pixels[index] = (old[index] & !planes) | (src & planes);
+            }
+        }
+        self.mark_all_dirty();
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Framebuffer {
+        Framebuffer::new()
+    }
+}
+
+impl PartialEq for Framebuffer {
+    fn eq(&self, other: &Framebuffer) -> bool {
+        self.high_res == other.high_res && self.pixels() == other.pixels()
+    }
+}
+
+impl fmt::Debug for Framebuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Framebuffer {}x{}:\n{}", self.width(), self.height(), self.to_text())
+    }
+}
+
+impl Display for Framebuffer {
+    fn clear(&mut self) {
+        let planes = self.planes;
+        self.clear_planes(planes);
+    }
+
+    fn draw(&mut self, x: usize, y: usize, sprite: &[u8], clip: bool) -> bool {
+        self.draw_sprite(x, y, sprite, 1, clip)
+    }
+
+    fn draw_wide(&mut self, x: usize, y: usize, sprite: &[u8], clip: bool) -> bool {
+        self.draw_sprite(x, y, sprite, 2, clip)
+    }
+
+    fn scroll_down(&mut self, n: usize) {
+        self.scroll(0, n as isize);
+    }
+
+    fn scroll_left(&mut self) {
+        self.scroll(-4, 0);
+    }
+
+    fn scroll_right(&mut self) {
+        self.scroll(4, 0);
+    }
+
+    fn set_high_res(&mut self, high_res: bool) {
+        self.high_res = high_res;
+        self.clear_planes(0xFF);
+    }
+
+    fn scroll_up(&mut self, n: usize) {
+        self.scroll(0, -(n as isize));
+    }
+
+    fn select_planes(&mut self, planes: u8) {
+        self.planes = planes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draw_reports_collisions() {
+        let mut fb = Framebuffer::new();
+        assert!(!fb.draw(0, 0, &[0xF0], false));
+        assert_eq!(&fb.rows().next().unwrap()[..5], &[1, 1, 1, 1, 0]);
+        assert!(fb.draw(2, 0, &[0x80], false));
+        assert_eq!(fb.pixel(2, 0), 0);
+        assert!(!fb.draw(2, 0, &[0x80], false));
+    }
+
+    #[test]
+    fn draw_wraps_or_clips() {
+        let mut wrapped = Framebuffer::new();
+        // Starting coordinates always wrap around.
+        wrapped.draw(LOW_RES_WIDTH + 63, 31, &[0xC0, 0xC0], false);
+        assert_eq!(wrapped.pixel(63, 31), 1);
+        assert_eq!(wrapped.pixel(0, 31), 1);
+        assert_eq!(wrapped.pixel(63, 0), 1);
+        assert_eq!(wrapped.pixel(0, 0), 1);
+
+        let mut clipped = Framebuffer::new();
+        clipped.draw(63, 31, &[0xC0, 0xC0], true);
+        assert_eq!(clipped.pixels().iter().filter(|&&p| p != 0).count(), 1);
+        assert_eq!(clipped.pixel(63, 31), 1);
+    }
+
+    #[test]
+    fn tracks_dirty_region() {
+        let mut fb = Framebuffer::new();
+        assert_eq!(fb.take_dirty_region(), None);
+        fb.draw(10, 5, &[0x80, 0x40], false);
+        fb.draw(20, 1, &[0x80], false);
+        assert_eq!(
+            fb.take_dirty_region(),
+            Some(Region { x: 10, y: 1, width: 11, height: 6 })
+        );
+        assert_eq!(fb.take_dirty_region(), None);
+
+        fb.set_high_res(true);
+        assert_eq!(
+            fb.take_dirty_region(),
+            Some(Region { x: 0, y: 0, width: HIGH_RES_WIDTH, height: HIGH_RES_HEIGHT })
+        );
+    }
+
+    #[test]
+    fn equality_ignores_dirty_region() {
+        let mut a = Framebuffer::new();
+        let mut b = Framebuffer::new();
+        a.draw(3, 3, &[0xFF], false);
+        b.draw(3, 3, &[0xFF], false);
+        b.take_dirty_region();
+        assert_eq!(a, b);
+
+        b.scroll_right();
+        assert!(a != b);
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let mut fb = Framebuffer::new();
+        fb.set_high_res(true);
+        fb.select_planes(3);
+        // First plane rows, then second plane rows.
+        fb.draw(126, 0, &[0xC0, 0x40, 0x40, 0xC0], false);
+        let text = fb.to_text();
+        assert!(text.starts_with(&format!("{}#@\n{}o@\n", ".".repeat(126), ".".repeat(126))));
+        assert_eq!(Framebuffer::from_text(&text), Some(fb));
+
+        assert_eq!(Framebuffer::from_text("..\n"), None);
+        assert_eq!(Framebuffer::from_text(&text.replace('@', "x")), None);
+    }
 }
@@ -0,0 +1,610 @@
+//! Stub implementing the GDB remote serial protocol.
+//!
+//! The stub doesn't run the VM by itself. The host keeps executing cycles with
+//! `Debugger::cycle`, calls `GdbStub::poll` regularly to process packets sent by GDB,
+//! and forwards stops and faults with `report_stop` and `report_fault`.
+//!
+//! Registers are exposed through a target description in the following order:
+//! `v0`-`vf` (8 bits), `i` (16 bits), `pc` (16 bits), `sp` (stack depth, 8 bits, read-only),
+//! `dt` and `st` (8 bits). 16-bit registers are transferred big-endian, so GDB needs
+//! `set endian big`.
+//!
+//! Supported are software breakpoints (`Z0`, `Z1`), write, read and access watchpoints
+//! (`Z2`-`Z4`), single-stepping, continuing and interrupting with Ctrl-C.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use debugger::{Debugger, Stop};
+use instruction::Reg;
+use vm::{Vm, Access, Watchpoint};
+use Error;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" type="uint8"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+const REG_I: usize = 16;
+const REG_PC: usize = 17;
+const REG_SP: usize = 18;
+const REG_DT: usize = 19;
+const REG_ST: usize = 20;
+const REG_COUNT: usize = 21;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+/// Waits for GDB to connect to `addr`.
+///
+/// The returned stream is non-blocking, as expected by `GdbStub::poll`.
+pub fn accept<A: ToSocketAddrs>(addr: A) -> io::Result<TcpStream> {
+    let listener = TcpListener::bind(addr)?;
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    stream.set_nonblocking(true)?;
+    Ok(stream)
+}
+
+pub struct GdbStub<S: Read + Write> {
+    stream: S,
+    /// Received bytes which don't form a complete packet yet.
+    input: Vec<u8>,
+    /// Whether GDB waits for a stop reply after `c` or `s`.
+    running: bool,
+    attached: bool,
+}
+
+impl<S: Read + Write> GdbStub<S> {
+    /// Creates a stub talking to GDB over `stream`, which should be non-blocking.
+    ///
+    /// GDB expects the target to be stopped when it attaches, so the debugger
+    /// should be paused beforehand.
+    pub fn new(stream: S) -> GdbStub<S> {
+        GdbStub {
+            stream,
+            input: Vec::new(),
+            running: false,
+            attached: true,
+        }
+    }
+
+    /// Returns `false` once GDB has detached or killed the program.
+    pub fn is_attached(&self) -> bool {
+        self.attached
+    }
+
+    /// Processes all packets received so far.
+    ///
+    /// Returns an error if the connection is broken.
+    pub fn poll(&mut self, vm: &mut Vm, debugger: &mut Debugger) -> io::Result<()> {
+        let mut buf = [0; 1024];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "GDB closed the connection",
+                    ))
+                }
+                Ok(len) => self.input.extend_from_slice(&buf[..len]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        while let Some(packet) = self.next_packet()? {
+            match packet {
+                Packet::Interrupt => {
+                    debugger.pause();
+                    if self.running {
+                        self.running = false;
+                        self.send(&format!("S{:02x}", SIGINT))?;
+                    }
+                }
+                Packet::Command(command) => {
+                    if let Some(reply) = self.handle(&command, vm, debugger) {
+                        self.send(&reply)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Notifies GDB that the debugger has stopped.
+    pub fn report_stop(&mut self, stop: Stop) -> io::Result<()> {
+        if !self.running {
+            return Ok(());
+        }
+        self.running = false;
+
+        let reply = match stop {
+            Stop::Watchpoint(hit) if hit.access == Access::Write => {
+                format!("T{:02x}watch:{:x};", SIGTRAP, hit.addr)
+            }
+            Stop::Watchpoint(hit) if hit.access == Access::Read => {
+                format!("T{:02x}rwatch:{:x};", SIGTRAP, hit.addr)
+            }
+            _ => format!("S{:02x}", SIGTRAP),
+        };
+        self.send(&reply)
+    }
+
+    /// Notifies GDB that the VM has faulted.
+    pub fn report_fault(&mut self, error: &Error) -> io::Result<()> {
+        if !self.running {
+            return Ok(());
+        }
+        self.running = false;
+
+        let signal = match *error {
+            Error::UnrecognizedInstruction(_) | Error::PcMisaligned(_) => SIGILL,
+            _ => SIGSEGV,
+        };
+        self.send(&format!("S{:02x}", signal))
+    }
+
+    /// Extracts the next packet from the input, acknowledging it.
+    fn next_packet(&mut self) -> io::Result<Option<Packet>> {
+        loop {
+            match self.input.first() {
+                None => return Ok(None),
+                Some(&0x03) => {
+                    self.input.remove(0);
+                    return Ok(Some(Packet::Interrupt));
+                }
+                Some(&b'$') => {}
+                // Acknowledgments and garbage between packets.
+                Some(_) => {
+                    self.input.remove(0);
+                    continue;
+                }
+            }
+
+            let end = match self.input.iter().position(|&b| b == b'#') {
+                Some(end) if end + 3 <= self.input.len() => end,
+                _ => return Ok(None),
+            };
+            let packet: Vec<u8> = self.input.drain(..end + 3).collect();
+            let data = &packet[1..end];
+            let checksum = ::std::str::from_utf8(&packet[end + 1..])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            if checksum != Some(checksum_of(data)) {
+                // GDB retransmits the packet.
+                self.stream.write_all(b"-")?;
+                continue;
+            }
+            self.stream.write_all(b"+")?;
+            return Ok(Some(Packet::Command(
+                String::from_utf8_lossy(data).into_owned(),
+            )));
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.stream.write_all(packet.as_bytes())?;
+        self.stream.flush()
+    }
+
+    /// Handles a command, returning the reply if there should be one immediately.
+    fn handle(&mut self, command: &str, vm: &mut Vm, debugger: &mut Debugger) -> Option<String> {
+        let (kind, args) = command.split_at(command.chars().next().map_or(0, |c| c.len_utf8()));
+        let reply = match kind {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => (0..REG_COUNT).map(|n| read_register(vm, n)).collect(),
+            "G" => {
+                let mut rest = args;
+                for n in 0..REG_COUNT {
+                    let width = register_width(n) * 2;
+                    if rest.len() < width {
+                        break;
+                    }
+                    let (value, tail) = rest.split_at(width);
+                    rest = tail;
+                    if write_register(vm, n, value).is_none() {
+                        return Some(error_reply());
+                    }
+                }
+                ok_reply()
+            }
+            "p" => {
+                match usize::from_str_radix(args, 16) {
+                    Ok(n) if n < REG_COUNT => read_register(vm, n),
+                    _ => error_reply(),
+                }
+            }
+            "P" => {
+                let mut parts = args.splitn(2, '=');
+                let n = parts.next().and_then(|n| usize::from_str_radix(n, 16).ok());
+                match (n, parts.next()) {
+                    (Some(n), Some(value)) if n < REG_COUNT => {
+                        match write_register(vm, n, value) {
+                            Some(()) => ok_reply(),
+                            None => error_reply(),
+                        }
+                    }
+                    _ => error_reply(),
+                }
+            }
+            "m" => {
+                match parse_addr_len(args) {
+                    Some((addr, len)) if addr < vm.memory.len() => {
+                        let end = addr.saturating_add(len).min(vm.memory.len());
+                        to_hex(&vm.memory[addr..end])
+                    }
+                    _ => error_reply(),
+                }
+            }
+            "M" => {
+                let mut parts = args.splitn(2, ':');
+                let range = parts.next().and_then(parse_addr_len);
+                let data = parts.next().and_then(from_hex);
+                match (range, data) {
+                    (Some((addr, len)), Some(ref data))
+                        if data.len() == len &&
+                               addr.checked_add(len).is_some_and(|end| end <= vm.memory.len()) => {
+                        vm.memory[addr..addr + len].copy_from_slice(data);
+                        ok_reply()
+                    }
+                    _ => error_reply(),
+                }
+            }
+            "c" | "s" => {
+                if !args.is_empty() {
+                    match u16::from_str_radix(args, 16) {
+                        Ok(pc) => vm.set_pc(pc),
+                        Err(_) => return Some(error_reply()),
+                    }
+                }
+                if kind == "c" {
+                    debugger.resume();
+                } else {
+                    debugger.step_into();
+                }
+                self.running = true;
+                return None;
+            }
+            "Z" | "z" => {
+                let insert = kind == "Z";
+                let mut parts = args.splitn(2, ',');
+                let point_type = parts.next().unwrap_or("");
+                let (addr, len) = match parts.next().and_then(parse_addr_len) {
+                    Some(addr_len) => addr_len,
+                    None => return Some(error_reply()),
+                };
+                match point_type {
+                    // A breakpoint outside memory would never be hit.
+                    "0" | "1" if addr >= vm.memory.len() => return Some(error_reply()),
+                    "0" | "1" => {
+                        if insert {
+                            debugger.add_breakpoint(addr as u16);
+                        } else {
+                            debugger.remove_breakpoint(addr as u16);
+                        }
+                    }
+                    "2" | "3" | "4" => {
+                        let end = match addr.checked_add(len) {
+                            Some(end) => end,
+                            None => return Some(error_reply()),
+                        };
+                        let watchpoint = Watchpoint {
+                            range: addr..end,
+                            read: point_type != "2",
+                            write: point_type != "3",
+                            execute: false,
+                        };
+                        if insert {
+                            vm.add_watchpoint(watchpoint);
+                        } else {
+                            vm.remove_watchpoint(&watchpoint);
+                        }
+                    }
+                    _ => return Some(String::new()),
+                }
+                ok_reply()
+            }
+            "D" | "k" => {
+                debugger.resume();
+                self.attached = false;
+                if kind == "k" {
+                    return None;
+                }
+                ok_reply()
+            }
+            "H" => ok_reply(),
+            "q" => {
+                if args.starts_with("Supported") {
+                    "PacketSize=1000;qXfer:features:read+".to_string()
+                } else if let Some(range) = args.strip_prefix("Xfer:features:read:target.xml:") {
+                    match parse_addr_len(range) {
+                        Some((offset, len)) => xfer_chunk(TARGET_XML.as_bytes(), offset, len),
+                        None => error_reply(),
+                    }
+                } else if args == "Attached" {
+                    "1".to_string()
+                } else if args == "fThreadInfo" {
+                    "m1".to_string()
+                } else if args == "sThreadInfo" {
+                    "l".to_string()
+                } else {
+                    String::new()
+                }
+            }
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+}
+
+enum Packet {
+    /// Ctrl-C sent while the target is running.
+    Interrupt,
+    Command(String),
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+fn ok_reply() -> String {
+    "OK".to_string()
+}
+
+fn error_reply() -> String {
+    "E01".to_string()
+}
+
+fn register_width(n: usize) -> usize {
+    match n {
+        REG_I | REG_PC => 2,
+        _ => 1,
+    }
+}
+
+fn read_register(vm: &Vm, n: usize) -> String {
+    match n {
+        0..=15 => format!("{:02x}", vm.reg(Reg::from_index(n as u8))),
+        REG_I => format!("{:04x}", vm.i()),
+        REG_PC => format!("{:04x}", vm.pc()),
+        REG_SP => format!("{:02x}", vm.stack().len()),
+        REG_DT => format!("{:02x}", vm.delay_timer()),
+        REG_ST => format!("{:02x}", vm.sound_timer()),
+        _ => unreachable!(),
+    }
+}
+
+/// Writes a register from its hex representation, returns `None` if it's malformed.
+fn write_register(vm: &mut Vm, n: usize, hex: &str) -> Option<()> {
+    if hex.len() != register_width(n) * 2 {
+        return None;
+    }
+    let value = u16::from_str_radix(hex, 16).ok()?;
+    match n {
+        0..=15 => vm.set_reg(Reg::from_index(n as u8), value as u8),
+        REG_I => vm.set_i(value),
+        REG_PC => vm.set_pc(value),
+        // Stack depth can't be changed.
+        REG_SP => {}
+        REG_DT => vm.set_delay_timer(value as u8),
+        REG_ST => vm.set_sound_timer(value as u8),
+        _ => return None,
+    }
+    Some(())
+}
+
+/// Parses `addr,len` in hex.
+fn parse_addr_len(s: &str) -> Option<(usize, usize)> {
+    let mut parts = s.splitn(2, ',');
+    let addr = usize::from_str_radix(parts.next()?, 16).ok()?;
+    let len = usize::from_str_radix(parts.next()?, 16).ok()?;
+    Some((addr, len))
+}
+
+/// Returns a chunk of `data` for `qXfer`, prefixed with `l` if it's the last one.
+fn xfer_chunk(data: &[u8], offset: usize, len: usize) -> String {
+    if offset >= data.len() {
+        return "l".to_string();
+    }
+    let end = offset.saturating_add(len).min(data.len());
+    let prefix = if end == data.len() { 'l' } else { 'm' };
+    format!("{}{}", prefix, String::from_utf8_lossy(&data[offset..end]))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    let hex = hex.as_bytes();
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    hex.chunks(2)
+        .map(|digits| {
+            let digits = ::std::str::from_utf8(digits).ok()?;
+            u8::from_str_radix(digits, 16).ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quirks::Quirks;
+    use vm::tests::env;
+
+    /// Stream replaying scripted input, which would block once it's exhausted.
+    struct Script {
+        input: io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Script {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.input.read(buf)? {
+                0 => Err(io::Error::new(io::ErrorKind::WouldBlock, "no input")),
+                len => Ok(len),
+            }
+        }
+    }
+
+    impl Write for Script {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn packet(data: &str) -> String {
+        format!("${}#{:02x}", data, checksum_of(data.as_bytes()))
+    }
+
+    /// Sends `commands` to the stub and returns everything it replied.
+    fn exchange(
+        stub: &mut GdbStub<Script>,
+        vm: &mut Vm,
+        debugger: &mut Debugger,
+        commands: &[&str],
+    ) -> String {
+        let input: String = commands.iter().map(|c| packet(c)).collect();
+        stub.stream.input = io::Cursor::new(input.into_bytes());
+        stub.stream.output.clear();
+        stub.poll(vm, debugger).unwrap();
+        String::from_utf8(stub.stream.output.clone()).unwrap()
+    }
+
+    fn stub() -> GdbStub<Script> {
+        GdbStub::new(Script {
+            input: io::Cursor::new(Vec::new()),
+            output: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn reads_and_writes_state() {
+        let mut vm = Vm::with_rom(&[0x12, 0x00], Quirks::default()).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.pause();
+        let mut stub = stub();
+
+        let replies = exchange(
+            &mut stub,
+            &mut vm,
+            &mut debugger,
+            &["P3=7f", "p3", "m200,2", "M300,2:abcd", "p11"],
+        );
+        assert_eq!(
+            replies,
+            format!(
+                "+{}+{}+{}+{}+{}",
+                packet("OK"),
+                packet("7f"),
+                packet("1200"),
+                packet("OK"),
+                packet("0200")
+            )
+        );
+        assert_eq!(vm.reg(Reg::V3), 0x7f);
+        assert_eq!(&vm.memory[0x300..0x302], &[0xab, 0xcd]);
+    }
+
+    #[test]
+    fn rejects_out_of_range_requests() {
+        let mut vm = Vm::with_rom(&[0x12, 0x00], Quirks::default()).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.pause();
+        let mut stub = stub();
+
+        let replies = exchange(
+            &mut stub,
+            &mut vm,
+            &mut debugger,
+            &["m200,ffffffffffffffff"],
+        );
+        let memory = to_hex(&vm.memory[0x200..]);
+        assert_eq!(replies, format!("+{}", packet(&memory)));
+
+        let replies = exchange(
+            &mut stub,
+            &mut vm,
+            &mut debugger,
+            &["Z2,300,ffffffffffffffff", "M300,3:\u{20ac}\u{20ac}"],
+        );
+        assert_eq!(replies, format!("+{}+{}", packet("E01"), packet("E01")));
+        assert!(vm.watchpoints().is_empty());
+
+        let replies = exchange(&mut stub, &mut vm, &mut debugger, &["Z0,10200,2", "z1,1000,2"]);
+        assert_eq!(replies, format!("+{}+{}", packet("E01"), packet("E01")));
+        assert!(debugger.breakpoints().is_empty());
+    }
+
+    #[test]
+    fn continues_to_breakpoint() {
+        // 0x200: LD V0, 0x01
+        // 0x202: LD V1, 0x02
+        // 0x204: JP 0x200
+        let rom = [0x60, 0x01, 0x61, 0x02, 0x12, 0x00];
+        let mut vm = Vm::with_rom(&rom, Quirks::default()).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.pause();
+        let mut stub = stub();
+
+        let replies = exchange(&mut stub, &mut vm, &mut debugger, &["Z0,204,2", "c"]);
+        assert_eq!(replies, format!("+{}+", packet("OK")));
+        assert!(!debugger.is_paused());
+
+        let mut env = env([0; 16]);
+        let stop = loop {
+            if let Some(stop) = debugger.cycle(&mut vm, &mut env).unwrap() {
+                break stop;
+            }
+        };
+        stub.stream.output.clear();
+        stub.report_stop(stop).unwrap();
+        assert_eq!(stub.stream.output, packet("S05").into_bytes());
+        assert_eq!(vm.pc(), 0x204);
+    }
+
+    #[test]
+    fn rejects_bad_checksum() {
+        let mut vm = Vm::new(Quirks::default());
+        let mut debugger = Debugger::new();
+        let mut stub = stub();
+        stub.stream.input = io::Cursor::new(b"$g#00".to_vec());
+        stub.poll(&mut vm, &mut debugger).unwrap();
+        assert_eq!(stub.stream.output, b"-");
+    }
+}
@@ -0,0 +1,123 @@
+//! Scripted keypad input for display-less runs.
+//!
+//! A script has a line per change of the keypad state: the frame number followed by
+//! the hex digits of keys held from that frame on. A line with just a frame number
+//! releases all keys. Everything after `#` is a comment.
+//!
+//! ```text
+//! # Press 5 on frame 120 and release it 10 frames later.
+//! 120 5
+//! 130
+//! # Hold 4 and 6 together.
+//! 300 4 6
+//! ```
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// 1-based number of the offending line.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InputScript {
+    /// Keypad states with frames they start at, in order of frames.
+    changes: Vec<(u64, [u8; 16])>,
+}
+
+impl InputScript {
+    pub fn parse(source: &str) -> Result<InputScript, ParseError> {
+        let mut script = InputScript::default();
+        for (index, line) in source.lines().enumerate() {
+            script.parse_line(line).map_err(|message| {
+                ParseError {
+                    line: index + 1,
+                    message,
+                }
+            })?;
+        }
+        Ok(script)
+    }
+
+    /// Parses a line of a script and appends the change of the keypad state on it, if any.
+    pub fn parse_line(&mut self, line: &str) -> Result<(), String> {
+        let code = line.split('#').next().unwrap_or("");
+        let mut fields = code.split_whitespace();
+        let frame = match fields.next() {
+            Some(frame) => {
+                frame.parse::<u64>().map_err(|_| {
+                    format!("invalid frame number '{}'", frame)
+                })?
+            }
+            None => return Ok(()),
+        };
+        if let Some(&(last_frame, _)) = self.changes.last() {
+            if frame <= last_frame {
+                return Err(format!("frame {} doesn't follow frame {}", frame, last_frame));
+            }
+        }
+
+        let mut keyboard = [0; 16];
+        for key in fields {
+            match u8::from_str_radix(key, 16) {
+                Ok(key) if key < 16 => keyboard[key as usize] = 1,
+                _ => return Err(format!("invalid key '{}'", key)),
+            }
+        }
+        self.changes.push((frame, keyboard));
+        Ok(())
+    }
+
+    /// Appends a change of the keypad state at `frame`, which should follow earlier changes.
+    pub fn push(&mut self, frame: u64, keyboard: [u8; 16]) {
+        self.changes.push((frame, keyboard));
+    }
+
+    /// Returns keypad states with frames they start at, in order of frames.
+    pub fn changes(&self) -> &[(u64, [u8; 16])] {
+        &self.changes
+    }
+
+    /// Returns the keypad state at `frame`.
+    pub fn keyboard_at(&self, frame: u64) -> [u8; 16] {
+        self.changes
+            .iter()
+            .take_while(|&&(start, _)| start <= frame)
+            .last()
+            .map_or([0; 16], |&(_, keyboard)| keyboard)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keyboard_follows_script() {
+        let script = InputScript::parse("# comment\n120 5\n\n130 # release\n300 4 6\n").unwrap();
+
+        let pressed = |frame| {
+            let keyboard = script.keyboard_at(frame);
+            (0..16).filter(|&key| keyboard[key] == 1).collect::<Vec<_>>()
+        };
+        assert_eq!(pressed(0), vec![]);
+        assert_eq!(pressed(120), vec![5]);
+        assert_eq!(pressed(129), vec![5]);
+        assert_eq!(pressed(130), vec![]);
+        assert_eq!(pressed(1000), vec![4, 6]);
+    }
+
+    #[test]
+    fn reports_errors() {
+        assert_eq!(InputScript::parse("10 5\n5 4").unwrap_err().line, 2);
+        assert_eq!(InputScript::parse("10 G").unwrap_err().line, 1);
+    }
+}
@@ -1,3 +1,5 @@
+use core::fmt;
+
 use super::{Result, Error};
 
 #[derive(Debug, Copy, Clone)]
@@ -13,7 +15,7 @@ impl InstructionWord {
     }
 
     pub fn addr(self) -> Addr {
-        Addr(self.nnn())
+        Addr(self.0 & 0x0FFF)
     }
 
     pub fn kk(self) -> u8 {
@@ -29,7 +31,7 @@ impl InstructionWord {
     }
 
     pub fn imm4(self) -> Imm4 {
-        Imm4(self.n())
+        Imm4((self.0 & 0xF) as u8)
     }
 
     pub fn x_reg(self) -> Reg {
@@ -43,8 +45,24 @@ impl InstructionWord {
     }
 }
 
-#[derive(Debug, PartialEq)]
-pub struct Addr(pub u16); // TODO: Only & 0x0FFF
+/// 12-bit address.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Addr(u16);
+
+impl Addr {
+    /// Returns `None` if `addr` doesn't fit in 12 bits.
+    pub fn new(addr: u16) -> Option<Addr> {
+        if addr <= 0x0FFF { Some(Addr(addr)) } else { None }
+    }
+
+    pub fn get(self) -> u16 {
+        self.0
+    }
+
+    fn encode_as_nnn(self) -> u16 {
+        self.0
+    }
+}
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Imm(pub u8);
@@ -55,10 +73,20 @@ impl Imm {
     }
 }
 
-#[derive(Debug, PartialEq)]
-pub struct Imm4(pub u8); // TODO: Only & 0x0F
+/// 4-bit immediate.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Imm4(u8);
 
 impl Imm4 {
+    /// Returns `None` if `n` doesn't fit in 4 bits.
+    pub fn new(n: u8) -> Option<Imm4> {
+        if n <= 0x0F { Some(Imm4(n)) } else { None }
+    }
+
+    pub fn get(self) -> u8 {
+        self.0
+    }
+
     fn encode_as_n(self) -> u16 {
         self.0 as u16
     }
@@ -148,6 +176,27 @@ pub enum Instruction {
     /// 0nnn - SYS addr
     Sys(Addr),
 
+    /// 00Cn - SCD nibble
+    ScrollDown(Imm4),
+
+    /// 00Dn - SCU nibble
+    ScrollUp(Imm4),
+
+    /// 00FB - SCR
+    ScrollRight,
+
+    /// 00FC - SCL
+    ScrollLeft,
+
+    /// 00FD - EXIT
+    Exit,
+
+    /// 00FE - LOW
+    LowRes,
+
+    /// 00FF - HIGH
+    HighRes,
+
     /// 1nnn - JP addr
     Jump(Addr),
 
@@ -162,6 +211,12 @@ pub enum Instruction {
     /// 9xy0 - SNE Vx, Vy
     SkipEqReg { vx: Reg, vy: Reg, inv: bool },
 
+    /// 5xy2 - SAVE Vx - Vy
+    StoreRange { vx: Reg, vy: Reg },
+
+    /// 5xy3 - LOAD Vx - Vy
+    LoadRange { vx: Reg, vy: Reg },
+
     /// 6xkk - LD Vx, byte
     PutImm { vx: Reg, imm: Imm },
 
@@ -213,6 +268,29 @@ pub enum Instruction {
 
     /// Fx65 - LD Vx, [I]
     LoadRegs(Reg),
+
+    /// Fx30 - LD HF, Vx
+    LoadBigGlyph(Reg),
+
+    /// Fx75 - LD R, Vx
+    StoreFlags(Reg),
+
+    /// Fx85 - LD Vx, R
+    LoadFlags(Reg),
+
+    /// F000 nnnn - LD I, long addr
+    ///
+    /// The address is stored in the word following the instruction.
+    SetILong,
+
+    /// Fn01 - PLANE n
+    SelectPlanes(Imm4),
+
+    /// F002 - AUDIO
+    LoadAudio,
+
+    /// Fx3A - PITCH Vx
+    SetPitch(Reg),
 }
 
 impl Instruction {
@@ -221,9 +299,16 @@ impl Instruction {
 
         let insn = match iw.op() {
             0x0 => {
-                match iw.kk() {
-                    0xE0 => ClearScreen,
-                    0xEE => Ret,
+                match iw.nnn() {
+                    0x0E0 => ClearScreen,
+                    0x0EE => Ret,
+                    0x0FB => ScrollRight,
+                    0x0FC => ScrollLeft,
+                    0x0FD => Exit,
+                    0x0FE => LowRes,
+                    0x0FF => HighRes,
+                    nnn if nnn & 0xFF0 == 0x0C0 => ScrollDown(iw.imm4()),
+                    nnn if nnn & 0xFF0 == 0x0D0 => ScrollUp(iw.imm4()),
                     _ => Sys(iw.addr()),
                 }
             }
@@ -244,13 +329,30 @@ impl Instruction {
                 }
             }
             0x5 => {
-                SkipEqReg {
-                    vx: iw.x_reg(),
-                    vy: iw.y_reg(),
-                    inv: false,
+                match iw.n() {
+                    0x0 => {
+                        SkipEqReg {
+                            vx: iw.x_reg(),
+                            vy: iw.y_reg(),
+                            inv: false,
+                        }
+                    }
+                    0x2 => {
+                        StoreRange {
+                            vx: iw.x_reg(),
+                            vy: iw.y_reg(),
+                        }
+                    }
+                    0x3 => {
+                        LoadRange {
+                            vx: iw.x_reg(),
+                            vy: iw.y_reg(),
+                        }
+                    }
+                    _ => return Err(Error::UnrecognizedInstruction(iw)),
                 }
             }
-            0x9 => {
+            0x9 if iw.n() == 0x0 => {
                 SkipEqReg {
                     vx: iw.x_reg(),
                     vy: iw.y_reg(),
@@ -276,9 +378,7 @@ impl Instruction {
                     f: {
                         use enum_primitive::FromPrimitive;
 
-                        Fun::from_u8(iw.n()).ok_or_else(
-                            || Error::UnrecognizedInstruction(iw),
-                        )?
+                        Fun::from_u8(iw.n()).ok_or(Error::UnrecognizedInstruction(iw))?
                     },
                 }
             }
@@ -316,6 +416,10 @@ impl Instruction {
             }
             0xF => {
                 match iw.kk() {
+                    0x00 if iw.0 == 0xF000 => SetILong,
+                    0x01 => SelectPlanes(Imm4(((iw.0 & 0x0F00) >> 8) as u8)),
+                    0x02 if iw.0 == 0xF002 => LoadAudio,
+                    0x3A => SetPitch(iw.x_reg()),
                     0x07 => GetDT(iw.x_reg()),
                     0x0A => WaitKey(iw.x_reg()),
                     0x15 => SetDT(iw.x_reg()),
@@ -325,6 +429,9 @@ impl Instruction {
                     0x33 => StoreBCD(iw.x_reg()),
                     0x55 => StoreRegs(iw.x_reg()),
                     0x65 => LoadRegs(iw.x_reg()),
+                    0x30 => LoadBigGlyph(iw.x_reg()),
+                    0x75 => StoreFlags(iw.x_reg()),
+                    0x85 => LoadFlags(iw.x_reg()),
                     _ => return Err(Error::UnrecognizedInstruction(iw)),
                 }
             }
@@ -339,8 +446,16 @@ impl Instruction {
         let encoding: u16 = match self {
             ClearScreen => 0x00E0,
             Ret => 0x00EE,
-            Jump(addr) => 0x1000 | addr.0,
-            Call(addr) => 0x2000 | addr.0,
+            Sys(addr) => addr.encode_as_nnn(),
+            ScrollDown(n) => 0x00C0 | n.encode_as_n(),
+            ScrollUp(n) => 0x00D0 | n.encode_as_n(),
+            ScrollRight => 0x00FB,
+            ScrollLeft => 0x00FC,
+            Exit => 0x00FD,
+            LowRes => 0x00FE,
+            HighRes => 0x00FF,
+            Jump(addr) => 0x1000 | addr.encode_as_nnn(),
+            Call(addr) => 0x2000 | addr.encode_as_nnn(),
             SkipEqImm { vx, imm, inv } => {
                 let opcode = if !inv { 0x3000 } else { 0x4000 };
                 opcode | vx.encode_as_vx() | imm.encode_as_kk()
@@ -349,14 +464,16 @@ impl Instruction {
                 let opcode = if !inv { 0x5000 } else { 0x9000 };
                 opcode | vx.encode_as_vx() | vy.encode_as_vy()
             }
+            StoreRange { vx, vy } => 0x5002 | vx.encode_as_vx() | vy.encode_as_vy(),
+            LoadRange { vx, vy } => 0x5003 | vx.encode_as_vx() | vy.encode_as_vy(),
             PutImm { vx, imm } => 0x6000 | vx.encode_as_vx() | imm.encode_as_kk(),
             AddImm { vx, imm } => 0x7000 | vx.encode_as_vx() | imm.encode_as_kk(),
             Apply { vx, vy, f } => {
                 let fun_op = f as u16;
                 0x8000 | vx.encode_as_vx() | vy.encode_as_vy() | fun_op
             }
-            SetI(addr) => 0xA000 | addr.0,
-            JumpPlusV0(addr) => 0xB000 | addr.0,
+            SetI(addr) => 0xA000 | addr.encode_as_nnn(),
+            JumpPlusV0(addr) => 0xB000 | addr.encode_as_nnn(),
             Randomize { vx, imm } => 0xC000 | vx.encode_as_vx() | imm.encode_as_kk(),
             Draw { vx, vy, n } => 0xD000 | vx.encode_as_vx() | vy.encode_as_vy() | n.encode_as_n(),
             SkipPressed { vx, inv } => {
@@ -372,12 +489,97 @@ impl Instruction {
             StoreBCD(vx) => 0xF000 | vx.encode_as_vx() | 0x0033,
             StoreRegs(vx) => 0xF000 | vx.encode_as_vx() | 0x0055,
             LoadRegs(vx) => 0xF000 | vx.encode_as_vx() | 0x0065,
-            _ => unimplemented!(),  
+            LoadBigGlyph(vx) => 0xF000 | vx.encode_as_vx() | 0x0030,
+            StoreFlags(vx) => 0xF000 | vx.encode_as_vx() | 0x0075,
+            LoadFlags(vx) => 0xF000 | vx.encode_as_vx() | 0x0085,
+            SetILong => 0xF000,
+            SelectPlanes(n) => 0xF001 | n.encode_as_n() << 8,
+            LoadAudio => 0xF002,
+            SetPitch(vx) => 0xF000 | vx.encode_as_vx() | 0x003A,
         };
         InstructionWord(encoding)
     }
 }
 
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "V{:X}", self.index())
+    }
+}
+
+impl fmt::Display for Instruction {
+    /// Formats the instruction using mnemonics from Cowgod's Chip-8 Technical Reference,
+    /// extended with SUPER-CHIP and XO-CHIP instructions.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Instruction::*;
+
+        match *self {
+            ClearScreen => write!(f, "CLS"),
+            Ret => write!(f, "RET"),
+            Sys(ref addr) => write!(f, "SYS 0x{:03X}", addr.0),
+            ScrollDown(ref n) => write!(f, "SCD {}", n.0),
+            ScrollUp(ref n) => write!(f, "SCU {}", n.0),
+            ScrollRight => write!(f, "SCR"),
+            ScrollLeft => write!(f, "SCL"),
+            Exit => write!(f, "EXIT"),
+            LowRes => write!(f, "LOW"),
+            HighRes => write!(f, "HIGH"),
+            Jump(ref addr) => write!(f, "JP 0x{:03X}", addr.0),
+            Call(ref addr) => write!(f, "CALL 0x{:03X}", addr.0),
+            SkipEqImm { vx, imm, inv } => {
+                let mnemonic = if !inv { "SE" } else { "SNE" };
+                write!(f, "{} {}, 0x{:02X}", mnemonic, vx, imm.0)
+            }
+            SkipEqReg { vx, vy, inv } => {
+                let mnemonic = if !inv { "SE" } else { "SNE" };
+                write!(f, "{} {}, {}", mnemonic, vx, vy)
+            }
+            StoreRange { vx, vy } => write!(f, "SAVE {} - {}", vx, vy),
+            LoadRange { vx, vy } => write!(f, "LOAD {} - {}", vx, vy),
+            PutImm { vx, imm } => write!(f, "LD {}, 0x{:02X}", vx, imm.0),
+            AddImm { vx, imm } => write!(f, "ADD {}, 0x{:02X}", vx, imm.0),
+            Apply { vx, vy, f: ref fun } => {
+                let mnemonic = match *fun {
+                    Fun::Id => "LD",
+                    Fun::Or => "OR",
+                    Fun::And => "AND",
+                    Fun::Xor => "XOR",
+                    Fun::Add => "ADD",
+                    Fun::Subtract => "SUB",
+                    Fun::ShiftRight => "SHR",
+                    Fun::SubtractInv => "SUBN",
+                    Fun::ShiftLeft => "SHL",
+                };
+                write!(f, "{} {}, {}", mnemonic, vx, vy)
+            }
+            SetI(ref addr) => write!(f, "LD I, 0x{:03X}", addr.0),
+            JumpPlusV0(ref addr) => write!(f, "JP V0, 0x{:03X}", addr.0),
+            Randomize { vx, imm } => write!(f, "RND {}, 0x{:02X}", vx, imm.0),
+            Draw { vx, vy, ref n } => write!(f, "DRW {}, {}, {}", vx, vy, n.0),
+            SkipPressed { vx, inv } => {
+                let mnemonic = if !inv { "SKP" } else { "SKNP" };
+                write!(f, "{} {}", mnemonic, vx)
+            }
+            GetDT(vx) => write!(f, "LD {}, DT", vx),
+            WaitKey(vx) => write!(f, "LD {}, K", vx),
+            SetDT(vx) => write!(f, "LD DT, {}", vx),
+            SetST(vx) => write!(f, "LD ST, {}", vx),
+            AddI(vx) => write!(f, "ADD I, {}", vx),
+            LoadGlyph(vx) => write!(f, "LD F, {}", vx),
+            StoreBCD(vx) => write!(f, "LD B, {}", vx),
+            StoreRegs(vx) => write!(f, "LD [I], {}", vx),
+            LoadRegs(vx) => write!(f, "LD {}, [I]", vx),
+            LoadBigGlyph(vx) => write!(f, "LD HF, {}", vx),
+            StoreFlags(vx) => write!(f, "LD R, {}", vx),
+            LoadFlags(vx) => write!(f, "LD {}, R", vx),
+            SetILong => write!(f, "LD I, LONG"),
+            SelectPlanes(ref n) => write!(f, "PLANE {}", n.0),
+            LoadAudio => write!(f, "AUDIO"),
+            SetPitch(vx) => write!(f, "PITCH {}", vx),
+        }
+    }
+}
+
 #[test]
 fn test_apply_xor() {
     let instruction = Instruction::Apply {
@@ -387,3 +589,96 @@ fn test_apply_xor() {
     };
     assert_eq!(instruction.encode().0, 0x83F3);
 }
+
+#[test]
+fn test_decode_sys() {
+    let instruction = Instruction::decode(InstructionWord(0x0123)).unwrap();
+    assert_eq!(instruction, Instruction::Sys(Addr(0x123)));
+}
+
+#[test]
+fn test_decode_jump_plus_v0() {
+    let instruction = Instruction::decode(InstructionWord(0xB234)).unwrap();
+    assert_eq!(instruction, Instruction::JumpPlusV0(Addr(0x234)));
+}
+
+#[test]
+fn test_decode_wait_key() {
+    let instruction = Instruction::decode(InstructionWord(0xF30A)).unwrap();
+    assert_eq!(instruction, Instruction::WaitKey(Reg::V3));
+}
+
+#[test]
+fn test_decode_super_chip() {
+    let decode = |w| Instruction::decode(InstructionWord(w)).unwrap();
+    assert_eq!(decode(0x00C5), Instruction::ScrollDown(Imm4(5)));
+    assert_eq!(decode(0x00FB), Instruction::ScrollRight);
+    assert_eq!(decode(0x00FC), Instruction::ScrollLeft);
+    assert_eq!(decode(0x00FD), Instruction::Exit);
+    assert_eq!(decode(0x00FE), Instruction::LowRes);
+    assert_eq!(decode(0x00FF), Instruction::HighRes);
+    assert_eq!(decode(0xF430), Instruction::LoadBigGlyph(Reg::V4));
+    assert_eq!(decode(0xF775), Instruction::StoreFlags(Reg::V7));
+    assert_eq!(decode(0xF785), Instruction::LoadFlags(Reg::V7));
+}
+
+#[test]
+fn test_decode_xo_chip() {
+    let decode = |w| Instruction::decode(InstructionWord(w)).unwrap();
+    assert_eq!(decode(0x00D3), Instruction::ScrollUp(Imm4(3)));
+    assert_eq!(
+        decode(0x5242),
+        Instruction::StoreRange {
+            vx: Reg::V2,
+            vy: Reg::V4,
+        }
+    );
+    assert_eq!(
+        decode(0x5243),
+        Instruction::LoadRange {
+            vx: Reg::V2,
+            vy: Reg::V4,
+        }
+    );
+    assert_eq!(decode(0xF000), Instruction::SetILong);
+    assert_eq!(decode(0xF201), Instruction::SelectPlanes(Imm4(2)));
+    assert_eq!(decode(0xF002), Instruction::LoadAudio);
+    assert_eq!(decode(0xF13A), Instruction::SetPitch(Reg::V1));
+    assert!(Instruction::decode(InstructionWord(0x5241)).is_err());
+}
+
+#[test]
+fn test_display() {
+    let display = |w| format!("{}", Instruction::decode(InstructionWord(w)).unwrap());
+    assert_eq!(display(0x6312), "LD V3, 0x12");
+    assert_eq!(display(0xD015), "DRW V0, V1, 5");
+    assert_eq!(display(0x2ABC), "CALL 0xABC");
+    assert_eq!(display(0x8AB6), "SHR VA, VB");
+    assert_eq!(display(0xFE65), "LD VE, [I]");
+    assert_eq!(display(0x5122), "SAVE V1 - V2");
+}
+
+#[test]
+fn test_encode_decode_round_trip() {
+    for word in 0..=0xFFFFu16 {
+        let instruction = match Instruction::decode(InstructionWord(word)) {
+            Ok(instruction) => instruction,
+            Err(_) => continue,
+        };
+        // Decoding is deterministic, so encoding back to `word` completes the round trip.
+        let name = format!("{:?}", instruction);
+        assert_eq!(instruction.encode().0, word, "{} doesn't encode back to {:04X}", name, word);
+    }
+}
+
+#[test]
+fn test_addr_range() {
+    assert_eq!(Addr::new(0xFFF), Some(Addr(0xFFF)));
+    assert_eq!(Addr::new(0x1000), None);
+}
+
+#[test]
+fn test_imm4_range() {
+    assert_eq!(Imm4::new(0xF), Some(Imm4(0xF)));
+    assert_eq!(Imm4::new(0x10), None);
+}
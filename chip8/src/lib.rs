@@ -7,7 +7,6 @@
 #[macro_use]
 extern crate collections;
 
-extern crate rand;
 extern crate byteorder;
 #[macro_use]
 extern crate enum_primitive;
@@ -19,16 +18,57 @@ mod stack;
 mod timer;
 mod vm;
 mod regfile;
+mod quirks;
 
+pub mod crc;
 pub mod display;
 pub mod instruction;
+pub mod disasm;
+pub mod asm;
+pub mod debugger;
+pub mod trace;
+pub mod png;
+pub mod input;
+pub mod script;
+pub mod random;
+pub mod movie;
+#[cfg(feature = "std")]
+pub mod gdb;
 
 pub use self::vm::Vm;
 pub use self::vm::Env;
+pub use self::vm::{Access, Watchpoint, WatchHit};
+pub use self::quirks::Quirks;
 
 #[derive(Debug)]
 pub enum Error {
+    /// Instruction word doesn't correspond to any known instruction.
     UnrecognizedInstruction(instruction::InstructionWord),
+
+    /// `CALL` executed at `pc` while all stack frames are in use.
+    StackOverflow { pc: u16 },
+
+    /// `RET` executed at `pc` with an empty stack.
+    StackUnderflow { pc: u16 },
+
+    /// Instruction at `pc` accessed `width` bytes starting at `addr`,
+    /// which doesn't fit in memory.
+    MemoryOutOfBounds { pc: u16, addr: usize, width: usize },
+
+    /// Instruction can't be fetched from `pc`.
+    PcOutOfBounds(u16),
+
+    /// Instruction fetched from odd `pc` with `Vm::set_strict_alignment` enabled.
+    ///
+    /// Odd `pc` isn't a fault by default: the COSMAC VIP fetches instructions from any
+    /// address, and some ROMs jump to code placed at odd addresses.
+    PcMisaligned(u16),
+
+    /// ROM of `len` bytes doesn't fit into the `max` bytes of memory from 0x200.
+    RomTooLarge { len: usize, max: usize },
+
+    /// Save state is malformed, corrupted or has an unsupported version.
+    InvalidSaveState(&'static str),
 }
 
 pub type Result<T> = core::result::Result<T, Error>;
@@ -39,11 +79,18 @@ impl std::error::Error for Error {
     fn description(&self) -> &str {
         match *self {
             Error::UnrecognizedInstruction(_) => "unrecognized instruction",
+            Error::StackOverflow { .. } => "stack overflow",
+            Error::StackUnderflow { .. } => "stack underflow",
+            Error::MemoryOutOfBounds { .. } => "memory access out of bounds",
+            Error::PcOutOfBounds(_) => "program counter out of bounds",
+            Error::PcMisaligned(_) => "program counter misaligned",
+            Error::RomTooLarge { .. } => "ROM too large",
+            Error::InvalidSaveState(_) => "invalid save state",
         }
     }
 
     /// The lower level cause of this error, if any.
-    fn cause(&self) -> Option<&std::error::Error> {
+    fn cause(&self) -> Option<&dyn std::error::Error> {
         None
     }
 }
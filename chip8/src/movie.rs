@@ -0,0 +1,334 @@
+//! Movies: keypad input recorded frame by frame together with everything else the run
+//! depends on, so it can be replayed exactly.
+//!
+//! A frame is `cycles_per_frame` instructions executed with the keypad state of the frame,
+//! followed by a timer tick. A movie is text with a line per header field, keypad changes in
+//! the format of `input` scripts, and `check` lines with `Vm::state_hash` of the machine
+//! before the given frame, to detect replays which diverged from the recording. Everything
+//! after `#` is a comment.
+//!
+//! ```text
+//! # CRC-32 of the ROM, quirks as in `Quirks::to_bits`, random source and its seed.
+//! rom 7c1a51f4
+//! quirks 03
+//! random xorshift 2281
+//! cycles-per-frame 8
+//! # Length of the movie in frames.
+//! frames 1800
+//! check 0 5d2b1e7a
+//! # Press 5 on frame 120 and release it 10 frames later.
+//! 120 5
+//! 130
+//! check 60 0b9c44d2
+//! ```
+//!
+//! Header lines are optional: the ROM isn't checked, quirks, random source and seed are
+//! the defaults of `Vm`, 8 instructions per frame, and the movie ends at its last line.
+
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crc::crc32;
+use display::DisplayState;
+use input::{InputScript, ParseError};
+use quirks::Quirks;
+use random;
+use vm::Vm;
+
+/// Frames between `check` lines written by `Movie::record`.
+pub const CHECK_INTERVAL: u64 = 60;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    /// CRC-32 of the ROM the movie was recorded with, if known.
+    pub rom_crc: Option<u32>,
+    pub quirks: Quirks,
+    /// Name of the random source, one of `random::NAMES`.
+    pub random: String,
+    pub seed: u32,
+    pub cycles_per_frame: u64,
+    /// Number of frames to play.
+    pub frames: u64,
+    input: InputScript,
+    /// State hashes with frames they are taken before, in order of frames.
+    checks: Vec<(u64, u32)>,
+}
+
+impl Movie {
+    /// Creates an empty movie for recording a run of `rom`.
+    pub fn new(
+        rom: &[u8],
+        quirks: Quirks,
+        random: &str,
+        seed: u32,
+        cycles_per_frame: u64,
+    ) -> Movie {
+        Movie {
+            rom_crc: Some(crc32(rom)),
+            quirks,
+            random: random.to_string(),
+            seed,
+            cycles_per_frame,
+            frames: 0,
+            input: InputScript::default(),
+            checks: Vec::new(),
+        }
+    }
+
+    pub fn parse(source: &str) -> Result<Movie, ParseError> {
+        let mut movie = Movie {
+            rom_crc: None,
+            quirks: Quirks::default(),
+            random: "xorshift".to_string(),
+            seed: 0,
+            cycles_per_frame: 8,
+            frames: 0,
+            input: InputScript::default(),
+            checks: Vec::new(),
+        };
+        let mut frames = None;
+        for (index, line) in source.lines().enumerate() {
+            let error = |message: String| {
+                ParseError {
+                    line: index + 1,
+                    message,
+                }
+            };
+
+            let code = line.split('#').next().unwrap_or("");
+            let fields: Vec<&str> = code.split_whitespace().collect();
+            match fields.as_slice() {
+                [] => {}
+                ["rom", crc] => movie.rom_crc = Some(parse_hex(crc).map_err(&error)?),
+                ["quirks", bits] => {
+                    let bits = parse_hex(bits).map_err(&error)?;
+                    if bits > 0x7F {
+                        return Err(error(format!("invalid quirks '{}'", fields[1])));
+                    }
+                    movie.quirks = Quirks::from_bits(bits as u8);
+                }
+                ["random", name, seed] => {
+                    if !random::NAMES.contains(name) {
+                        return Err(error(format!("unknown random source '{}'", name)));
+                    }
+                    movie.random = name.to_string();
+                    movie.seed = parse_number(seed).map_err(&error)?;
+                }
+                ["cycles-per-frame", cycles] => {
+                    movie.cycles_per_frame = parse_number(cycles).map_err(&error)?;
+                    if movie.cycles_per_frame == 0 {
+                        return Err(error("cycles per frame can't be zero".to_string()));
+                    }
+                }
+                ["frames", count] => frames = Some(parse_number(count).map_err(&error)?),
+                ["check", frame, hash] => {
+                    let frame = parse_number(frame).map_err(&error)?;
+                    if let Some(&(last_frame, _)) = movie.checks.last() {
+                        if frame <= last_frame {
+                            return Err(error(format!(
+                                "check of frame {} doesn't follow frame {}",
+                                frame,
+                                last_frame
+                            )));
+                        }
+                    }
+                    movie.checks.push((frame, parse_hex(hash).map_err(&error)?));
+                }
+                _ => {
+                    if fields[0].parse::<u64>().is_err() {
+                        return Err(error(format!("unknown line '{}'", code.trim())));
+                    }
+                    movie.input.parse_line(code).map_err(&error)?;
+                }
+            }
+        }
+
+        let last_change = movie.input.changes().last().map_or(0, |&(frame, _)| {
+            frame.saturating_add(1)
+        });
+        let last_check = movie.checks.last().map_or(0, |&(frame, _)| frame);
+        movie.frames = frames.unwrap_or_else(|| last_change.max(last_check));
+        Ok(movie)
+    }
+
+    /// Formats the movie as text accepted by `parse`.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        if let Some(rom_crc) = self.rom_crc {
+            writeln!(text, "rom {:08x}", rom_crc).unwrap();
+        }
+        writeln!(text, "quirks {:02x}", self.quirks.to_bits()).unwrap();
+        writeln!(text, "random {} {}", self.random, self.seed).unwrap();
+        writeln!(text, "cycles-per-frame {}", self.cycles_per_frame).unwrap();
+        writeln!(text, "frames {}", self.frames).unwrap();
+
+        let mut changes = self.input.changes().iter().peekable();
+        let mut checks = self.checks.iter().peekable();
+        loop {
+            let change_frame = changes.peek().map(|&&(frame, _)| frame);
+            let check_frame = checks.peek().map(|&&(frame, _)| frame);
+            match (change_frame, check_frame) {
+                (Some(change_frame), Some(check_frame)) if check_frame <= change_frame => {
+                    let &(frame, hash) = checks.next().unwrap();
+                    writeln!(text, "check {} {:08x}", frame, hash).unwrap();
+                }
+                (Some(_), _) => {
+                    let &(frame, keyboard) = changes.next().unwrap();
+                    write!(text, "{}", frame).unwrap();
+                    for key in (0..16).filter(|&key| keyboard[key] != 0) {
+                        write!(text, " {:X}", key).unwrap();
+                    }
+                    text.push('\n');
+                }
+                (None, Some(_)) => {
+                    let &(frame, hash) = checks.next().unwrap();
+                    writeln!(text, "check {} {:08x}", frame, hash).unwrap();
+                }
+                (None, None) => break,
+            }
+        }
+        text
+    }
+
+    /// Returns `true` unless the movie was recorded with a different ROM.
+    pub fn matches_rom(&self, rom: &[u8]) -> bool {
+        self.rom_crc.is_none_or(|rom_crc| rom_crc == crc32(rom))
+    }
+
+    /// Creates a VM with `rom` and the quirks and random source of the movie.
+    pub fn vm(&self, rom: &[u8]) -> ::Result<Vm> {
+        let mut vm = Vm::with_rom(rom, self.quirks)?;
+        vm.set_random(random::from_name(&self.random, self.seed).expect("checked by parse"));
+        Ok(vm)
+    }
+
+    /// Records the keypad state during `frame` and, every `CHECK_INTERVAL` frames, the state
+    /// of the machine before it. Frames are expected to be recorded in order, before they run.
+    pub fn record(&mut self, frame: u64, keyboard: [u8; 16], vm: &Vm, display: &DisplayState) {
+        if self.keyboard_at(frame) != keyboard {
+            self.input.push(frame, keyboard);
+        }
+        if frame % CHECK_INTERVAL == 0 {
+            self.checks.push((frame, vm.state_hash(display)));
+        }
+        self.frames = frame + 1;
+    }
+
+    /// Returns the keypad state during `frame`.
+    pub fn keyboard_at(&self, frame: u64) -> [u8; 16] {
+        self.input.keyboard_at(frame)
+    }
+
+    /// Returns `false` if the state of the machine before `frame` differs from the recorded one.
+    pub fn is_in_sync(&self, frame: u64, vm: &Vm, display: &DisplayState) -> bool {
+        match self.checks.binary_search_by_key(&frame, |&(frame, _)| frame) {
+            Ok(index) => self.checks[index].1 == vm.state_hash(display),
+            Err(_) => true,
+        }
+    }
+}
+
+fn parse_number<T: FromStr>(field: &str) -> Result<T, String> {
+    field.parse().map_err(|_| format!("invalid number '{}'", field))
+}
+
+fn parse_hex(field: &str) -> Result<u32, String> {
+    u32::from_str_radix(field, 16).map_err(|_| format!("invalid hexadecimal number '{}'", field))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use display::Framebuffer;
+    use vm::Env;
+
+    // 0x200: LD V1, 3
+    // 0x202: ADD V2, 1
+    // 0x204: SKNP V1
+    // 0x206: RND V0, 0xFF
+    // 0x208: JP 0x202
+    const ROM: [u8; 10] = [0x61, 0x03, 0x72, 0x01, 0xE1, 0xA1, 0xC0, 0xFF, 0x12, 0x02];
+
+    /// Plays `frames` frames of `movie`, returning the first frame which is out of sync.
+    fn play(movie: &Movie, frames: u64, mut record: Option<&mut Movie>) -> Option<u64> {
+        let mut vm = movie.vm(&ROM).unwrap();
+        let mut env = Env {
+            display: Framebuffer::new(),
+            keyboard: [0; 16],
+        };
+        for frame in 0..frames {
+            env.keyboard = movie.keyboard_at(frame);
+            if let Some(ref mut record) = record {
+                record.record(frame, env.keyboard, &vm, &env.display.display_state());
+            }
+            if !movie.is_in_sync(frame, &vm, &env.display.display_state()) {
+                return Some(frame);
+            }
+            for _ in 0..movie.cycles_per_frame {
+                vm.cycle(&mut env).unwrap();
+            }
+            vm.update_timers(1);
+        }
+        None
+    }
+
+    #[test]
+    fn parses_header_and_input() {
+        let movie = Movie::parse(
+            "rom 0000abcd\n\
+             quirks 21 # shift_uses_vy, display_wait\n\
+             random vip 258\n\
+             cycles-per-frame 4\n\
+             \n\
+             check 0 12345678\n\
+             120 5 a\n\
+             130\n",
+        ).unwrap();
+
+        assert_eq!(movie.rom_crc, Some(0xABCD));
+        assert_eq!(movie.quirks, Quirks::from_bits(0x21));
+        assert_eq!((movie.random.as_str(), movie.seed), ("vip", 258));
+        assert_eq!(movie.cycles_per_frame, 4);
+        assert_eq!(movie.frames, 131);
+        assert_eq!(movie.keyboard_at(119), [0; 16]);
+        assert_eq!(movie.keyboard_at(125)[0x5], 1);
+        assert_eq!(movie.keyboard_at(125)[0xA], 1);
+        assert_eq!(movie.keyboard_at(130), [0; 16]);
+        assert_eq!(Movie::parse(&movie.to_text()).unwrap(), movie);
+    }
+
+    #[test]
+    fn reports_errors() {
+        assert_eq!(Movie::parse("random lcg 1").unwrap_err().line, 1);
+        assert_eq!(Movie::parse("10 5\n5 4").unwrap_err().line, 2);
+        assert_eq!(Movie::parse("\ncheck 1 xyz").unwrap_err().line, 2);
+        assert_eq!(Movie::parse("cycles-per-frame 0").unwrap_err().line, 1);
+        assert_eq!(Movie::parse("rewind 10").unwrap_err().line, 1);
+        assert_eq!(Movie::parse("random xorshift 99999999999").unwrap_err().line, 1);
+        assert_eq!(Movie::parse("\n10 5 G").unwrap_err().line, 2);
+    }
+
+    #[test]
+    fn ends_at_last_frame_number() {
+        let movie = Movie::parse("18446744073709551615 5").unwrap();
+        assert_eq!(movie.frames, u64::MAX);
+        assert_eq!(movie.keyboard_at(u64::MAX)[0x5], 1);
+    }
+
+    #[test]
+    fn replays_recording() {
+        let input = Movie::parse("random vip 7\n30 3\n40\n90 C\n100").unwrap();
+        let mut recording = Movie::new(&ROM, input.quirks, "vip", 7, input.cycles_per_frame);
+        assert_eq!(play(&input, 200, Some(&mut recording)), None);
+        assert!(recording.matches_rom(&ROM));
+        assert!(!recording.matches_rom(&ROM[..6]));
+
+        let movie = Movie::parse(&recording.to_text()).unwrap();
+        assert_eq!(movie.frames, 200);
+        assert_eq!(play(&movie, movie.frames, None), None);
+
+        // Releasing the key earlier leaves the random source in a different state.
+        let desynced = Movie::parse(&recording.to_text().replace("\n40\n", "\n35\n")).unwrap();
+        assert_eq!(play(&desynced, desynced.frames, None), Some(60));
+    }
+}
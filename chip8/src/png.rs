@@ -0,0 +1,101 @@
+//! Minimal PNG encoder for screen dumps.
+//!
+//! Image data is stored uncompressed, which is good enough for CHIP-8 resolutions.
+
+use crc::crc32;
+
+/// Largest length of a stored deflate block.
+const MAX_BLOCK_LEN: usize = 0xFFFF;
+
+/// Encodes an 8-bit RGB image, `rgb` holds 3 bytes per pixel row by row.
+///
+/// Panics if `rgb` doesn't match the dimensions.
+pub fn encode_rgb(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    assert_eq!(rgb.len(), width * height * 3, "pixel data doesn't match dimensions");
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+
+    let mut header = Vec::new();
+    put_u32(&mut header, width as u32);
+    put_u32(&mut header, height as u32);
+    // Bit depth 8, RGB color type, default compression, filtering and no interlacing.
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    put_chunk(&mut png, b"IHDR", &header);
+
+    // Each scanline is prefixed with filter type 0 (none).
+    let mut scanlines = Vec::with_capacity(height * (width * 3 + 1));
+    for row in rgb.chunks(width * 3) {
+        scanlines.push(0);
+        scanlines.extend_from_slice(row);
+    }
+    put_chunk(&mut png, b"IDAT", &zlib_stored(&scanlines));
+
+    put_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/// Wraps `data` into a zlib stream of stored (uncompressed) deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = if data.is_empty() {
+        vec![&[]]
+    } else {
+        data.chunks(MAX_BLOCK_LEN).collect()
+    };
+    for (index, block) in blocks.iter().enumerate() {
+        let last = index == blocks.len() - 1;
+        stream.push(if last { 1 } else { 0 });
+        let len = block.len() as u16;
+        stream.extend_from_slice(&[len as u8, (len >> 8) as u8]);
+        stream.extend_from_slice(&[!len as u8, (!len >> 8) as u8]);
+        stream.extend_from_slice(block);
+    }
+    put_u32(&mut stream, adler32(data));
+    stream
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+fn put_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    put_u32(png, data.len() as u32);
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    put_u32(png, crc);
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&[(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adler32_check_value() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn encodes_single_pixel() {
+        let png = encode_rgb(1, 1, &[0xFF, 0x00, 0x00]);
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+        // IDAT holds the zlib header, a stored block with the scanline and the checksum.
+        assert_eq!(&png[37..41], b"IDAT");
+        assert_eq!(
+            &png[41..56],
+            &[0x78, 0x01, 0x01, 0x04, 0x00, 0xFB, 0xFF, 0x00, 0xFF, 0x00, 0x00, 0x03, 0x01, 0x01, 0x00]
+        );
+    }
+}
@@ -0,0 +1,150 @@
+/// Interpretation of instructions whose semantics differ between
+/// CHIP-8 implementations.
+///
+/// See https://github.com/Chromatophore/HP48-Superchip#behaviour-differences
+/// for the overview of the differences.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Quirks {
+    /// `8xy6`/`8xyE` shift `Vy` and store the result into `Vx`.
+    /// Otherwise `Vx` is shifted in place and `Vy` is ignored.
+    pub shift_uses_vy: bool,
+
+    /// `Fx55`/`Fx65` leave `I` pointing past the last accessed byte.
+    /// Otherwise `I` is left unchanged.
+    pub load_store_increments_i: bool,
+
+    /// `8xy1`/`8xy2`/`8xy3` reset `VF` to zero.
+    pub logic_resets_vf: bool,
+
+    /// Sprites drawn across the edge of the screen are clipped.
+    /// Otherwise they wrap around to the opposite edge.
+    pub clip_sprites: bool,
+
+    /// `Bnnn` is interpreted as `BXnn` and jumps to `xnn + Vx`.
+    /// Otherwise it jumps to `nnn + V0`.
+    pub jump_uses_vx: bool,
+
+    /// `Dxyn` waits for the vertical blank interrupt, so at most one
+    /// sprite is drawn per timer tick.
+    pub display_wait: bool,
+
+    /// Memory is 64K as in XO-CHIP. Otherwise it is 4K.
+    pub extended_memory: bool,
+}
+
+impl Quirks {
+    /// Names of presets, as accepted by `from_name`.
+    pub const NAMES: &'static [&'static str] = &["default", "vip", "chip48", "schip", "xochip"];
+
+    /// Returns the preset with the given name, as accepted by the `--quirks` options of the
+    /// tools and the `platform` field of chipster's ROM settings.
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name {
+            "default" => Some(Quirks::default()),
+            "vip" => Some(Quirks::cosmac_vip()),
+            "chip48" => Some(Quirks::chip48()),
+            "schip" => Some(Quirks::super_chip()),
+            "xochip" => Some(Quirks::xo_chip()),
+            _ => None,
+        }
+    }
+
+    /// Returns a bit set for each of `shift_uses_vy`, `load_store_increments_i`,
+    /// `logic_resets_vf`, `clip_sprites`, `jump_uses_vx`, `display_wait` and
+    /// `extended_memory`, starting from the least significant bit.
+    pub fn to_bits(self) -> u8 {
+        let bits = [
+            self.shift_uses_vy,
+            self.load_store_increments_i,
+            self.logic_resets_vf,
+            self.clip_sprites,
+            self.jump_uses_vx,
+            self.display_wait,
+            self.extended_memory,
+        ];
+        bits.iter().enumerate().fold(0, |acc, (bit, &set)| {
+            acc | (set as u8) << bit
+        })
+    }
+
+    /// Inverse of `to_bits`, the most significant bit is ignored.
+    pub fn from_bits(bits: u8) -> Quirks {
+        let bit = |n: u8| bits & (1 << n) != 0;
+        Quirks {
+            shift_uses_vy: bit(0),
+            load_store_increments_i: bit(1),
+            logic_resets_vf: bit(2),
+            clip_sprites: bit(3),
+            jump_uses_vx: bit(4),
+            display_wait: bit(5),
+            extended_memory: bit(6),
+        }
+    }
+
+    /// The original interpreter of the COSMAC VIP.
+    pub fn cosmac_vip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            logic_resets_vf: true,
+            clip_sprites: true,
+            jump_uses_vx: false,
+            display_wait: true,
+            extended_memory: false,
+        }
+    }
+
+    /// CHIP-48 for the HP-48 calculators.
+    pub fn chip48() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            jump_uses_vx: true,
+            display_wait: false,
+            extended_memory: false,
+        }
+    }
+
+    /// SUPER-CHIP 1.1 for the HP-48 calculators.
+    pub fn super_chip() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            jump_uses_vx: true,
+            display_wait: false,
+            extended_memory: false,
+        }
+    }
+
+    /// XO-CHIP, as implemented by Octo.
+    pub fn xo_chip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            jump_uses_vx: false,
+            display_wait: false,
+            extended_memory: true,
+        }
+    }
+}
+
+impl Default for Quirks {
+    /// Interpretation used by this VM before quirks became configurable.
+    fn default() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            jump_uses_vx: false,
+            display_wait: false,
+            extended_memory: false,
+        }
+    }
+}
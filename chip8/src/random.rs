@@ -0,0 +1,236 @@
+//! Sources of random bytes for `Cxkk` (`RND Vx, byte`).
+//!
+//! The source is a part of the VM: its state is stored in save states, restored by
+//! rewinding and advanced by timer ticks, so runs are reproducible from start to end.
+
+use Error;
+
+/// Source of random bytes for `Cxkk`.
+pub trait Random {
+    /// Name identifying the source in save states.
+    fn name(&self) -> &'static str;
+
+    /// Returns the next random byte, which `Cxkk` masks with `kk`.
+    ///
+    /// `memory` is the VM memory, for sources which read bytes of it.
+    fn next_byte(&mut self, memory: &[u8]) -> u8;
+
+    /// Advances the source on a timer tick. Does nothing by default.
+    fn tick(&mut self) {}
+
+    /// Returns the state of the source, as stored in save states.
+    fn state(&self) -> Vec<u8>;
+
+    /// Restores state returned by `state`.
+    fn set_state(&mut self, state: &[u8]) -> ::Result<()>;
+
+    /// Returns a boxed copy of the source, so VMs can be cloned.
+    fn box_clone(&self) -> Box<dyn Random>;
+}
+
+impl Clone for Box<dyn Random> {
+    fn clone(&self) -> Box<dyn Random> {
+        self.box_clone()
+    }
+}
+
+/// Names of built-in sources, as accepted by `from_name`.
+pub const NAMES: &[&str] = &["xorshift", "vip"];
+
+/// Returns a built-in source with the given name, initialized from `seed`.
+pub fn from_name(name: &str, seed: u32) -> Option<Box<dyn Random>> {
+    match name {
+        "xorshift" => Some(Box::new(Xorshift::new(seed))),
+        "vip" => Some(Box::new(Vip::new(seed))),
+        _ => None,
+    }
+}
+
+/// Marsaglia's xorshift generator with 32 bits of state, the default source.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Xorshift {
+    state: u32,
+}
+
+impl Xorshift {
+    const DEFAULT_SEED: u32 = 0x2281_2281;
+
+    /// Zero `seed` is replaced with the default one, as xorshift can't leave the zero state.
+    pub fn new(seed: u32) -> Xorshift {
+        Xorshift {
+            state: if seed == 0 { Xorshift::DEFAULT_SEED } else { seed },
+        }
+    }
+}
+
+impl Default for Xorshift {
+    fn default() -> Xorshift {
+        Xorshift::new(Xorshift::DEFAULT_SEED)
+    }
+}
+
+impl Random for Xorshift {
+    fn name(&self) -> &'static str {
+        "xorshift"
+    }
+
+    fn next_byte(&mut self, _memory: &[u8]) -> u8 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        (x >> 24) as u8
+    }
+
+    fn state(&self) -> Vec<u8> {
+        let x = self.state;
+        vec![(x >> 24) as u8, (x >> 16) as u8, (x >> 8) as u8, x as u8]
+    }
+
+    fn set_state(&mut self, state: &[u8]) -> ::Result<()> {
+        let x = match *state {
+            [a, b, c, d] => (a as u32) << 24 | (b as u32) << 16 | (c as u32) << 8 | d as u32,
+            _ => return Err(Error::InvalidSaveState("invalid random source state")),
+        };
+        if x == 0 {
+            return Err(Error::InvalidSaveState("invalid random source state"));
+        }
+        self.state = x;
+        Ok(())
+    }
+
+    fn box_clone(&self) -> Box<dyn Random> {
+        Box::new(*self)
+    }
+}
+
+/// The `Cxkk` routine of the COSMAC VIP CHIP-8 interpreter.
+///
+/// The VIP keeps a 16-bit random word in register R9, which its interrupt routine increments
+/// on every timer tick. `Cxkk` increments it too and computes, with DF the carry of the
+/// first addition:
+///
+/// ```text
+/// Vx = R9.1 + M(0x0100 + R9.0)
+/// R9.1 = Vx = (DF << 7 | Vx >> 1) + Vx
+/// Vx &= kk
+/// ```
+///
+/// The byte read is code of the interpreter's second page, which isn't in this VM's memory,
+/// so it is read from `INTERPRETER_PAGE` instead.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Vip {
+    r9: u16,
+}
+
+impl Vip {
+    /// The lowest 16 bits of `seed` are the initial random word.
+    pub fn new(seed: u32) -> Vip {
+        Vip { r9: seed as u16 }
+    }
+}
+
+impl Random for Vip {
+    fn name(&self) -> &'static str {
+        "vip"
+    }
+
+    fn next_byte(&mut self, _memory: &[u8]) -> u8 {
+        self.r9 = self.r9.wrapping_add(1);
+        let (high, low) = ((self.r9 >> 8) as u8, self.r9 as u8);
+        let (vx, df) = high.overflowing_add(INTERPRETER_PAGE[low as usize]);
+        let high = ((df as u8) << 7 | vx >> 1).wrapping_add(vx);
+        self.r9 = (high as u16) << 8 | low as u16;
+        high
+    }
+
+    fn tick(&mut self) {
+        self.r9 = self.r9.wrapping_add(1);
+    }
+
+    fn state(&self) -> Vec<u8> {
+        vec![(self.r9 >> 8) as u8, self.r9 as u8]
+    }
+
+    fn set_state(&mut self, state: &[u8]) -> ::Result<()> {
+        match *state {
+            [high, low] => {
+                self.r9 = (high as u16) << 8 | low as u16;
+                Ok(())
+            }
+            _ => Err(Error::InvalidSaveState("invalid random source state")),
+        }
+    }
+
+    fn box_clone(&self) -> Box<dyn Random> {
+        Box::new(*self)
+    }
+}
+
+/// Bytes 0x0100-0x01FF of the VIP CHIP-8 interpreter, read by its `Cxkk` routine.
+const INTERPRETER_PAGE: [u8; 256] = [
+    0x8D, 0xA7, 0x87, 0x32, 0xAC, 0x2A, 0x27, 0x30, 0xF5, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x45, 0xA3, 0x98, 0x56, 0xD4, 0xF8, 0x81, 0xBC, 0xF8, 0x95, 0xAC, 0x22, 0xDC, 0x12,
+    0x56, 0xD4, 0x06, 0xB8, 0xD4, 0x06, 0xA8, 0xD4, 0x64, 0x0A, 0x01, 0xE6, 0x8A, 0xF4, 0xAA, 0x3B,
+    0x28, 0x9A, 0xFC, 0x01, 0xBA, 0xD4, 0xF8, 0x81, 0xBA, 0x06, 0xFA, 0x0F, 0xAA, 0x0A, 0xAA, 0xD4,
+    0xE6, 0x06, 0xBF, 0x93, 0xBE, 0xF8, 0x1B, 0xAE, 0x2A, 0x1A, 0xF8, 0x00, 0x5A, 0x0E, 0xF5, 0x3B,
+    0x4B, 0x56, 0x0A, 0xFC, 0x01, 0x5A, 0x30, 0x40, 0x4E, 0xF6, 0x3B, 0x3C, 0x9F, 0x56, 0x2A, 0x2A,
+    0xD4, 0x00, 0x22, 0x86, 0x52, 0xF8, 0xF0, 0xA7, 0x07, 0x5A, 0x87, 0xF3, 0x17, 0x1A, 0x3A, 0x5B,
+    0x12, 0xD4, 0x22, 0x86, 0x52, 0xF8, 0xF0, 0xA7, 0x0A, 0x57, 0x87, 0xF3, 0x17, 0x1A, 0x3A, 0x6B,
+    0x12, 0xD4, 0x15, 0x85, 0x22, 0x73, 0x95, 0x52, 0x25, 0x45, 0xA5, 0x86, 0xFA, 0x0F, 0xB5, 0xD4,
+    0x45, 0xE6, 0xF3, 0x3A, 0x82, 0x15, 0x15, 0xD4, 0x45, 0xE6, 0xF3, 0x3A, 0x88, 0xD4, 0x45, 0x07,
+    0x30, 0x8C, 0x45, 0x07, 0x30, 0x84, 0xE6, 0x62, 0x26, 0x45, 0xA3, 0x36, 0x88, 0xD4, 0x3E, 0x88,
+    0xD4, 0xF8, 0xF0, 0xA7, 0xE7, 0x45, 0xF4, 0xA5, 0x86, 0xFA, 0x0F, 0x3B, 0xB2, 0xFC, 0x01, 0xB5,
+    0xD4, 0x45, 0x56, 0xD4, 0x45, 0xE6, 0xF4, 0x56, 0xD4, 0x45, 0xFA, 0x0F, 0x3A, 0xC4, 0x07, 0x56,
+    0xD4, 0xAF, 0x22, 0xF8, 0xD3, 0x73, 0x8F, 0xF9, 0xF0, 0x52, 0xE6, 0x07, 0xD2, 0x56, 0xF8, 0xFF,
+    0xA6, 0xF8, 0x00, 0x7E, 0x56, 0xD4, 0x19, 0x89, 0xAE, 0x93, 0xBE, 0x99, 0xEE, 0xF4, 0x56, 0x76,
+    0xE6, 0xF4, 0xB9, 0x56, 0x45, 0xF2, 0x56, 0xD4, 0x45, 0xAA, 0x86, 0xFA, 0x0F, 0xBA, 0xD4, 0x00,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(random: &mut dyn Random, memory: &[u8], count: usize) -> Vec<u8> {
+        (0..count).map(|_| random.next_byte(memory)).collect()
+    }
+
+    #[test]
+    fn xorshift_is_deterministic() {
+        let mut a = Xorshift::new(42);
+        let mut b = Xorshift::new(42);
+        let first = bytes(&mut a, &[], 64);
+        assert_eq!(first, bytes(&mut b, &[], 64));
+        assert!(first.iter().any(|&byte| byte != first[0]));
+        assert_eq!(Xorshift::new(0), Xorshift::default());
+    }
+
+    #[test]
+    fn vip_runs_the_interpreter_routine() {
+        let mut random = Vip::new(0x40FF);
+        // R9 = 0x4100: 0x41 + 0x8D = 0xCE, 0x67 + 0xCE = 0x35.
+        assert_eq!(bytes(&mut random, &[], 2), vec![0x35, 0x4A]);
+        random.tick();
+        assert_eq!(random.state(), vec![0x4A, 0x02]);
+        assert_eq!(bytes(&mut random, &[], 1), vec![0xBA]);
+
+        // The carry of the first addition is shifted into the second one.
+        assert_eq!(bytes(&mut Vip::new(0xF0FF), &[], 1), vec![0x3D]);
+    }
+
+    #[test]
+    fn state_round_trip() {
+        for &name in NAMES {
+            let mut random = from_name(name, 1234).unwrap();
+            random.tick();
+            random.next_byte(&[1, 2, 3]);
+
+            let mut restored = from_name(name, 0).unwrap();
+            restored.set_state(&random.state()).unwrap();
+            assert_eq!(restored.name(), name);
+            assert_eq!(bytes(&mut *restored, &[1, 2, 3], 16), bytes(&mut *random, &[1, 2, 3], 16));
+            assert!(restored.set_state(&[]).is_err());
+        }
+    }
+}
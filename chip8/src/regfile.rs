@@ -2,6 +2,7 @@ use core::ops::{Index, IndexMut};
 use core::fmt;
 use instruction::Reg;
 
+#[derive(Clone)]
 pub struct RegFile {
     gpr: [u8; 16],
 }
@@ -0,0 +1,179 @@
+//! Test scripts driving a ROM with keypad input and checking its screen at given frames.
+//!
+//! A script has a command per line, everything after `#` is a comment:
+//!
+//! ```text
+//! # Hold 4 and 6 for 10 frames starting at frame 120.
+//! at 120 press 4 6 for 10
+//! # Press 5 for a single frame.
+//! at 200 press 5
+//! # Compare the screen after 300 frames with a file in the text format of `Framebuffer`.
+//! at 300 expect screen brix-300.screen
+//! ```
+//!
+//! Presses may overlap, keys of all active presses are held. The script ends
+//! at the last checkpoint.
+
+use input::ParseError;
+
+/// Keys held for a number of frames.
+#[derive(Debug, Clone, PartialEq)]
+struct Press {
+    frame: u64,
+    frames: u64,
+    keys: Vec<u8>,
+}
+
+/// Screen expected after a number of frames.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    pub frame: u64,
+    /// Name of the screen file, relative to the script.
+    pub screen_file_name: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TestScript {
+    presses: Vec<Press>,
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl TestScript {
+    pub fn parse(source: &str) -> Result<TestScript, ParseError> {
+        let mut script = TestScript::default();
+        for (index, line) in source.lines().enumerate() {
+            let error = |message: String| {
+                ParseError {
+                    line: index + 1,
+                    message,
+                }
+            };
+
+            let code = line.split('#').next().unwrap_or("");
+            let fields: Vec<&str> = code.split_whitespace().collect();
+            if fields.is_empty() {
+                continue;
+            }
+            if fields.len() < 3 || fields[0] != "at" {
+                return Err(error("expected 'at FRAME press' or 'at FRAME expect'".to_string()));
+            }
+            let frame = parse_number(fields[1]).map_err(&error)?;
+
+            match fields[2] {
+                "press" => {
+                    let (keys, frames) = match fields[3..].iter().position(|&f| f == "for") {
+                        Some(for_index) => {
+                            let for_index = for_index + 3;
+                            if fields.len() != for_index + 2 {
+                                return Err(error("expected a frame count after 'for'".to_string()));
+                            }
+                            let frames = parse_number(fields[for_index + 1]).map_err(&error)?;
+                            (&fields[3..for_index], frames)
+                        }
+                        None => (&fields[3..], 1),
+                    };
+                    if keys.is_empty() {
+                        return Err(error("expected keys to press".to_string()));
+                    }
+                    let keys = keys.iter()
+                        .map(|key| match u8::from_str_radix(key, 16) {
+                            Ok(key) if key < 16 => Ok(key),
+                            _ => Err(error(format!("invalid key '{}'", key))),
+                        })
+                        .collect::<Result<Vec<u8>, ParseError>>()?;
+                    script.presses.push(Press { frame, frames, keys });
+                }
+                "expect" => {
+                    if fields.len() != 5 || fields[3] != "screen" {
+                        return Err(error("expected 'expect screen FILE'".to_string()));
+                    }
+                    script.checkpoints.push(Checkpoint {
+                        frame,
+                        screen_file_name: fields[4].to_string(),
+                    });
+                }
+                command => return Err(error(format!("unknown command '{}'", command))),
+            }
+        }
+        script.checkpoints.sort_by_key(|checkpoint| checkpoint.frame);
+        Ok(script)
+    }
+
+    /// Returns the keypad state during `frame`.
+    pub fn keyboard_at(&self, frame: u64) -> [u8; 16] {
+        let mut keyboard = [0; 16];
+        for press in &self.presses {
+            if press.frame <= frame && frame < press.frame.saturating_add(press.frames) {
+                for &key in &press.keys {
+                    keyboard[key as usize] = 1;
+                }
+            }
+        }
+        keyboard
+    }
+
+    /// Returns checkpoints in order of frames.
+    pub fn checkpoints(&self) -> &[Checkpoint] {
+        &self.checkpoints
+    }
+
+    /// Returns the number of frames to run, up to the last checkpoint.
+    pub fn frames(&self) -> u64 {
+        self.checkpoints.last().map_or(0, |checkpoint| checkpoint.frame)
+    }
+}
+
+fn parse_number(field: &str) -> Result<u64, String> {
+    field.parse().map_err(|_| format!("invalid number '{}'", field))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_presses_and_checkpoints() {
+        let script = TestScript::parse(
+            "# BRIX\n\
+             at 120 press 4 6 for 10\n\
+             at 125 press 5 # single frame\n\
+             at 300 expect screen brix-300.screen\n\
+             at 200 expect screen brix-200.screen\n",
+        ).unwrap();
+
+        let pressed = |frame| {
+            let keyboard = script.keyboard_at(frame);
+            (0..16).filter(|&key| keyboard[key] == 1).collect::<Vec<_>>()
+        };
+        assert_eq!(pressed(119), vec![]);
+        assert_eq!(pressed(120), vec![4, 6]);
+        assert_eq!(pressed(125), vec![4, 5, 6]);
+        assert_eq!(pressed(126), vec![4, 6]);
+        assert_eq!(pressed(130), vec![]);
+
+        let frames: Vec<u64> = script.checkpoints().iter().map(|c| c.frame).collect();
+        assert_eq!(frames, vec![200, 300]);
+        assert_eq!(script.checkpoints()[0].screen_file_name, "brix-200.screen");
+        assert_eq!(script.frames(), 300);
+    }
+
+    #[test]
+    fn holds_long_presses_to_the_last_frame() {
+        let script = TestScript::parse("at 1 press 5 for 18446744073709551615").unwrap();
+        assert_eq!(script.keyboard_at(0)[0x5], 0);
+        assert_eq!(script.keyboard_at(1)[0x5], 1);
+        assert_eq!(script.keyboard_at(u64::MAX)[0x5], 0);
+        assert_eq!(script.keyboard_at(u64::MAX - 1)[0x5], 1);
+    }
+
+    #[test]
+    fn reports_errors() {
+        let line = |source| TestScript::parse(source).unwrap_err().line;
+        assert_eq!(line("at 1 press 5\nat x press 5"), 2);
+        assert_eq!(line("at 1 press"), 1);
+        assert_eq!(line("at 1 press 5 for"), 1);
+        assert_eq!(line("at 1 press G"), 1);
+        assert_eq!(line("at 1 expect memory a.mem"), 1);
+        assert_eq!(line("\npress 5"), 2);
+    }
+}
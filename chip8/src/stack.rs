@@ -1,5 +1,4 @@
-
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Stack {
     sp: usize,
     frames: [u16; 16],
@@ -13,20 +12,35 @@ impl Stack {
         }
     }
 
-    pub fn pop(&mut self) -> u16 {
-        let value = self.frames[self.sp];
+    /// Returns frames in use, from the bottom to the top.
+    pub fn frames(&self) -> &[u16] {
+        &self.frames[..self.sp]
+    }
+
+    /// Returns frames in use for modification, from the bottom to the top.
+    pub fn frames_mut(&mut self) -> &mut [u16] {
+        &mut self.frames[..self.sp]
+    }
+
+    /// Pops the topmost frame, returns `None` if the stack is empty.
+    pub fn pop(&mut self) -> Option<u16> {
+        if self.sp == 0 {
+            return None;
+        }
+
         self.sp -= 1;
-        value
+        Some(self.frames[self.sp])
     }
 
-    pub fn push(&mut self, value: u16) {
-        let new_sp = self.sp + 1;
-        if new_sp > 15 {
-            panic!("stackoverflow! stack: {:?}", self.frames);
+    /// Pushes a frame, returns `None` if all 16 frames are already in use.
+    pub fn push(&mut self, value: u16) -> Option<()> {
+        if self.sp == self.frames.len() {
+            return None;
         }
 
-        self.sp = new_sp;
-        self.frames[new_sp] = value;
+        self.frames[self.sp] = value;
+        self.sp += 1;
+        Some(())
     }
 }
 
@@ -35,16 +49,25 @@ mod tests {
     use super::*;
 
     #[test]
-    #[should_panic]
     fn pop_from_empty() {
         let mut stack = Stack::new();
-        stack.pop();
+        assert_eq!(None, stack.pop());
     }
 
     #[test]
     fn simple_push_pop() {
         let mut stack = Stack::new();
-        stack.push(128);
-        assert_eq!(128, stack.pop());
+        stack.push(128).unwrap();
+        assert_eq!(Some(128), stack.pop());
+    }
+
+    #[test]
+    fn push_overflow() {
+        let mut stack = Stack::new();
+        for i in 0..16 {
+            assert_eq!(Some(()), stack.push(i));
+        }
+        assert_eq!(None, stack.push(16));
+        assert_eq!(Some(15), stack.pop());
     }
 }
@@ -1,4 +1,5 @@
 
+#[derive(Clone)]
 pub struct Timer {
     left: u8,
 }
@@ -9,7 +10,7 @@ impl Timer {
     }
 
     pub fn step(&mut self, dt: u8) {
-        self.left = self.left.checked_sub(dt).unwrap_or(0);
+        self.left = self.left.saturating_sub(dt);
     }
 
     pub fn get(&self) -> u8 {
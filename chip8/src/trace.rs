@@ -0,0 +1,290 @@
+//! Per-instruction execution traces.
+//!
+//! Traces are collected by `Vm` once enabled with `Vm::set_tracing` and can be stored
+//! in one of two formats.
+//!
+//! The text format has a line per executed instruction with space separated fields:
+//! cycle number in decimal, then PC, instruction word, `V0`-`VF` and `I` after execution
+//! in uppercase hex, followed by the mnemonic, which is ignored when reading:
+//!
+//! ```text
+//! 0 0200 6A02 00000000000000000000020000000000 0000 LD VA, 0x02
+//! ```
+//!
+//! The binary format starts with magic `C8TR` and version 1 as `u16`, followed by 30-byte
+//! records: cycle number `u64`, PC `u16`, instruction word `u16`, `V0`-`VF` and `I` `u16`.
+//! All values are big-endian.
+
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use instruction::{Instruction, InstructionWord};
+
+const MAGIC: &[u8; 4] = b"C8TR";
+const VERSION: u16 = 1;
+const RECORD_LEN: usize = 30;
+
+/// State of the machine after executing an instruction.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TraceRecord {
+    /// Number of instructions executed before this one.
+    pub cycle: u64,
+    /// Address of the instruction.
+    pub pc: u16,
+    pub word: u16,
+    pub regs: [u8; 16],
+    pub i: u16,
+}
+
+impl fmt::Display for TraceRecord {
+    /// Formats the record as a line of the text format, without the line break.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {:04X} {:04X} ", self.cycle, self.pc, self.word)?;
+        for reg in &self.regs {
+            write!(f, "{:02X}", reg)?;
+        }
+        write!(f, " {:04X}", self.i)?;
+        match Instruction::decode(InstructionWord(self.word)) {
+            Ok(instruction) => write!(f, " {}", instruction),
+            Err(_) => write!(f, " ???"),
+        }
+    }
+}
+
+impl TraceRecord {
+    /// Parses a line of the text format.
+    pub fn parse(line: &str) -> Option<TraceRecord> {
+        let mut fields = line.split_whitespace();
+        let cycle = fields.next()?.parse().ok()?;
+        let pc = u16::from_str_radix(fields.next()?, 16).ok()?;
+        let word = u16::from_str_radix(fields.next()?, 16).ok()?;
+        let regs_hex = fields.next()?;
+        let i = u16::from_str_radix(fields.next()?, 16).ok()?;
+
+        if regs_hex.len() != 32 {
+            return None;
+        }
+        let mut regs = [0; 16];
+        for (index, reg) in regs.iter_mut().enumerate() {
+            *reg = u8::from_str_radix(regs_hex.get(index * 2..index * 2 + 2)?, 16).ok()?;
+        }
+
+        Some(TraceRecord {
+            cycle,
+            pc,
+            word,
+            regs,
+            i,
+        })
+    }
+
+    /// Returns names of fields which differ from `other`, ignoring the cycle number.
+    pub fn diff(&self, other: &TraceRecord) -> Vec<String> {
+        let mut fields = Vec::new();
+        if self.pc != other.pc {
+            fields.push("PC".to_string());
+        }
+        if self.word != other.word {
+            fields.push("instruction".to_string());
+        }
+        for index in 0..16 {
+            if self.regs[index] != other.regs[index] {
+                fields.push(format!("V{:X}", index));
+            }
+        }
+        if self.i != other.i {
+            fields.push("I".to_string());
+        }
+        fields
+    }
+
+    fn encode(&self) -> [u8; RECORD_LEN] {
+        let mut buf = [0; RECORD_LEN];
+        for (index, byte) in buf[..8].iter_mut().enumerate() {
+            *byte = (self.cycle >> (56 - index * 8)) as u8;
+        }
+        buf[8] = (self.pc >> 8) as u8;
+        buf[9] = self.pc as u8;
+        buf[10] = (self.word >> 8) as u8;
+        buf[11] = self.word as u8;
+        buf[12..28].copy_from_slice(&self.regs);
+        buf[28] = (self.i >> 8) as u8;
+        buf[29] = self.i as u8;
+        buf
+    }
+
+    fn decode(buf: &[u8; RECORD_LEN]) -> TraceRecord {
+        let cycle = buf[..8].iter().fold(0u64, |cycle, &byte| cycle << 8 | byte as u64);
+        let mut regs = [0; 16];
+        regs.copy_from_slice(&buf[12..28]);
+        TraceRecord {
+            cycle,
+            pc: (buf[8] as u16) << 8 | buf[9] as u16,
+            word: (buf[10] as u16) << 8 | buf[11] as u16,
+            regs,
+            i: (buf[28] as u16) << 8 | buf[29] as u16,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Format {
+    Text,
+    Binary,
+}
+
+/// Writes trace records in the given format.
+pub struct TraceWriter<W: Write> {
+    writer: W,
+    format: Format,
+}
+
+impl<W: Write> TraceWriter<W> {
+    /// Creates a writer, writing the header if the format has one.
+    pub fn new(mut writer: W, format: Format) -> io::Result<TraceWriter<W>> {
+        if format == Format::Binary {
+            writer.write_all(MAGIC)?;
+            writer.write_all(&[(VERSION >> 8) as u8, VERSION as u8])?;
+        }
+        Ok(TraceWriter { writer, format })
+    }
+
+    pub fn write(&mut self, record: &TraceRecord) -> io::Result<()> {
+        match self.format {
+            Format::Text => writeln!(self.writer, "{}", record),
+            Format::Binary => self.writer.write_all(&record.encode()),
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Reads trace records, detecting the format from the header.
+pub struct TraceReader<R: BufRead> {
+    reader: R,
+    format: Format,
+    line: String,
+}
+
+impl<R: BufRead> TraceReader<R> {
+    pub fn new(mut reader: R) -> io::Result<TraceReader<R>> {
+        let format = if reader.fill_buf()?.starts_with(MAGIC) {
+            let mut header = [0; 6];
+            reader.read_exact(&mut header)?;
+            let version = (header[4] as u16) << 8 | header[5] as u16;
+            if version != VERSION {
+                return Err(invalid_data(format!("unsupported trace version {}", version)));
+            }
+            Format::Binary
+        } else {
+            Format::Text
+        };
+        Ok(TraceReader {
+            reader,
+            format,
+            line: String::new(),
+        })
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<TraceRecord>> {
+        match self.format {
+            Format::Text => {
+                loop {
+                    self.line.clear();
+                    if self.reader.read_line(&mut self.line)? == 0 {
+                        return Ok(None);
+                    }
+                    if !self.line.trim().is_empty() {
+                        break;
+                    }
+                }
+                TraceRecord::parse(&self.line).map(Some).ok_or_else(|| {
+                    invalid_data(format!("malformed trace line: {}", self.line.trim()))
+                })
+            }
+            Format::Binary => {
+                let mut buf = [0; RECORD_LEN];
+                let mut len = 0;
+                while len < RECORD_LEN {
+                    match self.reader.read(&mut buf[len..])? {
+                        0 if len == 0 => return Ok(None),
+                        0 => return Err(invalid_data("truncated trace record".to_string())),
+                        read => len += read,
+                    }
+                }
+                Ok(Some(TraceRecord::decode(&buf)))
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for TraceReader<R> {
+    type Item = io::Result<TraceRecord>;
+
+    fn next(&mut self) -> Option<io::Result<TraceRecord>> {
+        match self.read_record() {
+            Ok(Some(record)) => Some(Ok(record)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> TraceRecord {
+        let mut regs = [0; 16];
+        regs[0xA] = 0x02;
+        TraceRecord {
+            cycle: 300,
+            pc: 0x200,
+            word: 0x6A02,
+            regs,
+            i: 0x2F0,
+        }
+    }
+
+    #[test]
+    fn text_format() {
+        let line = record().to_string();
+        assert_eq!(line, "300 0200 6A02 00000000000000000000020000000000 02F0 LD VA, 0x02");
+        assert_eq!(TraceRecord::parse(&line), Some(record()));
+    }
+
+    #[test]
+    fn round_trip() {
+        for &format in &[Format::Text, Format::Binary] {
+            let mut buf = Vec::new();
+            {
+                let mut writer = TraceWriter::new(&mut buf, format).unwrap();
+                writer.write(&record()).unwrap();
+                writer.write(&record()).unwrap();
+            }
+
+            let reader = TraceReader::new(&buf[..]).unwrap();
+            assert_eq!(reader.format(), format);
+            let records: Vec<_> = reader.map(|record| record.unwrap()).collect();
+            assert_eq!(records, vec![record(), record()]);
+        }
+    }
+
+    #[test]
+    fn diff() {
+        let mut other = record();
+        other.regs[0xF] = 1;
+        other.i = 0;
+        assert_eq!(record().diff(&other), vec!["VF", "I"]);
+    }
+}
@@ -1,126 +1,423 @@
 use core::fmt;
-
-use rand::Rng;
+use core::ops::Range;
 
 use stack::Stack;
 use timer;
 use instruction::*;
 use regfile::RegFile;
 use display::Display;
+use quirks::Quirks;
+use random::{Random, Xorshift};
+use trace::TraceRecord;
+use Error;
+
+mod state;
+mod watch;
 
+pub use self::watch::{Access, Watchpoint, WatchHit};
+
+#[derive(Clone)]
 pub struct Vm {
-    pub memory: [u8; 4096],
+    pub memory: Vec<u8>,
     gpr: RegFile,
     stack: Stack,
     pc: u16,
     i: u16,
     dt: timer::Timer,
     st: timer::Timer,
+    wait_key: Option<KeyWait>,
+    vblank: bool,
+    halted: bool,
+    flags: [u8; 16],
+    planes: u8,
+    audio_pattern: Option<[u8; 16]>,
+    pitch: u8,
+    quirks: Quirks,
+    random: Box<dyn Random>,
+    watchpoints: Vec<Watchpoint>,
+    watch_hits: Vec<WatchHit>,
+    cycles: u64,
+    tracing: bool,
+    trace: Vec<TraceRecord>,
+    strict_alignment: bool,
 }
 
-pub struct Env<D: Display, R: Rng> {
+/// State of `Fx0A` (`LD Vx, K`) waiting for a key press.
+#[derive(Debug, Copy, Clone)]
+struct KeyWait {
+    vx: Reg,
+    pressed: Option<u8>,
+}
+
+pub struct Env<D: Display> {
     pub display: D,
-    pub rng: R,
     pub keyboard: [u8; 16],
 }
 
 impl Vm {
-    pub fn new() -> Vm {
+    pub fn new(quirks: Quirks) -> Vm {
+        let memory_size = if quirks.extended_memory { 0x10000 } else { 0x1000 };
         let mut vm = Vm {
-            memory: [0; 4096],
+            memory: vec![0; memory_size],
             gpr: RegFile::new(),
             stack: Stack::new(),
             pc: 0x200,
             i: 0, // TODO: Initial value?
             dt: timer::Timer::new(),
             st: timer::Timer::new(),
+            wait_key: None,
+            vblank: false,
+            halted: false,
+            flags: [0; 16],
+            planes: 1,
+            audio_pattern: None,
+            pitch: 64,
+            quirks,
+            random: Box::new(Xorshift::default()),
+            watchpoints: Vec::new(),
+            watch_hits: Vec::new(),
+            cycles: 0,
+            tracing: false,
+            trace: Vec::new(),
+            strict_alignment: false,
         };
 
         {
             let font_memory = &mut vm.memory[0..80];
             font_memory.copy_from_slice(&FONT_SPRITES);
         }
+        {
+            let big_font_start = BIG_FONT_MEMORY_OFFSET as usize;
+            let big_font_end = big_font_start + BIG_FONT_SPRITES.len();
+            let big_font_memory = &mut vm.memory[big_font_start..big_font_end];
+            big_font_memory.copy_from_slice(&BIG_FONT_SPRITES);
+        }
 
         vm
     }
 
-    pub fn with_rom(rom_data: &[u8]) -> Vm {
-        let mut vm = Vm::new();
+    /// Creates a VM with `rom_data` loaded at 0x200.
+    ///
+    /// Fails with `Error::RomTooLarge` if the ROM doesn't fit into memory.
+    pub fn with_rom(rom_data: &[u8], quirks: Quirks) -> ::Result<Vm> {
+        let mut vm = Vm::new(quirks);
         {
             let rom_start = 0x200;
+            let max = vm.memory.len() - rom_start;
+            if rom_data.len() > max {
+                return Err(Error::RomTooLarge {
+                    len: rom_data.len(),
+                    max,
+                });
+            }
             let rom_end = rom_start + rom_data.len();
-            let mut rom_slice = &mut vm.memory[rom_start..rom_end];
+            let rom_slice = &mut vm.memory[rom_start..rom_end];
             rom_slice.copy_from_slice(rom_data);
         }
 
-        vm
+        Ok(vm)
     }
 
     pub fn update_timers(&mut self, dt: u8) {
         self.dt.step(dt);
         self.st.step(dt);
+        for _ in 0..dt {
+            self.random.tick();
+        }
+        if dt > 0 {
+            self.vblank = true;
+        }
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// Returns the source of random bytes for `Cxkk`.
+    pub fn random(&self) -> &dyn Random {
+        &*self.random
+    }
+
+    /// Replaces the source of random bytes for `Cxkk`, `Xorshift` by default.
+    pub fn set_random(&mut self, random: Box<dyn Random>) {
+        self.random = random;
+    }
+
+    /// Returns `true` if the VM is blocked on `Fx0A` until a key is pressed and released.
+    ///
+    /// While waiting, `cycle` doesn't execute any instructions and only polls the keyboard,
+    /// so hosts don't need to run it at full speed.
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.wait_key.is_some()
+    }
+
+    /// Returns `true` if the instruction at PC is a `DRW` blocked until the next timer tick
+    /// by `Quirks::display_wait`.
+    ///
+    /// Like while waiting for a key, `cycle` doesn't execute anything until the tick.
+    pub fn is_waiting_for_vblank(&self) -> bool {
+        if !self.quirks.display_wait || self.vblank {
+            return false;
+        }
+        let word = self.read_word(self.pc).map(InstructionWord);
+        matches!(word.map(Instruction::decode), Some(Ok(Instruction::Draw { .. })))
+    }
+
+    /// Returns the XO-CHIP audio pattern, if the program has loaded one with `F002`.
+    ///
+    /// Each of 128 bits is a sample to be played back at `audio_sample_rate`.
+    pub fn audio_pattern(&self) -> Option<[u8; 16]> {
+        self.audio_pattern
+    }
+
+    /// Returns playback rate of the audio pattern in samples per second,
+    /// as set by `Fx3A`.
+    pub fn audio_sample_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+
+    /// Returns `true` if the program has executed `00FD` (`EXIT`).
+    ///
+    /// A halted VM doesn't execute any instructions.
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Returns how many instructions have been executed.
+    ///
+    /// Cycles spent waiting for a key or a timer tick, or halted don't count.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Enables or disables recording of a `TraceRecord` for every executed instruction.
+    pub fn set_tracing(&mut self, tracing: bool) {
+        self.tracing = tracing;
+    }
+
+    /// Enables or disables faulting with `Error::PcMisaligned` on fetches from odd addresses.
+    pub fn set_strict_alignment(&mut self, strict: bool) {
+        self.strict_alignment = strict;
+    }
+
+    /// Returns trace records collected since the last call.
+    ///
+    /// Records are accumulated while tracing is enabled, so the host should call this
+    /// regularly, e.g. once per frame.
+    pub fn take_trace(&mut self) -> Vec<TraceRecord> {
+        ::std::mem::take(&mut self.trace)
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn reg(&self, reg: Reg) -> u8 {
+        self.gpr[reg]
+    }
+
+    pub fn set_reg(&mut self, reg: Reg, value: u8) {
+        self.gpr[reg] = value;
+    }
+
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    pub fn set_i(&mut self, i: u16) {
+        self.i = i;
+    }
+
+    /// Returns return addresses of active calls, from the outermost to the innermost.
+    pub fn stack(&self) -> &[u16] {
+        self.stack.frames()
+    }
+
+    /// Returns return addresses of active calls for modification.
+    ///
+    /// The depth of the stack can only be changed by executing `CALL` and `RET`.
+    pub fn stack_mut(&mut self) -> &mut [u16] {
+        self.stack.frames_mut()
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.dt.get()
+    }
+
+    pub fn set_delay_timer(&mut self, ticks: u8) {
+        self.dt.set(ticks);
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.st.get()
     }
 
-    pub fn cycle<D: Display, R: Rng>(&mut self, env: &mut Env<D, R>) -> ::Result<()> {
-        let instruction_word = {
-            use byteorder::{ByteOrder, BigEndian};
-            let actual_pc = self.pc as usize;
-            InstructionWord(BigEndian::read_u16(&self.memory[actual_pc..]))
+    pub fn set_sound_timer(&mut self, ticks: u8) {
+        self.st.set(ticks);
+    }
+
+    pub fn cycle<D: Display>(&mut self, env: &mut Env<D>) -> ::Result<()> {
+        if self.halted {
+            return Ok(());
+        }
+
+        if let Some(wait) = self.wait_key {
+            self.poll_key(wait, &env.keyboard);
+            return Ok(());
+        }
+        if self.is_waiting_for_vblank() {
+            return Ok(());
+        }
+
+        if self.strict_alignment && self.pc % 2 != 0 {
+            return Err(Error::PcMisaligned(self.pc));
+        }
+        let instruction_word = match self.read_word(self.pc) {
+            Some(word) => InstructionWord(word),
+            None => return Err(Error::PcOutOfBounds(self.pc)),
         };
+        let pc = self.pc as usize;
+        self.watch_access(Access::Execute, pc..pc + 2);
         let instruction = Instruction::decode(instruction_word)?;
-        let next_pc = self.execute_instruction(env, instruction);
+        let next_pc = self.execute_instruction(env, instruction)?;
+
+        if self.tracing {
+            let mut regs = [0; 16];
+            for (index, reg) in regs.iter_mut().enumerate() {
+                *reg = self.gpr.read_at_index(index);
+            }
+            self.trace.push(TraceRecord {
+                cycle: self.cycles,
+                pc: self.pc,
+                word: instruction_word.0,
+                regs,
+                i: self.i,
+            });
+        }
+        self.cycles += 1;
         self.pc = next_pc;
 
         Ok(())
     }
 
-    fn execute_instruction<D: Display, R: Rng>(
+    fn read_word(&self, addr: u16) -> Option<u16> {
+        use byteorder::{ByteOrder, BigEndian};
+        let addr = addr as usize;
+        if addr + 2 > self.memory.len() {
+            return None;
+        }
+        Some(BigEndian::read_u16(&self.memory[addr..]))
+    }
+
+    /// Returns address of the instruction following the one at `next_pc`,
+    /// taking into account that `F000 nnnn` is 4 bytes long.
+    fn skip(&self, next_pc: u16) -> u16 {
+        if self.read_word(next_pc) == Some(0xF000) {
+            next_pc.wrapping_add(4)
+        } else {
+            next_pc.wrapping_add(2)
+        }
+    }
+
+    fn poll_key(&mut self, wait: KeyWait, keyboard: &[u8; 16]) {
+        match wait.pressed {
+            None => {
+                if let Some(key) = keyboard.iter().position(|&k| k == 1) {
+                    self.wait_key = Some(KeyWait {
+                        pressed: Some(key as u8),
+                        ..wait
+                    });
+                }
+            }
+            Some(key) => {
+                if keyboard[key as usize] != 1 {
+                    self.gpr[wait.vx] = key;
+                    self.wait_key = None;
+                }
+            }
+        }
+    }
+
+    fn execute_instruction<D: Display>(
         &mut self,
-        env: &mut Env<D, R>,
+        env: &mut Env<D>,
         instruction: Instruction,
-    ) -> u16 {
+    ) -> ::Result<u16> {
         use instruction::Instruction::*;
 
-        let mut next_pc = self.pc + 2;
+        let mut next_pc = self.pc.wrapping_add(2);
 
         match instruction {
             ClearScreen => env.display.clear(),
             Ret => {
-                let retaddr = self.stack.pop();
+                let retaddr = self.stack.pop().ok_or(
+                    Error::StackUnderflow { pc: self.pc },
+                )?;
                 next_pc = retaddr;
             }
             Sys(_addr) => {
-                unimplemented!();
+                // Calls to native RCA 1802 routines can't be emulated, so ignore them
+                // like most interpreters do.
+            }
+            ScrollDown(n) => env.display.scroll_down(n.get() as usize),
+            ScrollUp(n) => env.display.scroll_up(n.get() as usize),
+            ScrollRight => env.display.scroll_right(),
+            ScrollLeft => env.display.scroll_left(),
+            Exit => {
+                self.halted = true;
+                next_pc = self.pc;
             }
+            LowRes => env.display.set_high_res(false),
+            HighRes => env.display.set_high_res(true),
             Jump(addr) => {
-                next_pc = addr.0;
+                next_pc = addr.get();
             }
             Call(addr) => {
-                self.stack.push(next_pc);
-                next_pc = addr.0;
+                self.stack.push(next_pc).ok_or(
+                    Error::StackOverflow { pc: self.pc },
+                )?;
+                next_pc = addr.get();
             }
             SkipEqImm { vx, imm, inv } => {
                 if !inv {
                     if self.gpr[vx] == imm.0 {
-                        next_pc += 2;
+                        next_pc = self.skip(next_pc);
                     }
                 } else {
                     if self.gpr[vx] != imm.0 {
-                        next_pc += 2;
+                        next_pc = self.skip(next_pc);
                     }
                 }
             }
             SkipEqReg { vx, vy, inv } => {
                 if !inv {
                     if self.gpr[vx] == self.gpr[vy] {
-                        next_pc += 2;
+                        next_pc = self.skip(next_pc);
                     }
                 } else {
                     if self.gpr[vx] != self.gpr[vy] {
-                        next_pc += 2;
+                        next_pc = self.skip(next_pc);
                     }
                 }
             }
+            StoreRange { vx, vy } => {
+                let regs = reg_range(vx, vy);
+                let range = self.memory_range(self.i as usize, regs.len())?;
+                let bytes: Vec<u8> = regs.iter().map(|&reg| self.gpr.read_at_index(reg)).collect();
+                self.write_memory(range.start, &bytes);
+            }
+            LoadRange { vx, vy } => {
+                let regs = reg_range(vx, vy);
+                let range = self.memory_range(self.i as usize, regs.len())?;
+                self.watch_access(Access::Read, range.clone());
+                for (byte, reg) in self.memory[range].iter().zip(regs) {
+                    self.gpr.write_at_index(reg, *byte);
+                }
+            }
             PutImm { vx, imm } => {
                 self.gpr[vx] = imm.0;
             }
@@ -138,12 +435,15 @@ impl Vm {
                     }
                     Fun::Or => {
                         self.gpr[vx] = x | y;
+                        self.reset_vf_after_logic();
                     }
                     Fun::And => {
                         self.gpr[vx] = x & y;
+                        self.reset_vf_after_logic();
                     }
                     Fun::Xor => {
                         self.gpr[vx] = x ^ y;
+                        self.reset_vf_after_logic();
                     }
                     Fun::Add => {
                         let (v, overflow) = x.overflowing_add(y);
@@ -156,8 +456,9 @@ impl Vm {
                         self.gpr[Reg::Vf] = if borrow { 0 } else { 1 };
                     }
                     Fun::ShiftRight => {
-                        self.gpr[vx] = y >> 1;
-                        self.gpr[Reg::Vf] = y & 0x01;
+                        let src = if self.quirks.shift_uses_vy { y } else { x };
+                        self.gpr[vx] = src >> 1;
+                        self.gpr[Reg::Vf] = src & 0x01;
                     }
                     Fun::SubtractInv => {
                         let (v, borrow) = y.overflowing_sub(x);
@@ -165,44 +466,63 @@ impl Vm {
                         self.gpr[Reg::Vf] = if borrow { 0 } else { 1 };
                     }
                     Fun::ShiftLeft => {
-                        self.gpr[vx] = y << 1;
-                        self.gpr[Reg::Vf] = y << 1;
+                        let src = if self.quirks.shift_uses_vy { y } else { x };
+                        self.gpr[vx] = src << 1;
+                        self.gpr[Reg::Vf] = src >> 7;
                     }
                 }
             }
             SetI(addr) => {
-                self.i = addr.0;
+                self.i = addr.get();
             }
-            JumpPlusV0(_addr) => {
-                panic!("instruction not implemented 0xBxxx");
+            JumpPlusV0(addr) => {
+                let offset_reg = if self.quirks.jump_uses_vx {
+                    Reg::from_index((addr.get() >> 8) as u8)
+                } else {
+                    Reg::V0
+                };
+                next_pc = addr.get() + self.gpr[offset_reg] as u16;
             }
             Randomize { vx, imm } => {
-                let random_byte = env.rng.gen::<u8>();
+                let random_byte = self.random.next_byte(&self.memory);
                 self.gpr[vx] = random_byte & imm.0;
             }
 
             Draw { vx, vy, n } => {
+                if self.quirks.display_wait {
+                    // `cycle` only gets here after a timer tick, see `is_waiting_for_vblank`.
+                    self.vblank = false;
+                }
+
                 let x = self.gpr[vx] as usize;
                 let y = self.gpr[vy] as usize;
-                let from = self.i as usize;
-                let to = from + (n.0 as usize);
 
-                let collision_bit = {
-                    let sprite = &self.memory[from..to];
-                    env.display.draw(x, y, sprite)
+                let clip = self.quirks.clip_sprites;
+                let plane_count = self.planes.count_ones() as usize;
+                let collision_bit = if n.get() == 0 {
+                    // SUPER-CHIP 16x16 sprite.
+                    let sprite = self.memory_range(self.i as usize, 32 * plane_count)?;
+                    self.watch_access(Access::Read, sprite.clone());
+                    env.display.draw_wide(x, y, &self.memory[sprite], clip)
+                } else {
+                    let sprite_len = n.get() as usize * plane_count;
+                    let sprite = self.memory_range(self.i as usize, sprite_len)?;
+                    self.watch_access(Access::Read, sprite.clone());
+                    env.display.draw(x, y, &self.memory[sprite], clip)
                 };
 
                 self.gpr[Reg::Vf] = if collision_bit { 1 } else { 0 };
             }
             SkipPressed { vx, inv } => {
-                let x = self.gpr[vx] as usize;
+                // Keys are selected by the low nibble, as on the COSMAC VIP.
+                let x = (self.gpr[vx] & 0x0F) as usize;
                 if !inv {
                     if env.keyboard[x] == 1 {
-                        next_pc += 2;
+                        next_pc = self.skip(next_pc);
                     }
                 } else {
                     if env.keyboard[x] != 1 {
-                        next_pc += 2;
+                        next_pc = self.skip(next_pc);
                     }
                 }
             }
@@ -210,8 +530,8 @@ impl Vm {
                 let dt = self.dt.get();
                 self.gpr[vx] = dt;
             }
-            WaitKey(_vx) => {
-                panic!("instruction not implemented 0xFxxA");
+            WaitKey(vx) => {
+                self.wait_key = Some(KeyWait { vx, pressed: None });
             }
             SetDT(vx) => {
                 let x = self.gpr[vx];
@@ -226,34 +546,97 @@ impl Vm {
                 self.i = self.i.wrapping_add(x);
             }
             LoadGlyph(vx) => {
-                let v = self.gpr[vx];
+                let v = self.gpr[vx] & 0x0F;
                 self.i = FONT_MEMORY_OFFSET + v as u16 * 5;
             }
+            LoadBigGlyph(vx) => {
+                let v = self.gpr[vx] & 0x0F;
+                self.i = BIG_FONT_MEMORY_OFFSET + v as u16 * 10;
+            }
+            SetILong => {
+                let operand_addr = self.pc.wrapping_add(2);
+                self.i = self.read_word(operand_addr).ok_or(Error::MemoryOutOfBounds {
+                    pc: self.pc,
+                    addr: operand_addr as usize,
+                    width: 2,
+                })?;
+                let operand_addr = operand_addr as usize;
+                self.watch_access(Access::Read, operand_addr..operand_addr + 2);
+                next_pc = self.pc.wrapping_add(4);
+            }
+            SelectPlanes(n) => {
+                self.planes = n.get() & 0x03;
+                env.display.select_planes(self.planes);
+            }
+            LoadAudio => {
+                let range = self.memory_range(self.i as usize, 16)?;
+                self.watch_access(Access::Read, range.clone());
+                let mut pattern = [0; 16];
+                pattern.copy_from_slice(&self.memory[range]);
+                self.audio_pattern = Some(pattern);
+            }
+            SetPitch(vx) => {
+                self.pitch = self.gpr[vx];
+            }
+            StoreFlags(vx) => {
+                for index in 0..(vx.index() as usize + 1) {
+                    self.flags[index] = self.gpr.read_at_index(index);
+                }
+            }
+            LoadFlags(vx) => {
+                for index in 0..(vx.index() as usize + 1) {
+                    self.gpr.write_at_index(index, self.flags[index]);
+                }
+            }
             StoreBCD(vx) => {
                 let v = self.gpr[vx];
                 let i = self.i as usize;
 
-                self.memory[i] = v / 100;
-                self.memory[i + 1] = (v / 10) % 10;
-                self.memory[i + 2] = (v % 100) % 10;
+                self.memory_range(i, 3)?;
+                self.write_memory(i, &[v / 100, (v / 10) % 10, (v % 100) % 10]);
             }
             StoreRegs(vx) => {
-                let i = self.i as usize;
-                for offset in 0..(vx.index() as usize + 1) {
-                    self.memory[i + offset] = self.gpr.read_at_index(offset);
+                let count = vx.index() as usize + 1;
+                let regs = self.memory_range(self.i as usize, count)?;
+                let bytes: Vec<u8> = (0..count).map(|index| self.gpr.read_at_index(index)).collect();
+                self.write_memory(regs.start, &bytes);
+                if self.quirks.load_store_increments_i {
+                    self.i = self.i.wrapping_add(count as u16);
                 }
-                self.i += vx as u16 + 1;
             }
             LoadRegs(vx) => {
-                let i = self.i as usize;
-                for offset in 0..(vx.index() as usize + 1) {
-                    self.gpr.write_at_index(offset, self.memory[i + offset]);
+                let count = vx.index() as usize + 1;
+                let regs = self.memory_range(self.i as usize, count)?;
+                self.watch_access(Access::Read, regs.clone());
+                for (offset, byte) in self.memory[regs].iter().enumerate() {
+                    self.gpr.write_at_index(offset, *byte);
+                }
+                if self.quirks.load_store_increments_i {
+                    self.i = self.i.wrapping_add(count as u16);
                 }
-                self.i += vx as u16 + 1;
             }
         }
 
-        next_pc
+        Ok(next_pc)
+    }
+
+    fn reset_vf_after_logic(&mut self) {
+        if self.quirks.logic_resets_vf {
+            self.gpr[Reg::Vf] = 0;
+        }
+    }
+
+    /// Returns range of `width` bytes starting at `addr`, or an error
+    /// attributed to the current instruction if it doesn't fit in memory.
+    fn memory_range(&self, addr: usize, width: usize) -> ::Result<Range<usize>> {
+        if addr + width > self.memory.len() {
+            return Err(Error::MemoryOutOfBounds {
+                pc: self.pc,
+                addr,
+                width,
+            });
+        }
+        Ok(addr..addr + width)
     }
 
     pub fn is_beeping(&self) -> bool {
@@ -261,6 +644,16 @@ impl Vm {
     }
 }
 
+/// Returns indices of registers from `vx` to `vy` inclusive, in descending order if `vx > vy`.
+fn reg_range(vx: Reg, vy: Reg) -> Vec<usize> {
+    let (x, y) = (vx.index() as usize, vy.index() as usize);
+    if x <= y {
+        (x..y + 1).collect()
+    } else {
+        (y..x + 1).rev().collect()
+    }
+}
+
 impl fmt::Debug for Vm {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("Vm")
@@ -276,7 +669,7 @@ impl fmt::Debug for Vm {
 
 const FONT_MEMORY_OFFSET: u16 = 0;
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
+#[rustfmt::skip]
 const FONT_SPRITES: [u8; 80] = [
 	0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
 	0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -295,3 +688,465 @@ const FONT_SPRITES: [u8; 80] = [
 	0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
 	0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
+
+const BIG_FONT_MEMORY_OFFSET: u16 = 80;
+
+#[rustfmt::skip]
+const BIG_FONT_SPRITES: [u8; 160] = [
+	0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+	0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+	0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+	0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+	0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+	0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+	0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+	0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+	0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+	0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+	0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+	0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+	0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+	0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+	0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+	0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    pub struct NullDisplay;
+
+    impl Display for NullDisplay {
+        fn clear(&mut self) {}
+
+        fn draw(&mut self, _x: usize, _y: usize, _sprite: &[u8], _clip: bool) -> bool {
+            false
+        }
+
+        fn draw_wide(&mut self, _x: usize, _y: usize, _sprite: &[u8], _clip: bool) -> bool {
+            false
+        }
+
+        fn scroll_down(&mut self, _n: usize) {}
+
+        fn scroll_left(&mut self) {}
+
+        fn scroll_right(&mut self) {}
+
+        fn set_high_res(&mut self, _high_res: bool) {}
+
+        fn scroll_up(&mut self, _n: usize) {}
+
+        fn select_planes(&mut self, _planes: u8) {}
+    }
+
+    pub fn env(keyboard: [u8; 16]) -> Env<NullDisplay> {
+        Env {
+            display: NullDisplay,
+            keyboard,
+        }
+    }
+
+    fn run(vm: &mut Vm, cycles: usize) -> ::Result<()> {
+        let mut env = env([0; 16]);
+        for _ in 0..cycles {
+            vm.cycle(&mut env)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn watchpoints_report_accesses() {
+        // 0x200: LD I, 0x20A
+        // 0x202: DRW V0, V0, 1
+        // 0x204: LD [I], V1
+        // 0x206: JP 0x206
+        // 0x20A: DB 0x80
+        let rom = [0xA2, 0x0A, 0xD0, 0x01, 0xF1, 0x55, 0x12, 0x06, 0x00, 0x00, 0x80];
+        let mut vm = Vm::with_rom(&rom, Quirks::default()).unwrap();
+        vm.set_reg(Reg::V1, 0x42);
+        vm.add_watchpoint(Watchpoint {
+            range: 0x206..0x20C,
+            read: true,
+            write: true,
+            execute: true,
+        });
+        run(&mut vm, 4).unwrap();
+
+        let hit = |access, addr, old, new, pc| {
+            WatchHit {
+                access,
+                addr,
+                old,
+                new,
+                pc,
+            }
+        };
+        assert_eq!(
+            vm.take_watch_hits(),
+            vec![
+                hit(Access::Read, 0x20A, 0x80, 0x80, 0x202),
+                hit(Access::Write, 0x20A, 0x80, 0x00, 0x204),
+                hit(Access::Write, 0x20B, 0x00, 0x42, 0x204),
+                hit(Access::Execute, 0x206, 0x12, 0x12, 0x206),
+                hit(Access::Execute, 0x207, 0x06, 0x06, 0x206),
+            ]
+        );
+        assert!(vm.take_watch_hits().is_empty());
+    }
+
+    #[test]
+    fn traces_executed_instructions() {
+        // 0x200: LD VA, 0x02
+        // 0x202: LD I, 0x2F0
+        let mut vm = Vm::with_rom(&[0x6A, 0x02, 0xA2, 0xF0], Quirks::default()).unwrap();
+        vm.set_tracing(true);
+        run(&mut vm, 2).unwrap();
+
+        let trace = vm.take_trace();
+        assert_eq!(trace.len(), 2);
+        assert_eq!((trace[0].cycle, trace[0].pc, trace[0].word), (0, 0x200, 0x6A02));
+        assert_eq!(trace[0].regs[0xA], 0x02);
+        assert_eq!((trace[1].cycle, trace[1].pc, trace[1].i), (1, 0x202, 0x2F0));
+        assert_eq!(vm.cycles(), 2);
+        assert!(vm.take_trace().is_empty());
+    }
+
+    #[test]
+    fn sys_is_ignored() {
+        // 0x200: SYS 0x123
+        let mut vm = Vm::with_rom(&[0x01, 0x23], Quirks::default()).unwrap();
+        run(&mut vm, 1).unwrap();
+        assert_eq!(vm.pc, 0x202);
+    }
+
+    #[test]
+    fn jump_plus_v0() {
+        // 0x200: LD V0, 0x10
+        // 0x202: JP V0, 0x300
+        let mut vm = Vm::with_rom(&[0x60, 0x10, 0xB3, 0x00], Quirks::default()).unwrap();
+        run(&mut vm, 2).unwrap();
+        assert_eq!(vm.pc, 0x310);
+    }
+
+    #[test]
+    fn shift_left_sets_vf_to_msb() {
+        // 0x200: LD V1, 0x81
+        // 0x202: SHL V0, V1
+        let mut vm = Vm::with_rom(&[0x61, 0x81, 0x80, 0x1E], Quirks::default()).unwrap();
+        run(&mut vm, 2).unwrap();
+        assert_eq!(vm.gpr[Reg::V0], 0x02);
+        assert_eq!(vm.gpr[Reg::Vf], 0x01);
+    }
+
+    #[test]
+    fn shift_in_place_quirk() {
+        // 0x200: LD V0, 0x03
+        // 0x202: LD V1, 0x80
+        // 0x204: SHR V0, V1
+        let rom = [0x60, 0x03, 0x61, 0x80, 0x80, 0x16];
+
+        let mut vm = Vm::with_rom(&rom, Quirks::cosmac_vip()).unwrap();
+        run(&mut vm, 3).unwrap();
+        assert_eq!(vm.gpr[Reg::V0], 0x40);
+        assert_eq!(vm.gpr[Reg::Vf], 0x00);
+
+        let mut vm = Vm::with_rom(&rom, Quirks::super_chip()).unwrap();
+        run(&mut vm, 3).unwrap();
+        assert_eq!(vm.gpr[Reg::V0], 0x01);
+        assert_eq!(vm.gpr[Reg::Vf], 0x01);
+    }
+
+    #[test]
+    fn logic_resets_vf_quirk() {
+        // 0x200: LD VF, 0x05
+        // 0x202: OR V0, V1
+        let rom = [0x6F, 0x05, 0x80, 0x11];
+
+        let mut vm = Vm::with_rom(&rom, Quirks::cosmac_vip()).unwrap();
+        run(&mut vm, 2).unwrap();
+        assert_eq!(vm.gpr[Reg::Vf], 0x00);
+
+        let mut vm = Vm::with_rom(&rom, Quirks::chip48()).unwrap();
+        run(&mut vm, 2).unwrap();
+        assert_eq!(vm.gpr[Reg::Vf], 0x05);
+    }
+
+    #[test]
+    fn load_store_increments_i_quirk() {
+        // 0x200: LD I, 0x300
+        // 0x202: LD [I], V2
+        let rom = [0xA3, 0x00, 0xF2, 0x55];
+
+        let mut vm = Vm::with_rom(&rom, Quirks::cosmac_vip()).unwrap();
+        run(&mut vm, 2).unwrap();
+        assert_eq!(vm.i, 0x303);
+
+        let mut vm = Vm::with_rom(&rom, Quirks::super_chip()).unwrap();
+        run(&mut vm, 2).unwrap();
+        assert_eq!(vm.i, 0x300);
+    }
+
+    #[test]
+    fn jump_uses_vx_quirk() {
+        // 0x200: LD V0, 0x10
+        // 0x202: LD V3, 0x20
+        // 0x204: JP V0, 0x300
+        let rom = [0x60, 0x10, 0x63, 0x20, 0xB3, 0x00];
+
+        let mut vm = Vm::with_rom(&rom, Quirks::cosmac_vip()).unwrap();
+        run(&mut vm, 3).unwrap();
+        assert_eq!(vm.pc, 0x310);
+
+        let mut vm = Vm::with_rom(&rom, Quirks::chip48()).unwrap();
+        run(&mut vm, 3).unwrap();
+        assert_eq!(vm.pc, 0x320);
+    }
+
+    #[test]
+    fn display_wait_quirk() {
+        // 0x200: DRW V0, V0, 1
+        let mut vm = Vm::with_rom(&[0xD0, 0x01], Quirks::cosmac_vip()).unwrap();
+        run(&mut vm, 3).unwrap();
+        assert_eq!(vm.pc, 0x200);
+
+        vm.update_timers(1);
+        run(&mut vm, 1).unwrap();
+        assert_eq!(vm.pc, 0x202);
+    }
+
+    #[test]
+    fn display_wait_traces_draw_once() {
+        // 0x200: LD I, 0x300
+        // 0x202: DRW V0, V0, 1
+        let rom = [0xA3, 0x00, 0xD0, 0x01];
+        let mut vm = Vm::with_rom(&rom, Quirks::cosmac_vip()).unwrap();
+        vm.add_watchpoint(Watchpoint {
+            range: 0x202..0x203,
+            read: false,
+            write: false,
+            execute: true,
+        });
+        vm.set_tracing(true);
+        run(&mut vm, 5).unwrap();
+        assert!(vm.is_waiting_for_vblank());
+        assert_eq!(vm.cycles(), 1);
+        assert_eq!(vm.take_trace().len(), 1);
+        assert_eq!(vm.take_watch_hits().len(), 0);
+
+        vm.update_timers(1);
+        assert!(!vm.is_waiting_for_vblank());
+        run(&mut vm, 1).unwrap();
+        assert_eq!(vm.cycles(), 2);
+        assert_eq!(vm.take_trace()[0].pc, 0x202);
+        assert_eq!(vm.take_watch_hits().len(), 1);
+    }
+
+    #[test]
+    fn exit_halts() {
+        // 0x200: EXIT
+        let mut vm = Vm::with_rom(&[0x00, 0xFD], Quirks::default()).unwrap();
+        run(&mut vm, 2).unwrap();
+        assert!(vm.is_halted());
+        assert_eq!(vm.pc, 0x200);
+    }
+
+    #[test]
+    fn store_load_flags() {
+        // 0x200: LD V0, 0x12
+        // 0x202: LD V1, 0x34
+        // 0x204: LD R, V1
+        // 0x206: LD V0, 0x00
+        // 0x208: LD V1, 0x00
+        // 0x20A: LD V1, R
+        let rom = [0x60, 0x12, 0x61, 0x34, 0xF1, 0x75, 0x60, 0x00, 0x61, 0x00, 0xF1, 0x85];
+        let mut vm = Vm::with_rom(&rom, Quirks::default()).unwrap();
+        run(&mut vm, 6).unwrap();
+        assert_eq!(vm.gpr[Reg::V0], 0x12);
+        assert_eq!(vm.gpr[Reg::V1], 0x34);
+    }
+
+    #[test]
+    fn load_big_glyph() {
+        // 0x200: LD V2, 0x03
+        // 0x202: LD HF, V2
+        let mut vm = Vm::with_rom(&[0x62, 0x03, 0xF2, 0x30], Quirks::default()).unwrap();
+        run(&mut vm, 2).unwrap();
+        assert_eq!(vm.i, BIG_FONT_MEMORY_OFFSET + 30);
+    }
+
+    #[test]
+    fn set_i_long() {
+        // 0x200: LD I, long 0x1234
+        let mut vm = Vm::with_rom(&[0xF0, 0x00, 0x12, 0x34], Quirks::xo_chip()).unwrap();
+        run(&mut vm, 1).unwrap();
+        assert_eq!(vm.i, 0x1234);
+        assert_eq!(vm.pc, 0x204);
+    }
+
+    #[test]
+    fn skip_over_set_i_long() {
+        // 0x200: SE V0, 0x00
+        // 0x202: LD I, long 0x1234
+        let rom = [0x30, 0x00, 0xF0, 0x00, 0x12, 0x34];
+        let mut vm = Vm::with_rom(&rom, Quirks::xo_chip()).unwrap();
+        run(&mut vm, 1).unwrap();
+        assert_eq!(vm.pc, 0x206);
+    }
+
+    #[test]
+    fn store_load_range() {
+        // 0x200: LD I, long 0xF000
+        // 0x204: LD V2, 0x12
+        // 0x206: LD V3, 0x34
+        // 0x208: SAVE V3 - V2
+        // 0x20A: LOAD V5 - V6
+        let rom = [
+            0xF0, 0x00, 0xF0, 0x00, 0x62, 0x12, 0x63, 0x34, 0x53, 0x22, 0x55, 0x63
+        ];
+        let mut vm = Vm::with_rom(&rom, Quirks::xo_chip()).unwrap();
+        run(&mut vm, 5).unwrap();
+        assert_eq!(&vm.memory[0xF000..0xF002], &[0x34, 0x12]);
+        assert_eq!(vm.gpr[Reg::V5], 0x34);
+        assert_eq!(vm.gpr[Reg::V6], 0x12);
+        assert_eq!(vm.i, 0xF000);
+    }
+
+    #[test]
+    fn load_audio_and_pitch() {
+        // 0x200: LD I, 0x000
+        // 0x202: AUDIO
+        // 0x204: LD V0, 112
+        // 0x206: PITCH V0
+        let rom = [0xA0, 0x00, 0xF0, 0x02, 0x60, 112, 0xF0, 0x3A];
+        let mut vm = Vm::with_rom(&rom, Quirks::xo_chip()).unwrap();
+        run(&mut vm, 4).unwrap();
+        assert_eq!(&vm.audio_pattern().unwrap()[..5], &FONT_SPRITES[..5]);
+        assert_eq!(vm.audio_sample_rate(), 8000.0);
+    }
+
+    #[test]
+    fn wait_key_until_released() {
+        // 0x200: LD V5, K
+        // 0x202: LD V6, 0x01
+        let mut vm = Vm::with_rom(&[0xF5, 0x0A, 0x66, 0x01], Quirks::default()).unwrap();
+        let mut pressed = [0; 16];
+        pressed[0xB] = 1;
+
+        vm.cycle(&mut env([0; 16])).unwrap();
+        assert!(vm.is_waiting_for_key());
+
+        // Nothing is pressed, keep waiting.
+        vm.cycle(&mut env([0; 16])).unwrap();
+        assert!(vm.is_waiting_for_key());
+
+        // Key is pressed, but not yet released.
+        vm.cycle(&mut env(pressed)).unwrap();
+        vm.cycle(&mut env(pressed)).unwrap();
+        assert!(vm.is_waiting_for_key());
+        assert_eq!(vm.gpr[Reg::V6], 0);
+
+        vm.cycle(&mut env([0; 16])).unwrap();
+        assert!(!vm.is_waiting_for_key());
+        assert_eq!(vm.gpr[Reg::V5], 0xB);
+
+        vm.cycle(&mut env([0; 16])).unwrap();
+        assert_eq!(vm.gpr[Reg::V6], 0x01);
+    }
+
+    #[test]
+    fn stack_overflow() {
+        // 0x200: CALL 0x200
+        let mut vm = Vm::with_rom(&[0x22, 0x00], Quirks::default()).unwrap();
+        match run(&mut vm, 17) {
+            Err(Error::StackOverflow { pc: 0x200 }) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn stack_underflow() {
+        // 0x200: RET
+        let mut vm = Vm::with_rom(&[0x00, 0xEE], Quirks::default()).unwrap();
+        match run(&mut vm, 1) {
+            Err(Error::StackUnderflow { pc: 0x200 }) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn store_regs_out_of_bounds() {
+        // 0x200: LD I, 0xFFE
+        // 0x202: LD [I], V3
+        let mut vm = Vm::with_rom(&[0xAF, 0xFE, 0xF3, 0x55], Quirks::default()).unwrap();
+        match run(&mut vm, 2) {
+            Err(Error::MemoryOutOfBounds {
+                    pc: 0x202,
+                    addr: 0xFFE,
+                    width: 4,
+                }) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn load_glyph_uses_low_nibble() {
+        // 0x200: LD V2, 0x1A
+        // 0x202: LD F, V2
+        let mut vm = Vm::with_rom(&[0x62, 0x1A, 0xF2, 0x29], Quirks::default()).unwrap();
+        run(&mut vm, 2).unwrap();
+        assert_eq!(vm.i, FONT_MEMORY_OFFSET + 0xA * 5);
+    }
+
+    #[test]
+    fn skip_pressed_uses_low_nibble() {
+        // 0x200: LD V3, 0xF5
+        // 0x202: SKP V3
+        let mut vm = Vm::with_rom(&[0x63, 0xF5, 0xE3, 0x9E], Quirks::default()).unwrap();
+        let mut keyboard = [0; 16];
+        keyboard[5] = 1;
+        let mut env = env(keyboard);
+        for _ in 0..2 {
+            vm.cycle(&mut env).unwrap();
+        }
+        assert_eq!(vm.pc, 0x206);
+    }
+
+    #[test]
+    fn fetch_out_of_bounds() {
+        // 0x200: JP 0xFFF
+        let mut vm = Vm::with_rom(&[0x1F, 0xFF], Quirks::default()).unwrap();
+        match run(&mut vm, 2) {
+            Err(Error::PcOutOfBounds(0xFFF)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn strict_alignment() {
+        // 0x200: JP 0x203
+        // 0x203: JP 0x203
+        let rom = [0x12, 0x03, 0x00, 0x12, 0x03];
+        let mut vm = Vm::with_rom(&rom, Quirks::default()).unwrap();
+        assert!(run(&mut vm, 4).is_ok());
+        assert_eq!(vm.pc, 0x203);
+
+        let mut vm = Vm::with_rom(&rom, Quirks::default()).unwrap();
+        vm.set_strict_alignment(true);
+        match run(&mut vm, 2) {
+            Err(Error::PcMisaligned(0x203)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn rom_too_large() {
+        assert!(Vm::with_rom(&[0; 0xE00], Quirks::default()).is_ok());
+        match Vm::with_rom(&[0; 0xE01], Quirks::default()) {
+            Err(Error::RomTooLarge { len: 0xE01, max: 0xE00 }) => {}
+            r => panic!("unexpected result: {:?}", r.map(|_| ())),
+        }
+        assert!(Vm::with_rom(&[0; 0xE01], Quirks::xo_chip()).is_ok());
+    }
+}
@@ -0,0 +1,363 @@
+use super::{Vm, KeyWait};
+use crc::crc32;
+use display::DisplayState;
+use instruction::Reg;
+use quirks::Quirks;
+use random;
+use stack::Stack;
+use Error;
+
+const MAGIC: &[u8; 4] = b"C8SS";
+const VERSION: u16 = 2;
+
+impl Vm {
+    /// Serializes complete state of the VM together with `display` contents.
+    ///
+    /// All multi-byte values are big-endian. The state starts with a header:
+    ///
+    /// | Offset | Size | Description                        |
+    /// |--------|------|------------------------------------|
+    /// | 0      | 4    | Magic, `C8SS`                      |
+    /// | 4      | 2    | Format version, currently 2        |
+    /// | 6      | 4    | Payload length                     |
+    /// | 10     | 4    | CRC-32 (IEEE 802.3) of the payload |
+    /// | 14     |      | Payload                            |
+    ///
+    /// Payload consists of the following fields:
+    ///
+    /// - quirks, `u8`, see `Quirks::to_bits`,
+    /// - `PC`, `u16`; `I`, `u16`; `DT`, `u8`; `ST`, `u8`,
+    /// - `V0`-`VF`, 16 bytes,
+    /// - stack depth, `u8`, followed by return addresses from the bottom, `u16` each,
+    /// - SUPER-CHIP RPL flags, 16 bytes,
+    /// - `Fx0A` state, `u8`: 0 if not waiting, 1 if waiting for a key press,
+    ///   2 if waiting for a key release; followed by the register, `u8`, and the key, `u8`,
+    /// - whether timers ticked since the last draw, `u8`; whether the VM is halted, `u8`,
+    /// - XO-CHIP selected planes, `u8`; pitch, `u8`; whether audio pattern is loaded, `u8`;
+    ///   audio pattern, 16 bytes,
+    /// - memory size, `u32`, followed by memory contents,
+    /// - display resolution, `u8`: 1 for 128x64, 0 for 64x32; display selected planes, `u8`;
+    ///   pixel count, `u32`, followed by pixels,
+    /// - name of the random source, `u8` length followed by ASCII characters; length of its
+    ///   state, `u8`, followed by the state.
+    ///
+    /// Version 1 has no random source, the current source of the VM is kept when loading it.
+    pub fn save_state(&self, display: &DisplayState) -> Vec<u8> {
+        let payload = self.state_payload(display);
+        let mut state = Vec::with_capacity(14 + payload.len());
+        state.extend_from_slice(MAGIC);
+        put_u16(&mut state, VERSION);
+        put_u32(&mut state, payload.len() as u32);
+        put_u32(&mut state, crc32(&payload));
+        state.extend_from_slice(&payload);
+        state
+    }
+
+    /// Returns CRC-32 of the state saved by `save_state`, to check that runs don't diverge.
+    pub fn state_hash(&self, display: &DisplayState) -> u32 {
+        crc32(&self.state_payload(display))
+    }
+
+    fn state_payload(&self, display: &DisplayState) -> Vec<u8> {
+        let mut payload = Vec::new();
+
+        payload.push(self.quirks.to_bits());
+        put_u16(&mut payload, self.pc);
+        put_u16(&mut payload, self.i);
+        payload.push(self.dt.get());
+        payload.push(self.st.get());
+        for index in 0..16 {
+            payload.push(self.gpr.read_at_index(index));
+        }
+
+        let frames = self.stack.frames();
+        payload.push(frames.len() as u8);
+        for &frame in frames {
+            put_u16(&mut payload, frame);
+        }
+
+        payload.extend_from_slice(&self.flags);
+
+        match self.wait_key {
+            None => payload.extend_from_slice(&[0, 0, 0]),
+            Some(KeyWait { vx, pressed: None }) => payload.extend_from_slice(&[1, vx.index(), 0]),
+            Some(KeyWait { vx, pressed: Some(key) }) => {
+                payload.extend_from_slice(&[2, vx.index(), key])
+            }
+        }
+        payload.push(self.vblank as u8);
+        payload.push(self.halted as u8);
+
+        payload.push(self.planes);
+        payload.push(self.pitch);
+        payload.push(self.audio_pattern.is_some() as u8);
+        payload.extend_from_slice(&self.audio_pattern.unwrap_or([0; 16]));
+
+        put_u32(&mut payload, self.memory.len() as u32);
+        payload.extend_from_slice(&self.memory);
+
+        payload.push(display.high_res as u8);
+        payload.push(display.planes);
+        put_u32(&mut payload, display.pixels.len() as u32);
+        payload.extend_from_slice(&display.pixels);
+
+        let name = self.random.name();
+        payload.push(name.len() as u8);
+        payload.extend_from_slice(name.as_bytes());
+        let random_state = self.random.state();
+        payload.push(random_state.len() as u8);
+        payload.extend_from_slice(&random_state);
+        payload
+    }
+
+    /// Restores state saved by `save_state`, returns the saved display contents.
+    ///
+    /// Watchpoints with their pending hits, tracing with its pending records and the count
+    /// of executed cycles are kept, as they belong to the host rather than to the machine.
+    /// The VM is left untouched if the state is invalid.
+    pub fn load_state(&mut self, state: &[u8]) -> ::Result<DisplayState> {
+        let mut header = Reader::new(state);
+        if header.bytes(4)? != MAGIC {
+            return Err(Error::InvalidSaveState("not a save state"));
+        }
+        let version = header.u16()?;
+        if version != 1 && version != VERSION {
+            return Err(Error::InvalidSaveState("unsupported version"));
+        }
+        let payload_len = header.u32()? as usize;
+        let checksum = header.u32()?;
+        let payload = header.bytes(payload_len)?;
+        if crc32(payload) != checksum {
+            return Err(Error::InvalidSaveState("checksum mismatch"));
+        }
+
+        let mut r = Reader::new(payload);
+        let mut vm = Vm::new(Quirks::from_bits(r.u8()?));
+        vm.pc = r.u16()?;
+        vm.i = r.u16()?;
+        vm.dt.set(r.u8()?);
+        vm.st.set(r.u8()?);
+        for index in 0..16 {
+            vm.gpr.write_at_index(index, r.u8()?);
+        }
+
+        let depth = r.u8()?;
+        vm.stack = Stack::new();
+        for _ in 0..depth {
+            vm.stack.push(r.u16()?).ok_or(
+                Error::InvalidSaveState("stack is too deep"),
+            )?;
+        }
+
+        vm.flags.copy_from_slice(r.bytes(16)?);
+
+        let (wait_state, wait_reg, wait_key) = (r.u8()?, r.u8()?, r.u8()?);
+        let vx = Reg::from_index(wait_reg & 0x0F);
+        vm.wait_key = match wait_state {
+            0 => None,
+            1 => Some(KeyWait { vx, pressed: None }),
+            2 => Some(KeyWait {
+                vx,
+                pressed: Some(wait_key & 0x0F),
+            }),
+            _ => return Err(Error::InvalidSaveState("unknown key wait state")),
+        };
+        vm.vblank = r.u8()? != 0;
+        vm.halted = r.u8()? != 0;
+
+        vm.planes = r.u8()?;
+        vm.pitch = r.u8()?;
+        let has_audio_pattern = r.u8()? != 0;
+        let mut audio_pattern = [0; 16];
+        audio_pattern.copy_from_slice(r.bytes(16)?);
+        vm.audio_pattern = if has_audio_pattern {
+            Some(audio_pattern)
+        } else {
+            None
+        };
+
+        let memory_size = r.u32()? as usize;
+        if memory_size != vm.memory.len() {
+            return Err(Error::InvalidSaveState("memory size doesn't match quirks"));
+        }
+        vm.memory.copy_from_slice(r.bytes(memory_size)?);
+
+        let high_res = r.u8()? != 0;
+        let planes = r.u8()?;
+        let pixel_count = r.u32()? as usize;
+        let display = DisplayState {
+            high_res,
+            planes,
+            pixels: r.bytes(pixel_count)?.to_vec(),
+        };
+
+        vm.random = self.random.clone();
+        if version >= 2 {
+            let name_len = r.u8()? as usize;
+            let name = r.bytes(name_len)?;
+            let state_len = r.u8()? as usize;
+            let state = r.bytes(state_len)?;
+            if name != vm.random.name().as_bytes() {
+                let name = ::core::str::from_utf8(name).unwrap_or("");
+                vm.random = random::from_name(name, 0)
+                    .ok_or(Error::InvalidSaveState("unknown random source"))?;
+            }
+            vm.random.set_state(state)?;
+        }
+
+        vm.watchpoints = ::std::mem::take(&mut self.watchpoints);
+        vm.watch_hits = ::std::mem::take(&mut self.watch_hits);
+        vm.cycles = self.cycles;
+        vm.tracing = self.tracing;
+        vm.trace = ::std::mem::take(&mut self.trace);
+        *self = vm;
+        Ok(display)
+    }
+}
+
+fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&[(value >> 8) as u8, value as u8]);
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    put_u16(buf, (value >> 16) as u16);
+    put_u16(buf, value as u16);
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data }
+    }
+
+    fn bytes(&mut self, len: usize) -> ::Result<&'a [u8]> {
+        if self.data.len() < len {
+            return Err(Error::InvalidSaveState("unexpected end of data"));
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> ::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> ::Result<u16> {
+        let bytes = self.bytes(2)?;
+        Ok((bytes[0] as u16) << 8 | bytes[1] as u16)
+    }
+
+    fn u32(&mut self) -> ::Result<u32> {
+        Ok((self.u16()? as u32) << 16 | self.u16()? as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn display() -> DisplayState {
+        DisplayState {
+            high_res: false,
+            planes: 1,
+            pixels: (0..64 * 32).map(|i| (i % 3) as u8).collect(),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        // 0x200: LD V3, 0x42
+        // 0x202: CALL 0x300
+        let mut vm = Vm::with_rom(&[0x63, 0x42, 0x23, 0x00], Quirks::cosmac_vip()).unwrap();
+        vm.pc = 0x300;
+        vm.i = 0x123;
+        vm.gpr[Reg::V3] = 0x42;
+        vm.stack.push(0x204).unwrap();
+        vm.dt.set(10);
+        vm.wait_key = Some(KeyWait {
+            vx: Reg::V7,
+            pressed: Some(0xA),
+        });
+        vm.set_random(random::from_name("vip", 0x1234).unwrap());
+        let state = vm.save_state(&display());
+
+        let mut restored = Vm::new(Quirks::default());
+        let restored_display = restored.load_state(&state).unwrap();
+
+        assert_eq!(restored_display, display());
+        assert_eq!(restored.quirks(), Quirks::cosmac_vip());
+        assert_eq!(restored.pc, 0x300);
+        assert_eq!(restored.i, 0x123);
+        assert_eq!(restored.gpr[Reg::V3], 0x42);
+        assert_eq!(restored.stack.frames(), &[0x204]);
+        assert_eq!(restored.dt.get(), 10);
+        assert_eq!(&restored.memory[0x200..0x204], &[0x63, 0x42, 0x23, 0x00]);
+        assert_eq!(restored.random().name(), "vip");
+        assert_eq!(restored.random().state(), vec![0x12, 0x34]);
+        assert_eq!(restored.save_state(&restored_display), state);
+    }
+
+    #[test]
+    fn keeps_watchpoints() {
+        use vm::Watchpoint;
+
+        let mut vm = Vm::new(Quirks::default());
+        let state = vm.save_state(&display());
+        let watchpoint = Watchpoint {
+            range: 0x300..0x310,
+            read: false,
+            write: true,
+            execute: false,
+        };
+        vm.add_watchpoint(watchpoint.clone());
+        vm.load_state(&state).unwrap();
+        assert_eq!(vm.watchpoints(), &[watchpoint]);
+    }
+
+    #[test]
+    fn keeps_tracing_and_cycles() {
+        use vm::tests::env;
+
+        // 0x200: JP 0x200
+        let mut vm = Vm::with_rom(&[0x12, 0x00], Quirks::default()).unwrap();
+        let state = vm.save_state(&display());
+        vm.set_tracing(true);
+        let mut env = env([0; 16]);
+        vm.cycle(&mut env).unwrap();
+        vm.load_state(&state).unwrap();
+        vm.cycle(&mut env).unwrap();
+
+        assert_eq!(vm.cycles(), 2);
+        let trace = vm.take_trace();
+        assert_eq!(trace.len(), 2);
+        assert_eq!(trace[1].cycle, 1);
+    }
+
+    #[test]
+    fn corrupted_state() {
+        let mut vm = Vm::new(Quirks::default());
+        let mut state = vm.save_state(&display());
+        let last = state.len() - 1;
+        state[last] ^= 0xFF;
+
+        match vm.load_state(&state) {
+            Err(Error::InvalidSaveState("checksum mismatch")) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn truncated_state() {
+        let mut vm = Vm::new(Quirks::default());
+        let state = vm.save_state(&display());
+
+        match vm.load_state(&state[..20]) {
+            Err(Error::InvalidSaveState("unexpected end of data")) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use core::ops::Range;
+
+use super::Vm;
+
+/// Kind of memory access made by an instruction.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    /// Instruction fetch.
+    Execute,
+}
+
+/// Range of memory addresses to watch for accesses of the selected kinds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watchpoint {
+    pub range: Range<usize>,
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl Watchpoint {
+    fn matches(&self, access: Access, addr: usize) -> bool {
+        let kind_matches = match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+            Access::Execute => self.execute,
+        };
+        kind_matches && self.range.start <= addr && addr < self.range.end
+    }
+}
+
+/// Access to a single byte of memory matching a watchpoint.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WatchHit {
+    pub access: Access,
+    pub addr: usize,
+    /// Value before the access.
+    pub old: u8,
+    /// Value after the access, the same as `old` unless it's a write.
+    pub new: u8,
+    /// Address of the instruction that made the access.
+    pub pc: u16,
+}
+
+impl Vm {
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    /// Removes watchpoints equal to `watchpoint`.
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) {
+        self.watchpoints.retain(|w| w != watchpoint);
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Returns accesses that hit watchpoints since the last call, in order of occurrence.
+    ///
+    /// Hits are accumulated as long as any watchpoints are set, so the host
+    /// should call this regularly, e.g. after each cycle.
+    pub fn take_watch_hits(&mut self) -> Vec<WatchHit> {
+        ::std::mem::take(&mut self.watch_hits)
+    }
+
+    /// Records reads or fetches of `range` made by the current instruction.
+    pub(super) fn watch_access(&mut self, access: Access, range: Range<usize>) {
+        if self.watchpoints.is_empty() {
+            return;
+        }
+        for addr in range {
+            if self.watchpoints.iter().any(|w| w.matches(access, addr)) {
+                let value = self.memory[addr];
+                self.watch_hits.push(WatchHit {
+                    access,
+                    addr,
+                    old: value,
+                    new: value,
+                    pc: self.pc,
+                });
+            }
+        }
+    }
+
+    /// Writes `bytes` at `addr`, recording writes that hit watchpoints.
+    ///
+    /// The range should be checked with `memory_range` beforehand.
+    pub(super) fn write_memory(&mut self, addr: usize, bytes: &[u8]) {
+        for (offset, &new) in bytes.iter().enumerate() {
+            let addr = addr + offset;
+            if self.watchpoints.iter().any(|w| w.matches(Access::Write, addr)) {
+                self.watch_hits.push(WatchHit {
+                    access: Access::Write,
+                    addr,
+                    old: self.memory[addr],
+                    new,
+                    pc: self.pc,
+                });
+            }
+            self.memory[addr] = new;
+        }
+    }
+}
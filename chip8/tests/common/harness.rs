@@ -0,0 +1,180 @@
+//! Minimal test runner for test crates built with `harness = false`.
+//!
+//! Understands a subset of the standard harness' arguments: test name filters, `--exact`,
+//! `--list` and `--test-threads N` (or `RUST_TEST_THREADS`), plus `--bless` which tests can
+//! use to rewrite their expectations. Other flags are ignored.
+
+use std::any::Any;
+use std::env;
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+/// Test failing if `run` panics.
+pub struct Test {
+    pub name: String,
+    pub run: Box<dyn Fn() + Send>,
+}
+
+impl Test {
+    pub fn new<F: Fn() + Send + 'static>(name: String, run: F) -> Test {
+        Test {
+            name,
+            run: Box::new(run),
+        }
+    }
+}
+
+pub struct Options {
+    pub bless: bool,
+    pub list: bool,
+    pub exact: bool,
+    pub test_threads: usize,
+    /// Only tests whose names contain any of the filters are run.
+    pub filters: Vec<String>,
+}
+
+impl Options {
+    pub fn from_args() -> Options {
+        let mut options = Options {
+            bless: false,
+            list: false,
+            exact: false,
+            test_threads: env::var("RUST_TEST_THREADS")
+                .ok()
+                .and_then(|threads| threads.parse().ok())
+                .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get())),
+            filters: Vec::new(),
+        };
+
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--bless" => options.bless = true,
+                "--list" => options.list = true,
+                "--exact" => options.exact = true,
+                "--test-threads" => {
+                    options.test_threads = args
+                        .next()
+                        .and_then(|threads| threads.parse().ok())
+                        .expect("--test-threads expects a number");
+                }
+                _ if arg.starts_with('-') => {}
+                _ => options.filters.push(arg),
+            }
+        }
+        options
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        self.filters.is_empty() || self.filters.iter().any(|filter| {
+            if self.exact {
+                name == filter
+            } else {
+                name.contains(filter.as_str())
+            }
+        })
+    }
+}
+
+/// Runs tests selected by `options` in parallel, prints a summary and exits
+/// with a non-zero code if any of them failed.
+pub fn run_tests(options: &Options, tests: Vec<Test>) -> ! {
+    let total = tests.len();
+    let mut tests: Vec<Test> = tests.into_iter().filter(|test| options.matches(&test.name)).collect();
+    tests.sort_by(|a, b| a.name.cmp(&b.name));
+    let filtered_out = total - tests.len();
+
+    if options.list {
+        for test in &tests {
+            println!("{}: test", test.name);
+        }
+        process::exit(0);
+    }
+
+    println!();
+    println!("running {} tests", tests.len());
+    let start = Instant::now();
+
+    // Failures are reported with the summary, not by the default panic hook.
+    panic::set_hook(Box::new(|_| {}));
+
+    let count = tests.len();
+    let queue = Arc::new(Mutex::new(tests.into_iter()));
+    let (sender, receiver) = mpsc::channel();
+    let workers: Vec<_> = (0..options.test_threads.max(1).min(count.max(1)))
+        .map(|_| {
+            let queue = queue.clone();
+            let sender = sender.clone();
+            thread::spawn(move || loop {
+                let test = match queue.lock().unwrap().next() {
+                    Some(test) => test,
+                    None => break,
+                };
+                let result = panic::catch_unwind(AssertUnwindSafe(|| (test.run)()))
+                    .map_err(panic_message);
+                sender.send((test.name, result)).unwrap();
+            })
+        })
+        .collect();
+    drop(sender);
+
+    let mut failures = Vec::new();
+    for (name, result) in receiver.iter().take(count) {
+        match result {
+            Ok(()) => println!("test {} ... ok", name),
+            Err(message) => {
+                println!("test {} ... FAILED", name);
+                failures.push((name, message));
+            }
+        }
+    }
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    let _ = panic::take_hook();
+
+    if !failures.is_empty() {
+        failures.sort();
+        println!();
+        println!("failures:");
+        for (name, message) in &failures {
+            println!();
+            println!("---- {} ----", name);
+            println!("{}", message);
+        }
+        println!();
+        println!("failures:");
+        for (name, _) in &failures {
+            println!("    {}", name);
+        }
+    }
+
+    let elapsed = start.elapsed();
+    println!();
+    println!(
+        "test result: {}. {} passed; {} failed; {} filtered out; finished in {}.{:02}s",
+        if failures.is_empty() { "ok" } else { "FAILED" },
+        count - failures.len(),
+        failures.len(),
+        filtered_out,
+        elapsed.as_secs(),
+        elapsed.subsec_nanos() / 10_000_000
+    );
+    println!();
+
+    process::exit(if failures.is_empty() { 0 } else { 1 });
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else {
+        "test panicked".to_string()
+    }
+}
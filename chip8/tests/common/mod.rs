@@ -0,0 +1,86 @@
+//! Helpers shared by the ROM test harnesses for reading, writing and comparing expectations.
+
+// Each test crate uses only some of the helpers.
+#![allow(dead_code)]
+
+use std::fs::File;
+use std::io::prelude::*;
+
+use chip8::display::Framebuffer;
+
+pub mod harness;
+
+/// Compares `framebuffer` with the expected screen stored in `filename`.
+pub fn check_screen(filename: &str, framebuffer: &Framebuffer) -> Option<String> {
+    match read_screen(filename) {
+        Ok(expected) => diff_screen(&expected, framebuffer),
+        Err(failure) => Some(failure),
+    }
+}
+
+/// Reads a screen stored in the text format of `Framebuffer`.
+pub fn read_screen(filename: &str) -> Result<Framebuffer, String> {
+    let text = read_expected(filename)?;
+    Framebuffer::from_text(&String::from_utf8_lossy(&text))
+        .ok_or_else(|| format!("{} is malformed", filename))
+}
+
+pub fn read_expected(filename: &str) -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+    File::open(filename)
+        .and_then(|mut file| file.read_to_end(&mut data))
+        .map_err(|e| {
+            format!(
+                "Can't read {}: {}\nRun the tests with `--bless` to create it.",
+                filename,
+                e
+            )
+        })?;
+    Ok(data)
+}
+
+pub fn write_expected(filename: &str, data: &[u8]) {
+    println!("Writing {}", filename);
+    let mut file = File::create(filename).unwrap();
+    file.write_all(data).unwrap();
+}
+
+/// Describes differing pixels of the screen, if any.
+///
+/// Rows of the expected and actual screens are shown side by side along with marks:
+/// `-` for pixels lit only in the expected screen, `+` for pixels lit only in the actual one
+/// and `*` for pixels with different planes lit.
+pub fn diff_screen(expected: &Framebuffer, actual: &Framebuffer) -> Option<String> {
+    if expected == actual {
+        return None;
+    }
+    if expected.is_high_res() != actual.is_high_res() {
+        return Some(format!(
+            "Screen resolution differs: expected {}x{}, got {}x{}:\n{}",
+            expected.width(),
+            expected.height(),
+            actual.width(),
+            actual.height(),
+            actual.to_text()
+        ));
+    }
+
+    let count = expected.pixels().iter().zip(actual.pixels()).filter(|&(e, a)| e != a).count();
+    let mut report = format!("Screen differs in {} pixels (expected, actual, difference):", count);
+    let expected_text = expected.to_text();
+    let actual_text = actual.to_text();
+    let text_rows = expected_text.lines().zip(actual_text.lines());
+    for ((expected_row, actual_row), (e, a)) in text_rows.zip(expected.rows().zip(actual.rows())) {
+        let marks: String = e.iter()
+            .zip(a)
+            .map(|(&e, &a)| match (e, a) {
+                (e, a) if e == a => '.',
+                (_, 0) => '-',
+                (0, _) => '+',
+                _ => '*',
+            })
+            .collect();
+        report += &format!("\n{} {} {}", expected_row, actual_row, marks);
+    }
+    Some(report)
+}
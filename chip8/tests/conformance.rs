@@ -0,0 +1,269 @@
+//! Conformance tests running CHIP-8 test ROMs and checking the screens they draw.
+//!
+//! Each `tests/conformance/*.suite` file describes a run of a ROM from `tests/conformance/roms`
+//! and the parts of its final screen to compare with `tests/conformance/expected`:
+//!
+//! ```text
+//! rom opcodes.asm         # .asm files are assembled before running
+//! quirks vip              # vip, chip48, schip or xochip, default quirks otherwise
+//! frames 100              # timer ticks to run for
+//! cycles-per-frame 16     # instructions per timer tick, 16 by default
+//! screen opcodes.screen   # expected final screen
+//! check 8XY4 0 0 8 6      # named region of the screen: x, y, width and height
+//! at 10 press 1 for 5     # keypad input, as in `chip8::script`
+//! ```
+//!
+//! Without `check` lines the whole screen is a single check. Results are printed as a table
+//! with a row per check.
+//!
+//! Run with `--bless` to store the current screens as expected, and with other arguments
+//! to run only suites whose names contain them, see `common::harness`.
+
+extern crate chip8;
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use std::process;
+
+use chip8::display::Framebuffer;
+use chip8::script::TestScript;
+use chip8::{Env, Quirks, Vm};
+
+mod common;
+
+use common::{diff_screen, read_expected, read_screen, write_expected};
+use common::harness::Options;
+
+const SUITE_DIR: &str = "tests/conformance";
+
+/// Region of the screen showing the result of a single check.
+struct Check {
+    name: String,
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+struct Suite {
+    rom_file_name: String,
+    quirks: Quirks,
+    frames: u64,
+    cycles_per_frame: u64,
+    screen_file_name: String,
+    checks: Vec<Check>,
+    input: TestScript,
+}
+
+#[derive(Clone)]
+enum Outcome {
+    Pass,
+    Fail,
+}
+
+fn parse_suite(name: &str, source: &str) -> Result<Suite, String> {
+    let mut suite = Suite {
+        rom_file_name: String::new(),
+        quirks: Quirks::default(),
+        frames: 0,
+        cycles_per_frame: 16,
+        screen_file_name: format!("{}.screen", name),
+        checks: Vec::new(),
+        input: TestScript::default(),
+    };
+    let mut input = String::new();
+
+    for (index, line) in source.lines().enumerate() {
+        let error = |message: &str| format!("line {}: {}", index + 1, message);
+        let number = |field: &str| field.parse().map_err(|_| error("invalid number"));
+
+        let code = line.split('#').next().unwrap_or("");
+        let fields: Vec<&str> = code.split_whitespace().collect();
+        match (fields.first().cloned(), fields.len()) {
+            (None, _) => {}
+            (Some("rom"), 2) => suite.rom_file_name = fields[1].to_string(),
+            (Some("quirks"), 2) => {
+                suite.quirks = Quirks::from_name(fields[1]).ok_or_else(|| error("unknown quirks"))?
+            }
+            (Some("frames"), 2) => suite.frames = number(fields[1])?,
+            (Some("cycles-per-frame"), 2) => suite.cycles_per_frame = number(fields[1])?,
+            (Some("screen"), 2) => suite.screen_file_name = fields[1].to_string(),
+            (Some("check"), 6) => {
+                suite.checks.push(Check {
+                    name: fields[1].to_string(),
+                    x: number(fields[2])? as usize,
+                    y: number(fields[3])? as usize,
+                    width: number(fields[4])? as usize,
+                    height: number(fields[5])? as usize,
+                })
+            }
+            (Some("at"), _) => {
+                input.push_str(code);
+                input.push('\n');
+            }
+            _ => return Err(error("unknown directive")),
+        }
+        // Keep line numbers of input errors matching the suite file.
+        if fields.first() != Some(&"at") {
+            input.push('\n');
+        }
+    }
+
+    if suite.rom_file_name.is_empty() {
+        return Err("missing 'rom'".to_string());
+    }
+    suite.input = TestScript::parse(&input).map_err(|e| e.to_string())?;
+    Ok(suite)
+}
+
+fn load_rom(file_name: &str) -> Result<Vec<u8>, String> {
+    if file_name.ends_with(".asm") {
+        return chip8::asm::assemble_file(file_name).map_err(|e| e.to_string());
+    }
+    let mut rom = Vec::new();
+    File::open(file_name)
+        .and_then(|mut file| file.read_to_end(&mut rom))
+        .map_err(|e| format!("Can't read {}: {}", file_name, e))?;
+    Ok(rom)
+}
+
+fn run_suite(suite: &Suite, rom: &[u8]) -> Result<Framebuffer, String> {
+    let mut vm = Vm::with_rom(rom, suite.quirks).map_err(|e| e.to_string())?;
+    let mut env = Env {
+        display: Framebuffer::new(),
+        keyboard: [0; 16],
+    };
+
+    for frame in 0..suite.frames {
+        env.keyboard = suite.input.keyboard_at(frame);
+        for _ in 0..suite.cycles_per_frame {
+            vm.cycle(&mut env)
+                .map_err(|e| format!("VM fault at frame {}: {}", frame, e))?;
+        }
+        vm.update_timers(1);
+    }
+    Ok(env.display)
+}
+
+fn check_matches(check: &Check, expected: &Framebuffer, actual: &Framebuffer) -> bool {
+    if expected.is_high_res() != actual.is_high_res() {
+        return false;
+    }
+    let x_end = (check.x + check.width).min(actual.width());
+    let y_end = (check.y + check.height).min(actual.height());
+    (check.y..y_end).all(|y| (check.x..x_end).all(|x| expected.pixel(x, y) == actual.pixel(x, y)))
+}
+
+/// Runs a suite, returning outcomes of its checks and details of failures.
+fn test_suite(suite: &Suite, bless: bool) -> (Vec<(String, Outcome)>, Option<String>) {
+    let whole_screen = [Check {
+        name: "screen".to_string(),
+        x: 0,
+        y: 0,
+        width: usize::MAX,
+        height: usize::MAX,
+    }];
+    let checks: &[Check] = if suite.checks.is_empty() {
+        &whole_screen
+    } else {
+        &suite.checks
+    };
+    let all = |outcome: Outcome| {
+        checks.iter().map(|check| (check.name.clone(), outcome.clone())).collect()
+    };
+
+    let rom_file_name = format!("{}/roms/{}", SUITE_DIR, suite.rom_file_name);
+    let actual = match load_rom(&rom_file_name).and_then(|rom| run_suite(suite, &rom)) {
+        Ok(actual) => actual,
+        Err(failure) => return (all(Outcome::Fail), Some(failure)),
+    };
+
+    let screen_file_name = format!("{}/expected/{}", SUITE_DIR, suite.screen_file_name);
+    if bless {
+        write_expected(&screen_file_name, actual.to_text().as_bytes());
+        return (all(Outcome::Pass), None);
+    }
+    let expected = match read_screen(&screen_file_name) {
+        Ok(expected) => expected,
+        Err(failure) => return (all(Outcome::Fail), Some(failure)),
+    };
+
+    let outcomes = checks
+        .iter()
+        .map(|check| {
+            let outcome = if check_matches(check, &expected, &actual) {
+                Outcome::Pass
+            } else {
+                Outcome::Fail
+            };
+            (check.name.clone(), outcome)
+        })
+        .collect();
+    (outcomes, diff_screen(&expected, &actual))
+}
+
+fn main() {
+    let options = Options::from_args();
+
+    let mut suite_names: Vec<String> = Path::new(SUITE_DIR)
+        .read_dir()
+        .expect("read_dir call failed")
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "suite"))
+        .map(|path| path.file_stem().unwrap().to_str().unwrap().to_string())
+        .filter(|name| options.matches(name))
+        .collect();
+    suite_names.sort();
+
+    let mut rows = Vec::new();
+    let mut failures = Vec::new();
+    for name in &suite_names {
+        let file_name = format!("{}/{}.suite", SUITE_DIR, name);
+        let suite = read_expected(&file_name)
+            .and_then(|source| parse_suite(name, &String::from_utf8_lossy(&source)))
+            .unwrap_or_else(|e| panic!("{}: {}", file_name, e));
+
+        let (outcomes, failure) = test_suite(&suite, options.bless);
+        let failed = outcomes.iter().any(|(_, outcome)| matches!(outcome, Outcome::Fail));
+        if failed {
+            failures.push(format!(
+                "{}: {}",
+                name,
+                failure.unwrap_or_else(|| "checks failed".to_string())
+            ));
+        }
+        for (check, outcome) in outcomes {
+            rows.push((name.clone(), check, outcome));
+        }
+    }
+
+    let suite_width = rows.iter().map(|row| row.0.len()).max().unwrap_or(0).max(5);
+    let check_width = rows.iter().map(|row| row.1.len()).max().unwrap_or(0).max(5);
+    println!("{:2$}  {:3$}  Result", "Suite", "Check", suite_width, check_width);
+    let (mut passed, mut failed) = (0, 0);
+    for (suite, check, outcome) in &rows {
+        let result = match outcome {
+            Outcome::Pass => {
+                passed += 1;
+                "ok"
+            }
+            Outcome::Fail => {
+                failed += 1;
+                "FAILED"
+            }
+        };
+        println!("{:3$}  {:4$}  {}", suite, check, result, suite_width, check_width);
+    }
+    println!();
+    println!("{} passed, {} failed", passed, failed);
+
+    if !failures.is_empty() {
+        println!();
+        for failure in &failures {
+            println!("{}\n", failure);
+        }
+        process::exit(1);
+    }
+}
@@ -0,0 +1,39 @@
+; Shared by the conformance ROMs: draws results into a grid of 8x6 cells,
+; filled row by row starting at the top left corner. VA and VB hold the position
+; of the next cell and must not be used by the checks.
+
+; Passes if V0 = V3 and V5 = V4, typically a result and its flag.
+check:
+        LD V1, 0
+        SE V0, V3
+        JP mark
+        SE V5, V4
+        JP mark
+        LD V1, 1
+
+; Draws a tick into the next cell if V1 is set, a cross otherwise.
+mark:
+        LD I, cross
+        SE V1, 0
+        LD I, tick
+        DRW VA, VB, 5
+        ADD VA, 8
+        SE VA, 64
+        RET
+        LD VA, 0
+        ADD VB, 6
+        RET
+
+tick:
+        DB 0b00000001
+        DB 0b00000010
+        DB 0b10000100
+        DB 0b01001000
+        DB 0b00110000
+
+cross:
+        DB 0b10001000
+        DB 0b01010000
+        DB 0b00100000
+        DB 0b01010000
+        DB 0b10001000
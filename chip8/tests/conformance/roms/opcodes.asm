@@ -0,0 +1,255 @@
+; Checks results and VF flags of CHIP-8 opcodes which don't depend on quirks.
+; Each check draws a tick or a cross, in the order listed in opcodes.suite.
+
+        LD VA, 0
+        LD VB, 0
+
+; 3XNN
+        LD V0, 0x42
+        LD V1, 1
+        SE V0, 0x42
+        LD V1, 0
+        CALL mark
+
+; 4XNN
+        LD V1, 1
+        SNE V0, 0x43
+        LD V1, 0
+        CALL mark
+
+; 5XY0
+        LD V2, 0x42
+        LD V1, 1
+        SE V0, V2
+        LD V1, 0
+        CALL mark
+
+; 9XY0
+        LD V2, 0x43
+        LD V1, 1
+        SNE V0, V2
+        LD V1, 0
+        CALL mark
+
+; 7XNN wraps around and leaves VF alone.
+        LD VF, 0
+        LD V0, 0xFF
+        ADD V0, 2
+        LD V5, VF
+        LD V3, 1
+        LD V4, 0
+        CALL check
+
+; 8XY0
+        LD V2, 0x37
+        LD V0, V2
+        LD V5, 0
+        LD V3, 0x37
+        LD V4, 0
+        CALL check
+
+; 8XY1
+        LD V0, 0x0F
+        LD V2, 0x3C
+        OR V0, V2
+        LD V3, 0x3F
+        CALL check
+
+; 8XY2
+        LD V0, 0x0F
+        AND V0, V2
+        LD V3, 0x0C
+        CALL check
+
+; 8XY3
+        LD V0, 0x0F
+        XOR V0, V2
+        LD V3, 0x33
+        CALL check
+
+; 8XY4 with carry.
+        LD V0, 0xF0
+        LD V2, 0x20
+        ADD V0, V2
+        LD V5, VF
+        LD V3, 0x10
+        LD V4, 1
+        CALL check
+
+; 8XY4 without carry.
+        LD V0, 0x10
+        ADD V0, V2
+        LD V5, VF
+        LD V3, 0x30
+        LD V4, 0
+        CALL check
+
+; 8XY5 without borrow.
+        LD V0, 0x30
+        LD V2, 0x10
+        SUB V0, V2
+        LD V5, VF
+        LD V3, 0x20
+        LD V4, 1
+        CALL check
+
+; 8XY5 with borrow.
+        LD V0, 0x10
+        LD V2, 0x30
+        SUB V0, V2
+        LD V5, VF
+        LD V3, 0xE0
+        LD V4, 0
+        CALL check
+
+; 8XY6, shifting a register into itself.
+        LD V0, 0x05
+        SHR V0, V0
+        LD V5, VF
+        LD V3, 0x02
+        LD V4, 1
+        CALL check
+
+; 8XY7
+        LD V0, 0x10
+        LD V2, 0x30
+        SUBN V0, V2
+        LD V5, VF
+        LD V3, 0x20
+        LD V4, 1
+        CALL check
+
+; 8XYE, shifting a register into itself.
+        LD V0, 0x81
+        SHL V0, V0
+        LD V5, VF
+        LD V3, 0x02
+        LD V4, 1
+        CALL check
+
+; 8XY4 into VF, the flag overwrites the result.
+        LD VF, 0xF0
+        LD V2, 0x20
+        ADD VF, V2
+        LD V0, VF
+        LD V5, VF
+        LD V3, 1
+        LD V4, 1
+        CALL check
+
+; 8XY5 into VF.
+        LD VF, 0x10
+        LD V2, 0x30
+        SUB VF, V2
+        LD V0, VF
+        LD V5, VF
+        LD V3, 0
+        LD V4, 0
+        CALL check
+
+; 8XY6 into VF.
+        LD VF, 0x02
+        SHR VF, VF
+        LD V0, VF
+        LD V5, VF
+        LD V3, 0
+        LD V4, 0
+        CALL check
+
+; 8XYE into VF.
+        LD VF, 0x81
+        SHL VF, VF
+        LD V0, VF
+        LD V5, VF
+        LD V3, 1
+        LD V4, 1
+        CALL check
+
+; 2NNN and 00EE
+        LD V1, 0
+        CALL set_v1
+        CALL mark
+
+; BNNN without the jump quirk.
+        LD V1, 0
+        LD V0, 2
+        JP V0, jump_base
+jump_base:
+        JP jump_done
+        LD V1, 1
+jump_done:
+        CALL mark
+
+; ANNN and FX1E
+        LD I, data
+        LD V0, 1
+        ADD I, V0
+        LD V0, [I]
+        LD V5, 0
+        LD V3, 0x22
+        LD V4, 0
+        CALL check
+
+; FX33
+        LD V0, 234
+        LD I, scratch
+        LD B, V0
+        LD I, scratch
+        LD V2, [I]
+        SE V1, 3
+        LD V0, 0xFF
+        LD V5, V2
+        LD V3, 2
+        LD V4, 4
+        CALL check
+
+; FX55 and FX65
+        LD V0, 1
+        LD V1, 2
+        LD V2, 3
+        LD I, scratch
+        LD [I], V2
+        LD V0, 0
+        LD V1, 0
+        LD V2, 0
+        LD I, scratch
+        LD V2, [I]
+        SE V1, 2
+        LD V0, 0xFF
+        LD V5, V2
+        LD V3, 1
+        LD V4, 3
+        CALL check
+
+; FX15 and FX07
+        LD V0, 0x20
+        LD DT, V0
+        LD V0, DT
+        LD V1, 1
+        SNE V0, 0
+        LD V1, 0
+        CALL mark
+
+; FX29, the first row of the glyph of A.
+        LD V0, 0xA
+        LD F, V0
+        LD V0, [I]
+        LD V5, 0
+        LD V3, 0xF0
+        LD V4, 0
+        CALL check
+
+end:
+        JP end
+
+set_v1:
+        LD V1, 1
+        RET
+
+data:
+        DB 0x11, 0x22
+
+scratch:
+        DB 0, 0, 0
+
+include "marks.asm"
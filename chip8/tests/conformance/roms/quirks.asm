@@ -0,0 +1,73 @@
+; Detects quirks of the interpreter. Each check draws a tick if the quirk is in effect
+; and a cross otherwise, in the order listed in the quirks-*.suite files.
+;
+; The code has to stay within 0x200-0x2FF for the BNNN check.
+
+        LD VA, 0
+        LD VB, 0
+
+; shift_uses_vy: 8XY6 shifts VY instead of VX.
+        LD V0, 0x01
+        LD V2, 0x04
+        SHR V0, V2
+        LD V1, 0
+        SE V0, 0x02
+        JP shift_done
+        LD V1, 1
+shift_done:
+        CALL mark
+
+; load_store_increments_i: FX65 leaves I past the loaded registers.
+        LD I, pair
+        LD V0, [I]
+        LD V0, [I]
+        LD V1, 0
+        SE V0, 0xBB
+        JP load_done
+        LD V1, 1
+load_done:
+        CALL mark
+
+; logic_resets_vf: 8XY1 resets VF.
+        LD VF, 5
+        OR V0, V0
+        LD V1, 0
+        SNE VF, 0
+        LD V1, 1
+        CALL mark
+
+; jump_uses_vx: BNNN jumps to XNN + VX, here X = 2.
+        LD V0, 0
+        LD V2, 2
+        LD V1, 0
+        JP V0, jump_base
+jump_base:
+        JP jump_done
+        LD V1, 1
+jump_done:
+        CALL mark
+
+; clip_sprites: sprites crossing the right edge are clipped instead of wrapping around.
+        LD I, line
+        LD V2, 60
+        LD V3, 31
+        DRW V2, V3, 1
+        LD V4, 0
+        DRW V4, V3, 1
+        LD V1, 1
+        SE VF, 0
+        LD V1, 0
+        DRW V4, V3, 1
+        DRW V2, V3, 1
+        CALL mark
+
+end:
+        JP end
+
+pair:
+        DB 0xAA, 0xBB
+
+line:
+        DB 0xFF
+
+include "marks.asm"
@@ -1,143 +1,159 @@
- #![feature(test)]
+//! Regression tests running ROMs and comparing their final memory and screen with
+//! `tests/expected`, and playing test scripts from `tests/scripts`.
+//!
+//! Runs with its own harness, see `common::harness` for the supported arguments.
+//! `--bless` rewrites expectation files with the current results instead of comparing.
 
 extern crate chip8;
-extern crate rand;
-extern crate test;
-
-use test::{TestDesc, TestDescAndFn, DynTestName, TestFn, Options};
 
 use std::io::prelude::*;
 use std::fs::File;
-use std::rc::Rc;
-use std::cell::RefCell;
 use std::path::Path;
-use std::env;
 
-use chip8::{Vm, Env};
-use rand::{SeedableRng, StdRng};
+use chip8::{Vm, Env, Quirks};
+use chip8::display::Framebuffer;
+use chip8::script::TestScript;
 
-const DISPLAY_WIDTH: usize = 64;
-const DISPLAY_HEIGHT: usize = 32;
+mod common;
 
-pub struct RenderBufDisplay {
-    mem: Rc<RefCell<[bool; DISPLAY_WIDTH * DISPLAY_HEIGHT]>>,
-}
+use common::{check_screen, read_expected, write_expected};
+use common::harness::{self, Options, Test};
 
-impl RenderBufDisplay {
-    fn new(mem: Rc<RefCell<[bool; DISPLAY_WIDTH * DISPLAY_HEIGHT]>>) -> RenderBufDisplay {
-        RenderBufDisplay { mem }
-    }
-}
+fn main() {
+    let options = Options::from_args();
+    let bless = options.bless;
 
-impl chip8::display::Display for RenderBufDisplay {
-    fn clear(&mut self) {
-        for i in self.mem.borrow_mut().iter_mut() {
-            *i = false;
-        }
-    }
+    let mut tests = Vec::new();
 
-    fn draw(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
-        let mut collision_bit = false;
-        let mut mem = self.mem.borrow_mut();
-
-        for (sy, byte) in sprite.iter().enumerate() {
-            let dy = (y + sy) % DISPLAY_HEIGHT;
-            for sx in 0..8 {
-                let bit_mask = 0b1000_0000 >> sx;
-                if (byte & bit_mask) != 0 {
-                    let dx = (x + sx) % DISPLAY_WIDTH;
-                    let index = dy * DISPLAY_WIDTH + dx;
-
-                    if mem[index] {
-                        collision_bit = true;
-                    }
-                    mem[index] ^= true;
-                }
-            }
+    let path = Path::new("tests/roms");
+    for entry in path.read_dir().expect("read_dir call failed").flatten() {
+        if entry.metadata().unwrap().is_dir() {
+            continue;
         }
 
-        collision_bit
+        let filename = entry.file_name().into_string().unwrap();
+        tests.push(Test::new(filename.clone(), move || test_rom_snapshot(&filename, bless)));
     }
-}
-
-#[test]
-fn main() {
-    let args: Vec<_> = env::args().collect();
-    let src_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
-    println!("{:?}", src_dir);
-
-    let mut tests: Vec<TestDescAndFn> = Vec::new();
 
-    let path = Path::new("tests/roms");
-    for entry in path.read_dir().expect("read_dir call failed") {
-        if let Ok(entry) = entry {
-            if entry.metadata().unwrap().is_dir() {
-                continue;
-            }
-
-            let filename = entry.file_name().into_string().unwrap();
-
-            let test_desc_and_fn = TestDescAndFn {
-                desc: TestDesc {
-                    name: DynTestName(filename.clone()),
-                    ignore: false,
-                    should_panic: test::ShouldPanic::No,
-                    allow_fail: false,
-                },
-                testfn: TestFn::DynTestFn(Box::new(move |_| { test_rom_snapshot(&filename); })),
-            };
-            tests.push(test_desc_and_fn);
+    // Scripts play ROMs of the same name, see `chip8::script` for the format.
+    let path = Path::new("tests/scripts");
+    for entry in path.read_dir().expect("read_dir call failed").flatten() {
+        let path = entry.path();
+        if path.extension().is_none_or(|extension| extension != "script") {
+            continue;
         }
+
+        let rom_name = path.file_stem().unwrap().to_str().unwrap().to_string();
+        let name = format!("script/{}", rom_name);
+        tests.push(Test::new(name, move || test_rom_script(&rom_name, bless)));
     }
 
-    test::test_main(&args, tests, Options::new());
+    harness::run_tests(&options, tests);
 }
 
-fn test_rom_snapshot(rom_name: &str) {
-    let mem = Rc::new(RefCell::new([false; DISPLAY_WIDTH * DISPLAY_HEIGHT]));
-
+fn load_rom(rom_name: &str) -> Vm {
     let rom_filename = format!("tests/roms/{}", rom_name);
-    println!("{}", rom_filename);
     let mut rom_file = File::open(rom_filename).expect("rom file should exists");
     let mut buf = Vec::new();
     rom_file.read_to_end(&mut buf).unwrap();
-    let mut vm = Vm::with_rom(&buf);
+    Vm::with_rom(&buf, Quirks::default()).unwrap()
+}
 
-    let seed: &[_] = &[2, 2, 8, 1];
-    let rng: StdRng = SeedableRng::from_seed(seed);
+fn run_frame(vm: &mut Vm, framebuffer: &mut Framebuffer, keyboard: [u8; 16]) {
+    for _ in 0..4 {
+        vm.cycle(&mut Env {
+            keyboard,
+            display: &mut *framebuffer,
+        }).unwrap();
+    }
+    vm.update_timers(1);
+}
+
+fn test_rom_snapshot(rom_name: &str, bless: bool) {
+    let mut framebuffer = Framebuffer::new();
+    let mut vm = load_rom(rom_name);
 
     for _ in 0..10000 {
-        for _ in 0..4 {
-            let display = RenderBufDisplay::new(mem.clone());
-            let keyboard = [0u8; 16];
-            vm.cycle(&mut Env {
-                keyboard: keyboard,
-                display: display,
-                rng: rng,
-            }).unwrap();
-        }
-        vm.update_timers(1);
+        run_frame(&mut vm, &mut framebuffer, [0; 16]);
     }
 
     let final_mem = vm.memory.to_vec();
+    let final_screen = framebuffer.to_text();
 
     let expected_mem_filename = format!("tests/expected/{}.mem", rom_name);
-    let expected_mem: Vec<u8> = {
-        match File::open(&expected_mem_filename) {
-            Ok(mut file) => {
-                let mut expected_mem = Vec::new();
-                file.read_to_end(&mut expected_mem).unwrap();
-                expected_mem
-            }
-            Err(e) => {
-                // Assume file is not found.
-                println!("Can't open file {}, creating new", e);
-                let mut new_expected_file = File::create(&expected_mem_filename).unwrap();
-                new_expected_file.write_all(&final_mem).unwrap();
-                return;
-            }
+    let expected_screen_filename = format!("tests/expected/{}.screen", rom_name);
+    if bless {
+        write_expected(&expected_mem_filename, &final_mem);
+        write_expected(&expected_screen_filename, final_screen.as_bytes());
+        return;
+    }
+
+    let mut failures = Vec::new();
+    match read_expected(&expected_mem_filename) {
+        Ok(expected_mem) => failures.extend(diff_memory(&expected_mem, &final_mem)),
+        Err(failure) => failures.push(failure),
+    }
+    failures.extend(check_screen(&expected_screen_filename, &framebuffer));
+
+    if !failures.is_empty() {
+        panic!("{} doesn't match expectations:\n\n{}", rom_name, failures.join("\n\n"));
+    }
+}
+
+fn test_rom_script(rom_name: &str, bless: bool) {
+    let script_filename = format!("tests/scripts/{}.script", rom_name);
+    let source = read_expected(&script_filename).unwrap_or_else(|failure| panic!("{}", failure));
+    let script = TestScript::parse(&String::from_utf8_lossy(&source))
+        .unwrap_or_else(|e| panic!("{}: {}", script_filename, e));
+
+    let mut framebuffer = Framebuffer::new();
+    let mut vm = load_rom(rom_name);
+
+    let mut failures = Vec::new();
+    let mut frame = 0;
+    for checkpoint in script.checkpoints() {
+        while frame < checkpoint.frame {
+            run_frame(&mut vm, &mut framebuffer, script.keyboard_at(frame));
+            frame += 1;
         }
-    };
 
-    assert_eq!(final_mem, expected_mem);
+        let screen_filename = format!("tests/scripts/{}", checkpoint.screen_file_name);
+        if bless {
+            write_expected(&screen_filename, framebuffer.to_text().as_bytes());
+        } else if let Some(failure) = check_screen(&screen_filename, &framebuffer) {
+            failures.push(format!("At frame {}: {}", frame, failure));
+        }
+    }
+
+    if !failures.is_empty() {
+        panic!("{} doesn't match expectations:\n\n{}", script_filename, failures.join("\n\n"));
+    }
+}
+
+/// Describes differing bytes of memory, if any.
+fn diff_memory(expected: &[u8], actual: &[u8]) -> Option<String> {
+    const MAX_LISTED: usize = 16;
+
+    if expected.len() != actual.len() {
+        return Some(format!(
+            "Memory size differs: expected {} bytes, got {}",
+            expected.len(),
+            actual.len()
+        ));
+    }
+    let differences: Vec<usize> = (0..actual.len())
+        .filter(|&addr| expected[addr] != actual[addr])
+        .collect();
+    if differences.is_empty() {
+        return None;
+    }
+
+    let mut report = format!("Memory differs in {} bytes:", differences.len());
+    for &addr in differences.iter().take(MAX_LISTED) {
+        report += &format!("\n  {:04X}: expected {:02X}, got {:02X}", addr, expected[addr], actual[addr]);
+    }
+    if differences.len() > MAX_LISTED {
+        report += "\n  ...";
+    }
+    Some(report)
 }
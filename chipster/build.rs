@@ -0,0 +1,8 @@
+use std::env;
+
+fn main() {
+    // Emscripten builds link SDL2 from its ports instead of a system library.
+    if env::var("CARGO_CFG_TARGET_OS").map(|os| os == "emscripten").unwrap_or(false) {
+        println!("cargo:rustc-link-arg=-sUSE_SDL=2");
+    }
+}
@@ -1,30 +1,37 @@
 use sdl2::audio::{AudioCallback, AudioSpecDesired, AudioDevice};
 use sdl2::AudioSubsystem;
 
-struct SquareWave {
-    phase_inc: f32,
+struct Tone {
+    freq: f32,
     phase: f32,
     volume: f32,
+    /// XO-CHIP audio pattern and its playback rate in bits per second.
+    /// Plain square wave is played if there is no pattern.
+    pattern: Option<([u8; 16], f32)>,
 }
 
-impl AudioCallback for SquareWave {
+impl AudioCallback for Tone {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
         for x in out.iter_mut() {
-            *x = if self.phase >= 0.0 && self.phase <= 0.5 {
-                self.volume
-            } else {
-                -self.volume
+            let (high, phase_inc) = match self.pattern {
+                Some((ref pattern, rate)) => {
+                    let bit = (self.phase * 128.0) as usize % 128;
+                    let high = pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+                    (high, rate / 128.0 / self.freq)
+                }
+                None => (self.phase >= 0.0 && self.phase <= 0.5, 440.0 / self.freq),
             };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+            *x = if high { self.volume } else { -self.volume };
+            self.phase = (self.phase + phase_inc) % 1.0;
         }
     }
 }
 
 pub struct Beeper {
     beeping: bool,
-    device: AudioDevice<SquareWave>,
+    device: AudioDevice<Tone>,
 }
 
 impl Beeper {
@@ -36,10 +43,11 @@ impl Beeper {
         };
 
         let device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
-            SquareWave {
-                phase_inc: 440.0 / spec.freq as f32,
+            Tone {
+                freq: spec.freq as f32,
                 phase: 0.0,
                 volume: 0.25,
+                pattern: None,
             }
         })?;
 
@@ -60,4 +68,10 @@ impl Beeper {
         }
         Ok(())
     }
+
+    /// Sets XO-CHIP audio pattern to be played instead of the square wave.
+    pub fn set_pattern(&mut self, pattern: Option<[u8; 16]>, rate: f32) {
+        let mut tone = self.device.lock();
+        tone.pattern = pattern.map(|pattern| (pattern, rate));
+    }
 }
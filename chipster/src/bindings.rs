@@ -0,0 +1,450 @@
+//! Host keys bound to CHIP-8 keys and to frontend actions.
+//!
+//! Keys are named as in SDL, e.g. `Q`, `Left` or `Keypad 8`. Action keys may be prefixed with
+//! `Shift+`, `Ctrl+` and `Alt+`, then the action only runs with exactly those modifiers held.
+
+use sdl2::keyboard::{self, Keycode, Mod};
+use toml::Value;
+use toml::value::Table;
+
+use Result;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Action {
+    Quit,
+    /// Toggles pause.
+    Pause,
+    /// Restarts the ROM.
+    Reset,
+    /// Runs backwards while held.
+    Rewind,
+    /// Runs faster while held.
+    FastForward,
+    /// Writes the screen into a PNG file next to the ROM.
+    Screenshot,
+    /// Breaks into the debugger or continues.
+    Debugger,
+    /// Runs to the next instruction in the paused debugger, over subroutine calls.
+    StepOver,
+    /// Runs one instruction in the paused debugger.
+    StepInto,
+    /// Runs until the current subroutine returns in the paused debugger.
+    StepOut,
+    /// Toggles a breakpoint at PC in the paused debugger.
+    ToggleBreakpoint,
+    SaveState(u8),
+    LoadState(u8),
+}
+
+impl Action {
+    fn from_name(name: &str) -> Option<Action> {
+        let slot = |prefix: &str| {
+            name.strip_prefix(prefix)
+                .and_then(|slot| slot.parse::<u8>().ok())
+                .filter(|slot| (1..=9).contains(slot))
+        };
+        match name {
+            "quit" => Some(Action::Quit),
+            "pause" => Some(Action::Pause),
+            "reset" => Some(Action::Reset),
+            "rewind" => Some(Action::Rewind),
+            "fast-forward" => Some(Action::FastForward),
+            "screenshot" => Some(Action::Screenshot),
+            "debugger" => Some(Action::Debugger),
+            "step-over" => Some(Action::StepOver),
+            "step-into" => Some(Action::StepInto),
+            "step-out" => Some(Action::StepOut),
+            "toggle-breakpoint" => Some(Action::ToggleBreakpoint),
+            _ => {
+                slot("save-state-")
+                    .map(Action::SaveState)
+                    .or_else(|| slot("load-state-").map(Action::LoadState))
+            }
+        }
+    }
+
+    /// Returns `true` for actions which last while the key is held.
+    pub fn is_held(self) -> bool {
+        matches!(self, Action::Rewind | Action::FastForward)
+    }
+
+    /// Returns `true` for actions which only run while the debugger is paused. Their keys act
+    /// as CHIP-8 keys otherwise.
+    pub fn is_debugger_command(self) -> bool {
+        matches!(
+            self,
+            Action::StepOver | Action::StepInto | Action::StepOut | Action::ToggleBreakpoint
+        )
+    }
+}
+
+/// Key with the modifiers that have to be held with it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct KeyCombo {
+    keycode: Keycode,
+    shift: bool,
+    ctrl: bool,
+    alt: bool,
+}
+
+impl KeyCombo {
+    fn parse(mut name: &str) -> Result<KeyCombo> {
+        let (mut shift, mut ctrl, mut alt) = (false, false, false);
+        loop {
+            if let Some(rest) = name.strip_prefix("Shift+") {
+                shift = true;
+                name = rest;
+            } else if let Some(rest) = name.strip_prefix("Ctrl+") {
+                ctrl = true;
+                name = rest;
+            } else if let Some(rest) = name.strip_prefix("Alt+") {
+                alt = true;
+                name = rest;
+            } else {
+                break;
+            }
+        }
+        Ok(KeyCombo {
+            keycode: parse_keycode(name)?,
+            shift,
+            ctrl,
+            alt,
+        })
+    }
+
+    /// Returns the name of the combo as accepted by `parse`.
+    pub fn name(&self) -> String {
+        let mut name = String::new();
+        if self.shift {
+            name.push_str("Shift+");
+        }
+        if self.ctrl {
+            name.push_str("Ctrl+");
+        }
+        if self.alt {
+            name.push_str("Alt+");
+        }
+        name.push_str(&self.keycode.name());
+        name
+    }
+
+    fn matches(&self, keycode: Keycode, keymod: Mod) -> bool {
+        self.keycode == keycode &&
+            self.shift == keymod.intersects(keyboard::LSHIFTMOD | keyboard::RSHIFTMOD) &&
+            self.ctrl == keymod.intersects(keyboard::LCTRLMOD | keyboard::RCTRLMOD) &&
+            self.alt == keymod.intersects(keyboard::LALTMOD | keyboard::RALTMOD)
+    }
+}
+
+/// Changes to the default bindings, from the config or the command line.
+///
+/// Every CHIP-8 key and action listed gets exactly the given keys, which are taken away from
+/// the defaults and from earlier entries.
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    pub keys: Vec<(usize, Vec<Keycode>)>,
+    pub actions: Vec<(Action, Vec<KeyCombo>)>,
+}
+
+impl Overrides {
+    /// Parses a table of CHIP-8 keys in hex to a key name or a list of them.
+    pub fn parse_keys(&mut self, table: &Table) -> Result<()> {
+        for (key, value) in table {
+            let key = parse_chip8_key(key)?;
+            let keycodes = names(value)?
+                .into_iter()
+                .map(parse_keycode)
+                .collect::<Result<Vec<_>>>()?;
+            self.keys.push((key, keycodes));
+        }
+        Ok(())
+    }
+
+    /// Parses a table of action names to a key name or a list of them.
+    pub fn parse_actions(&mut self, table: &Table) -> Result<()> {
+        for (action, value) in table {
+            let action = Action::from_name(action)
+                .ok_or_else(|| format!("unknown action '{}'", action))?;
+            let combos = names(value)?
+                .into_iter()
+                .map(KeyCombo::parse)
+                .collect::<Result<Vec<_>>>()?;
+            self.actions.push((action, combos));
+        }
+        Ok(())
+    }
+
+    /// Parses a command line binding, `TARGET=KEY` where the target is a CHIP-8 key in hex
+    /// or an action. Bindings of the same target are collected.
+    pub fn parse_binding(&mut self, binding: &str) -> Result<()> {
+        let mut parts = binding.splitn(2, '=');
+        let (target, name) = match (parts.next(), parts.next()) {
+            (Some(target), Some(name)) => (target, name),
+            _ => bail!("binding '{}' should be TARGET=KEY", binding),
+        };
+        if let Some(action) = Action::from_name(target) {
+            let combo = KeyCombo::parse(name)?;
+            match self.actions.iter_mut().find(|&&mut (other, _)| other == action) {
+                Some(&mut (_, ref mut combos)) => combos.push(combo),
+                None => self.actions.push((action, vec![combo])),
+            }
+        } else {
+            let key = parse_chip8_key(target)
+                .map_err(|_| format!("'{}' is neither a CHIP-8 key nor an action", target))?;
+            let keycode = parse_keycode(name)?;
+            match self.keys.iter_mut().find(|&&mut (other, _)| other == key) {
+                Some(&mut (_, ref mut keycodes)) => keycodes.push(keycode),
+                None => self.keys.push((key, vec![keycode])),
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns these overrides with the targets of `other` replaced.
+    pub fn merge(&self, other: &Overrides) -> Overrides {
+        let mut keys = self.keys.clone();
+        keys.retain(|&(key, _)| other.keys.iter().all(|&(other, _)| other != key));
+        keys.extend(other.keys.iter().cloned());
+        let mut actions = self.actions.clone();
+        actions.retain(|&(action, _)| other.actions.iter().all(|&(other, _)| other != action));
+        actions.extend(other.actions.iter().cloned());
+        Overrides { keys, actions }
+    }
+}
+
+pub struct Bindings {
+    keys: Vec<(Keycode, usize)>,
+    actions: Vec<(KeyCombo, Action)>,
+}
+
+impl Bindings {
+    /// Creates the default bindings with `overrides` applied.
+    ///
+    /// A host key or combo bound by an override no longer triggers what it was bound to
+    /// before, so later overrides take precedence over earlier ones and over the defaults.
+    pub fn new(overrides: &Overrides) -> Bindings {
+        let mut keys: Vec<(Keycode, usize)> = DEFAULT_KEYS
+            .iter()
+            .cloned()
+            .filter(|&(_, key)| overrides.keys.iter().all(|&(other, _)| other != key))
+            .collect();
+        for &(key, ref keycodes) in &overrides.keys {
+            keys.retain(|&(other, _)| !keycodes.contains(&other));
+            keys.extend(keycodes.iter().map(|&keycode| (keycode, key)));
+        }
+
+        let mut actions: Vec<(KeyCombo, Action)> = default_actions()
+            .into_iter()
+            .filter(|&(_, action)| overrides.actions.iter().all(|&(other, _)| other != action))
+            .collect();
+        for &(action, ref combos) in &overrides.actions {
+            actions.retain(|&(other, _)| !combos.contains(&other));
+            actions.extend(combos.iter().map(|&combo| (combo, action)));
+        }
+
+        Bindings { keys, actions }
+    }
+
+    /// Returns the CHIP-8 key bound to `keycode`.
+    pub fn key(&self, keycode: Keycode) -> Option<usize> {
+        self.keys
+            .iter()
+            .find(|&&(other, _)| other == keycode)
+            .map(|&(_, key)| key)
+    }
+
+    /// Returns the action bound to `keycode` pressed with `keymod`.
+    pub fn action(&self, keycode: Keycode, keymod: Mod) -> Option<Action> {
+        self.actions
+            .iter()
+            .find(|&&(combo, _)| combo.matches(keycode, keymod))
+            .map(|&(_, action)| action)
+    }
+
+    /// Returns the first combo bound to `action`.
+    pub fn combo(&self, action: Action) -> Option<KeyCombo> {
+        self.actions
+            .iter()
+            .find(|&&(_, other)| other == action)
+            .map(|&(combo, _)| combo)
+    }
+
+    /// Returns held actions bound to `keycode` with any modifiers, which end when it's released.
+    pub fn held_actions(&self, keycode: Keycode) -> Vec<Action> {
+        self.actions
+            .iter()
+            .filter(|&&(combo, action)| combo.keycode == keycode && action.is_held())
+            .map(|&(_, action)| action)
+            .collect()
+    }
+}
+
+// Classical layout, see http://devernay.free.fr/hacks/chip8/C8TECH10.HTM#2.3
+// +---+---+---+---+
+// | 1 | 2 | 3 | C |
+// +---+---+---+---+
+// | 4 | 5 | 6 | D |
+// +---+---+---+---+
+// | 7 | 8 | 9 | E |
+// +---+---+---+---+
+// | A | 0 | B | F |
+// +---+---+---+---+
+#[rustfmt::skip]
+const DEFAULT_KEYS: [(Keycode, usize); 16] = [
+    (Keycode::Num1, 0x1), (Keycode::Num2, 0x2), (Keycode::Num3, 0x3), (Keycode::Num4, 0xC),
+    (Keycode::Q, 0x4), (Keycode::W, 0x5), (Keycode::E, 0x6), (Keycode::R, 0xD),
+    (Keycode::A, 0x7), (Keycode::S, 0x8), (Keycode::D, 0x9), (Keycode::F, 0xE),
+    (Keycode::Z, 0xA), (Keycode::X, 0x0), (Keycode::C, 0xB), (Keycode::V, 0xF),
+];
+
+/// Returns the default action bindings. F1-F9 load states from slots, Shift + F1-F9 save them.
+fn default_actions() -> Vec<(KeyCombo, Action)> {
+    let key = |keycode| KeyCombo {
+        keycode,
+        shift: false,
+        ctrl: false,
+        alt: false,
+    };
+    let mut actions = vec![
+        (key(Keycode::Escape), Action::Quit),
+        (key(Keycode::Space), Action::Pause),
+        (KeyCombo { ctrl: true, ..key(Keycode::R) }, Action::Reset),
+        (key(Keycode::Backspace), Action::Rewind),
+        (key(Keycode::Backquote), Action::FastForward),
+        (key(Keycode::PrintScreen), Action::Screenshot),
+        (KeyCombo { ctrl: true, ..key(Keycode::P) }, Action::Screenshot),
+        (key(Keycode::Tab), Action::Debugger),
+        (key(Keycode::F10), Action::StepOver),
+        (key(Keycode::F11), Action::StepInto),
+        (key(Keycode::F12), Action::StepOut),
+        (key(Keycode::B), Action::ToggleBreakpoint),
+    ];
+    let slot_keys = [
+        Keycode::F1,
+        Keycode::F2,
+        Keycode::F3,
+        Keycode::F4,
+        Keycode::F5,
+        Keycode::F6,
+        Keycode::F7,
+        Keycode::F8,
+        Keycode::F9,
+    ];
+    for (index, &keycode) in slot_keys.iter().enumerate() {
+        let slot = index as u8 + 1;
+        actions.push((key(keycode), Action::LoadState(slot)));
+        actions.push((KeyCombo { shift: true, ..key(keycode) }, Action::SaveState(slot)));
+    }
+    actions
+}
+
+fn parse_chip8_key(key: &str) -> Result<usize> {
+    match usize::from_str_radix(key, 16) {
+        Ok(key) if key < 16 => Ok(key),
+        _ => bail!("'{}' isn't a CHIP-8 key", key),
+    }
+}
+
+fn parse_keycode(name: &str) -> Result<Keycode> {
+    Keycode::from_name(name).ok_or_else(|| format!("unknown key '{}'", name).into())
+}
+
+/// Returns a key name or a list of them.
+fn names(value: &Value) -> Result<Vec<&str>> {
+    match *value {
+        Value::String(ref name) => Ok(vec![name.as_str()]),
+        Value::Array(ref names) => {
+            names
+                .iter()
+                .map(|name| name.as_str().ok_or_else(|| "key names should be strings".into()))
+                .collect()
+        }
+        _ => bail!("keys should be a name or a list of names"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overrides(bindings: &[&str]) -> Overrides {
+        let mut overrides = Overrides::default();
+        for binding in bindings {
+            overrides.parse_binding(binding).unwrap();
+        }
+        overrides
+    }
+
+    #[test]
+    fn defaults() {
+        let bindings = Bindings::new(&Overrides::default());
+        assert_eq!(bindings.key(Keycode::Q), Some(0x4));
+        assert_eq!(bindings.key(Keycode::V), Some(0xF));
+        assert_eq!(bindings.key(Keycode::Up), None);
+        assert_eq!(bindings.action(Keycode::F2, keyboard::NOMOD), Some(Action::LoadState(2)));
+        assert_eq!(bindings.action(Keycode::F2, keyboard::LSHIFTMOD), Some(Action::SaveState(2)));
+        assert_eq!(bindings.action(Keycode::R, keyboard::NOMOD), None);
+        assert_eq!(bindings.key(Keycode::R), Some(0xD));
+        assert_eq!(bindings.action(Keycode::F10, keyboard::NOMOD), Some(Action::StepOver));
+    }
+
+    #[test]
+    fn overrides_take_keys_from_defaults() {
+        let bindings = Bindings::new(&overrides(&["5=Q"]));
+        assert_eq!(bindings.key(Keycode::Q), Some(0x5));
+        // Key 5 loses its default key W, and key 4 is left without a key.
+        assert_eq!(bindings.key(Keycode::W), None);
+        assert_eq!(bindings.key(Keycode::E), Some(0x6));
+
+        let bindings = Bindings::new(&overrides(&["quit=Backspace"]));
+        assert_eq!(bindings.action(Keycode::Backspace, keyboard::NOMOD), Some(Action::Quit));
+        assert!(bindings.held_actions(Keycode::Backspace).is_empty());
+        assert_eq!(bindings.action(Keycode::Escape, keyboard::NOMOD), None);
+    }
+
+    #[test]
+    fn finds_combos_of_actions() {
+        let bindings = Bindings::new(&overrides(&["step-into=Ctrl+S", "step-into=I"]));
+        let combo = bindings.combo(Action::StepInto).unwrap();
+        assert_eq!(combo, KeyCombo::parse("Ctrl+S").unwrap());
+        assert_eq!(bindings.combo(Action::ToggleBreakpoint), Some(KeyCombo::parse("B").unwrap()));
+
+        let bindings = Bindings::new(&overrides(&["pause=B"]));
+        assert_eq!(bindings.combo(Action::ToggleBreakpoint), None);
+    }
+
+    #[test]
+    fn later_overrides_take_precedence() {
+        let bundled = overrides(&["4=Q", "4=Left"]);
+        let bindings = Bindings::new(&bundled.merge(&overrides(&["6=Left"])));
+        assert_eq!(bindings.key(Keycode::Left), Some(0x6));
+        assert_eq!(bindings.key(Keycode::Q), Some(0x4));
+        assert_eq!(bindings.key(Keycode::E), None);
+    }
+
+    #[test]
+    fn binds_several_keys() {
+        let overrides = overrides(&["pause=P", "pause=Ctrl+Space", "a=Up", "a=Down"]);
+        assert_eq!(overrides.actions.len(), 1);
+        assert_eq!(overrides.keys.len(), 1);
+
+        let bindings = Bindings::new(&overrides);
+        assert_eq!(bindings.action(Keycode::P, keyboard::NOMOD), Some(Action::Pause));
+        assert_eq!(bindings.action(Keycode::Space, keyboard::LCTRLMOD), Some(Action::Pause));
+        assert_eq!(bindings.action(Keycode::Space, keyboard::NOMOD), None);
+        assert_eq!(bindings.key(Keycode::Up), Some(0xA));
+        assert_eq!(bindings.key(Keycode::Down), Some(0xA));
+        assert_eq!(bindings.key(Keycode::Z), None);
+    }
+
+    #[test]
+    fn rejects_invalid_bindings() {
+        let mut overrides = Overrides::default();
+        assert!(overrides.parse_binding("Q").is_err());
+        assert!(overrides.parse_binding("10=Q").is_err());
+        assert!(overrides.parse_binding("jump=Q").is_err());
+        assert!(overrides.parse_binding("save-state-0=Q").is_err());
+        assert!(overrides.parse_binding("5=NoSuchKey").is_err());
+        assert!(overrides.keys.is_empty() && overrides.actions.is_empty());
+    }
+}
@@ -0,0 +1,389 @@
+//! Settings of ROMs from the database bundled with chipster and from the user config file.
+//!
+//! Both are TOML with a `[roms.CRC]` section per ROM, keyed by CRC-32 of the ROM file.
+//! See `roms.toml` for the fields. The user config may also have `[keys]` and `[actions]`
+//! sections of the same form as ROM `keys` and `actions`, which apply to all ROMs.
+
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+
+use chip8::Quirks;
+use chip8::crc::crc32;
+use chip8::display::{Palette, DEFAULT_PALETTE};
+use toml::Value;
+use toml::value::Table;
+
+use bindings::Overrides;
+use {read_file, Result};
+
+#[derive(Debug, Clone, Default)]
+pub struct RomSettings {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    /// Quirks of the platform the ROM was written for.
+    pub platform: Option<Quirks>,
+    /// Quirks differing from the platform, by field name of `Quirks`.
+    pub quirks: Vec<(String, bool)>,
+    /// Instructions per frame.
+    pub tickrate: Option<u32>,
+    /// Keys of CHIP-8 keys and actions, replacing their default keys.
+    pub bindings: Overrides,
+    pub palette: Option<Palette>,
+}
+
+impl RomSettings {
+    fn parse(section: &Table) -> Result<RomSettings> {
+        let mut settings = RomSettings::default();
+        for (field, value) in section {
+            match field.as_str() {
+                "title" => settings.title = Some(string(field, value)?.to_string()),
+                "author" => settings.author = Some(string(field, value)?.to_string()),
+                "platform" => {
+                    let name = string(field, value)?;
+                    settings.platform = Some(Quirks::from_name(name).ok_or_else(|| {
+                        format!("unknown platform '{}'", name)
+                    })?);
+                }
+                "quirks" => {
+                    for (field, value) in table(field, value)? {
+                        let set = value.as_bool().ok_or_else(|| {
+                            format!("quirk '{}' should be true or false", field)
+                        })?;
+                        if !set_quirk(&mut Quirks::default(), field, set) {
+                            bail!("unknown quirk '{}'", field);
+                        }
+                        settings.quirks.push((field.clone(), set));
+                    }
+                }
+                "tickrate" => {
+                    settings.tickrate = match value.as_integer() {
+                        Some(tickrate) if tickrate > 0 && tickrate <= 1_000_000 => {
+                            Some(tickrate as u32)
+                        }
+                        _ => bail!("tickrate should be a positive integer"),
+                    }
+                }
+                "keys" => settings.bindings.parse_keys(table(field, value)?)?,
+                "actions" => settings.bindings.parse_actions(table(field, value)?)?,
+                "colors" => settings.palette = Some(parse_palette(value)?),
+                _ => bail!("unknown field '{}'", field),
+            }
+        }
+
+        Ok(settings)
+    }
+
+    /// Returns quirks of the platform with the quirks of these settings applied, or `None` if
+    /// neither is set.
+    pub fn quirks(&self) -> Option<Quirks> {
+        if self.platform.is_none() && self.quirks.is_empty() {
+            return None;
+        }
+        let mut quirks = self.platform.unwrap_or_default();
+        for (field, set) in &self.quirks {
+            set_quirk(&mut quirks, field, *set);
+        }
+        Some(quirks)
+    }
+
+    /// Returns these settings with fields set in `overrides` replaced. Quirks are replaced
+    /// one by one, so they apply over the platform of these settings.
+    fn merge(&self, overrides: &RomSettings) -> RomSettings {
+        let mut quirks = self.quirks.clone();
+        quirks.retain(|(field, _)| overrides.quirks.iter().all(|(other, _)| other != field));
+        quirks.extend(overrides.quirks.iter().cloned());
+        RomSettings {
+            title: overrides.title.clone().or_else(|| self.title.clone()),
+            author: overrides.author.clone().or_else(|| self.author.clone()),
+            platform: overrides.platform.or(self.platform),
+            quirks,
+            tickrate: overrides.tickrate.or(self.tickrate),
+            bindings: self.bindings.merge(&overrides.bindings),
+            palette: overrides.palette.or(self.palette),
+        }
+    }
+}
+
+/// ROM settings by CRC-32 of the ROM.
+#[derive(Debug, Default)]
+pub struct RomDatabase {
+    roms: HashMap<u32, RomSettings>,
+}
+
+impl RomDatabase {
+    /// Returns the database bundled with chipster.
+    pub fn bundled() -> RomDatabase {
+        let source = include_str!("../roms.toml");
+        let root = source.parse::<Value>().expect("bundled ROM database should be valid TOML");
+        RomDatabase::from_root(root.as_table().unwrap())
+            .expect("bundled ROM database should be valid")
+    }
+
+    /// Reads the `roms` table of a parsed config file.
+    fn from_root(root: &Table) -> Result<RomDatabase> {
+        let mut roms = HashMap::new();
+        if let Some(sections) = root.get("roms") {
+            for (name, section) in table("roms", sections)? {
+                let crc = u32::from_str_radix(name, 16)
+                    .map_err(|_| format!("'{}' isn't a CRC-32 of a ROM", name))?;
+                let settings = RomSettings::parse(table(name, section)?)
+                    .map_err(|e| format!("ROM {}: {}", name, e))?;
+                roms.insert(crc, settings);
+            }
+        }
+        Ok(RomDatabase { roms })
+    }
+
+    pub fn get(&self, rom: &[u8]) -> Option<&RomSettings> {
+        self.roms.get(&crc32(rom))
+    }
+}
+
+/// The user config file.
+#[derive(Debug, Default)]
+pub struct Config {
+    pub bindings: Overrides,
+    pub roms: RomDatabase,
+}
+
+impl Config {
+    /// Reads the config from `file_name`, or from the default location if it's `None`.
+    ///
+    /// A missing file at the default location is the same as an empty one.
+    pub fn load(file_name: Option<&str>) -> Result<Config> {
+        let path = match file_name {
+            Some(file_name) => PathBuf::from(file_name),
+            None => match default_path() {
+                Some(ref path) if path.exists() => path.clone(),
+                _ => return Ok(Config::default()),
+            },
+        };
+        let source = String::from_utf8(read_file(&path)?)
+            .map_err(|_| format!("{}: not UTF-8", path.display()))?;
+        Config::parse(&source).map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+
+    fn parse(source: &str) -> Result<Config> {
+        let root = source.parse::<Value>().map_err(|e| e.to_string())?;
+        let root = root.as_table().unwrap();
+        let mut bindings = Overrides::default();
+        for (field, value) in root {
+            match field.as_str() {
+                "keys" => bindings.parse_keys(table(field, value)?)?,
+                "actions" => bindings.parse_actions(table(field, value)?)?,
+                "roms" => {}
+                _ => bail!("unknown section '{}'", field),
+            }
+        }
+        Ok(Config {
+            bindings,
+            roms: RomDatabase::from_root(root)?,
+        })
+    }
+
+    /// Returns settings of `rom` from `database` with the user overrides applied.
+    ///
+    /// Bindings of the user config apply over the bundled ones of the ROM, and bindings of
+    /// the ROM section of the user config apply over both.
+    pub fn rom_settings(&self, database: &RomDatabase, rom: &[u8]) -> RomSettings {
+        let mut bundled = database.get(rom).cloned().unwrap_or_default();
+        bundled.bindings = bundled.bindings.merge(&self.bindings);
+        match self.roms.get(rom) {
+            Some(overrides) => bundled.merge(overrides),
+            None => bundled,
+        }
+    }
+}
+
+/// Returns `$XDG_CONFIG_HOME/chipster/config.toml`, `~/.config` if the variable isn't set.
+pub fn default_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("chipster").join("config.toml"))
+}
+
+/// Sets the field of `quirks` named `field`, returning `false` if there is no such field.
+fn set_quirk(quirks: &mut Quirks, field: &str, set: bool) -> bool {
+    match field {
+        "shift_uses_vy" => quirks.shift_uses_vy = set,
+        "load_store_increments_i" => quirks.load_store_increments_i = set,
+        "logic_resets_vf" => quirks.logic_resets_vf = set,
+        "clip_sprites" => quirks.clip_sprites = set,
+        "jump_uses_vx" => quirks.jump_uses_vx = set,
+        "display_wait" => quirks.display_wait = set,
+        "extended_memory" => quirks.extended_memory = set,
+        _ => return false,
+    }
+    true
+}
+
+fn string<'a>(field: &str, value: &'a Value) -> Result<&'a str> {
+    value.as_str().ok_or_else(|| format!("'{}' should be a string", field).into())
+}
+
+fn table<'a>(field: &str, value: &'a Value) -> Result<&'a Table> {
+    value.as_table().ok_or_else(|| format!("'{}' should be a table", field).into())
+}
+
+fn parse_palette(value: &Value) -> Result<Palette> {
+    let colors = match value.as_array() {
+        Some(colors) if colors.len() == 4 => colors,
+        _ => bail!("colors should be a list of 4 colors"),
+    };
+    let mut palette = DEFAULT_PALETTE;
+    for (color, value) in palette.iter_mut().zip(colors) {
+        let hex = string("colors", value)?;
+        let rgb = match hex.strip_prefix('#') {
+            Some(digits) if digits.len() == 6 => u32::from_str_radix(digits, 16).ok(),
+            _ => None,
+        };
+        let rgb = rgb.ok_or_else(|| format!("'{}' isn't a color in #rrggbb format", hex))?;
+        *color = ((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8);
+    }
+    Ok(palette)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM: [u8; 4] = [0x12, 0x00, 0x00, 0xE0];
+
+    fn section(source: &str) -> Result<RomSettings> {
+        let root = source.parse::<Value>().unwrap();
+        RomSettings::parse(root.as_table().unwrap())
+    }
+
+    fn database(source: &str) -> RomDatabase {
+        let source = source.replace("CRC", &format!("{:08x}", crc32(&ROM)));
+        RomDatabase::from_root(source.parse::<Value>().unwrap().as_table().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn parses_rom_settings() {
+        let settings = section(
+            "title = \"Brix\"\n\
+             platform = \"vip\"\n\
+             quirks = { clip_sprites = false }\n\
+             tickrate = 15\n\
+             colors = [\"#000000\", \"#33ff66\", \"#ff3366\", \"#ffffff\"]\n",
+        ).unwrap();
+
+        let mut quirks = Quirks::cosmac_vip();
+        quirks.clip_sprites = false;
+        assert_eq!(settings.title.as_deref(), Some("Brix"));
+        assert_eq!(settings.quirks(), Some(quirks));
+        assert_eq!(settings.tickrate, Some(15));
+        assert_eq!(settings.palette.unwrap()[1], (0x33, 0xFF, 0x66));
+
+        // Quirks without a platform apply over the default ones.
+        let settings = section("quirks = { shift_uses_vy = true }").unwrap();
+        let quirks = Quirks {
+            shift_uses_vy: true,
+            ..Quirks::default()
+        };
+        assert_eq!(settings.quirks(), Some(quirks));
+        assert_eq!(section("").unwrap().quirks(), None);
+    }
+
+    #[test]
+    fn rejects_invalid_rom_settings() {
+        assert!(section("platform = \"c64\"").is_err());
+        assert!(section("quirks = { wrap_sprites = true }").is_err());
+        assert!(section("quirks = { clip_sprites = 1 }").is_err());
+        assert!(section("tickrate = 0").is_err());
+        assert!(section("colors = [\"#000000\"]").is_err());
+        assert!(section("speed = 10").is_err());
+        assert!(Config::parse("[roms.xyz]\ntitle = \"?\"").is_err());
+    }
+
+    #[test]
+    fn user_settings_override_bundled_ones() {
+        let bundled = database(
+            "[roms.CRC]\n\
+             title = \"Brix\"\n\
+             platform = \"vip\"\n\
+             tickrate = 10\n",
+        );
+        let config = Config::parse(
+            &"[roms.CRC]\n\
+              platform = \"schip\"\n"
+                .replace("CRC", &format!("{:08x}", crc32(&ROM))),
+        ).unwrap();
+
+        let settings = config.rom_settings(&bundled, &ROM);
+        assert_eq!(settings.title.as_deref(), Some("Brix"));
+        assert_eq!(settings.quirks(), Some(Quirks::super_chip()));
+        assert_eq!(settings.tickrate, Some(10));
+
+        let settings = config.rom_settings(&bundled, &[0x00, 0xE0]);
+        assert!(settings.title.is_none() && settings.quirks().is_none());
+    }
+
+    #[test]
+    fn user_quirks_apply_over_bundled_platform() {
+        let bundled = database(
+            "[roms.CRC]\n\
+             platform = \"vip\"\n\
+             quirks = { display_wait = false, clip_sprites = false }\n",
+        );
+        let config = Config::parse(
+            &"[roms.CRC]\n\
+              quirks = { clip_sprites = true, shift_uses_vy = false }\n"
+                .replace("CRC", &format!("{:08x}", crc32(&ROM))),
+        ).unwrap();
+
+        let quirks = Quirks {
+            display_wait: false,
+            shift_uses_vy: false,
+            ..Quirks::cosmac_vip()
+        };
+        assert_eq!(config.rom_settings(&bundled, &ROM).quirks(), Some(quirks));
+    }
+
+    #[test]
+    fn rom_sections_override_key_bindings() {
+        use bindings::Bindings;
+        use sdl2::keyboard::Keycode;
+
+        let bundled = database(
+            "[roms.CRC]\n\
+             keys = { 4 = [\"Q\", \"Left\"], 6 = [\"E\", \"Right\"] }\n",
+        );
+        let config = Config::parse(
+            &"[keys]\n\
+              4 = \"Left\"\n\
+              5 = [\"W\", \"Up\"]\n\
+              [roms.CRC]\n\
+              keys = { 6 = \"Left\" }\n"
+                .replace("CRC", &format!("{:08x}", crc32(&ROM))),
+        ).unwrap();
+
+        // The user config replaces the bundled keys of key 4, and the ROM section of the
+        // user config takes Left for key 6.
+        let bindings = Bindings::new(&config.rom_settings(&bundled, &ROM).bindings);
+        assert_eq!(bindings.key(Keycode::Left), Some(0x6));
+        assert_eq!(bindings.key(Keycode::Q), None);
+        assert_eq!(bindings.key(Keycode::Right), None);
+        assert_eq!(bindings.key(Keycode::Up), Some(0x5));
+        assert_eq!(bindings.key(Keycode::W), Some(0x5));
+
+        // Other ROMs only get the keys of the user config.
+        let bindings = Bindings::new(&config.rom_settings(&bundled, &[0x00, 0xE0]).bindings);
+        assert_eq!(bindings.key(Keycode::Left), Some(0x4));
+        assert_eq!(bindings.key(Keycode::Q), None);
+        assert_eq!(bindings.key(Keycode::E), Some(0x6));
+    }
+
+    #[test]
+    fn bundled_database_is_valid() {
+        let database = RomDatabase::bundled();
+        assert!(!database.roms.is_empty());
+        for settings in database.roms.values() {
+            assert!(settings.title.is_some());
+            assert!(settings.platform.is_some() && settings.tickrate.is_some());
+        }
+    }
+}
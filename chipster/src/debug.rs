@@ -0,0 +1,127 @@
+use chip8::{Vm, Access};
+use chip8::debugger::{Debugger, Stop};
+use chip8::instruction::{Instruction, InstructionWord, Reg};
+
+use bindings::{Action, Bindings};
+
+/// How many instructions before and after PC are disassembled.
+const DISASM_BEFORE: u16 = 4;
+const DISASM_AFTER: u16 = 6;
+
+/// How many rows of 16 bytes are dumped, starting at the row containing I.
+const MEMORY_ROWS: usize = 6;
+
+/// Why the debugger has paused, shown on the top of the panel.
+pub enum Status {
+    Stopped(Stop),
+    Paused,
+    Fault(chip8::Error),
+}
+
+/// Debugger actions with their labels, listed with their keys on the bottom of the panel.
+const HINTS: [(Action, &str); 5] = [
+    (Action::Debugger, "CONTINUE"),
+    (Action::StepOver, "OVER"),
+    (Action::StepInto, "INTO"),
+    (Action::StepOut, "OUT"),
+    (Action::ToggleBreakpoint, "BREAKPOINT"),
+];
+
+/// Formats the debugger panel: registers, stack, disassembly around PC, memory at I and keys
+/// of debugger actions in `bindings`.
+pub fn panel_lines(
+    vm: &Vm,
+    debugger: &Debugger,
+    status: &Status,
+    bindings: &Bindings,
+) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    lines.push(match *status {
+        Status::Stopped(Stop::Breakpoint(addr)) => format!("BREAKPOINT AT 0x{:03X}", addr),
+        Status::Stopped(Stop::Step) => "STEP".to_string(),
+        Status::Stopped(Stop::Watchpoint(hit)) => {
+            let access = match hit.access {
+                Access::Read => "READ",
+                Access::Write => "WRITE",
+                Access::Execute => "EXECUTE",
+            };
+            format!(
+                "{} 0x{:03X}: {:02X} -> {:02X} BY 0x{:03X}",
+                access,
+                hit.addr,
+                hit.old,
+                hit.new,
+                hit.pc
+            )
+        }
+        Status::Paused => "PAUSED".to_string(),
+        Status::Fault(ref message) => format!("FAULT: {}", message),
+    });
+
+    lines.push(format!(
+        "PC 0x{:03X}  I 0x{:03X}  DT {:02X}  ST {:02X}",
+        vm.pc(),
+        vm.i(),
+        vm.delay_timer(),
+        vm.sound_timer()
+    ));
+    for half in 0..2 {
+        let regs: Vec<_> = (half * 8..half * 8 + 8)
+            .map(|index| {
+                let reg = Reg::from_index(index);
+                format!("{} {:02X}", reg, vm.reg(reg))
+            })
+            .collect();
+        lines.push(regs.join("  "));
+    }
+    let frames: Vec<_> = vm.stack().iter().map(|addr| format!("0x{:03X}", addr)).collect();
+    lines.push(format!("STACK {}", frames.join(" ")));
+    lines.push(String::new());
+
+    let first = vm.pc().saturating_sub(DISASM_BEFORE * 2);
+    for index in 0..DISASM_BEFORE + 1 + DISASM_AFTER {
+        let addr = match first.checked_add(index * 2) {
+            Some(addr) => addr,
+            None => break,
+        };
+        let bytes = match vm.memory.get(addr as usize..addr as usize + 2) {
+            Some(bytes) => bytes,
+            None => break,
+        };
+        let word = (bytes[0] as u16) << 8 | bytes[1] as u16;
+        let marker = if addr == vm.pc() {
+            '>'
+        } else if debugger.breakpoints().contains(&addr) {
+            '*'
+        } else {
+            ' '
+        };
+        let mnemonic = match Instruction::decode(InstructionWord(word)) {
+            Ok(instruction) => instruction.to_string(),
+            Err(_) => format!("DW 0x{:04X}", word),
+        };
+        lines.push(format!("{} 0x{:03X}  {:04X}  {}", marker, addr, word, mnemonic));
+    }
+    lines.push(String::new());
+
+    let row_start = (vm.i() & !0xF) as usize;
+    for row in 0..MEMORY_ROWS {
+        let addr = row_start + row * 16;
+        let bytes = match vm.memory.get(addr..addr + 16) {
+            Some(bytes) => bytes,
+            None => break,
+        };
+        let hex: Vec<_> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        lines.push(format!("0x{:03X}  {}", addr, hex.join(" ")));
+    }
+
+    let hints: Vec<_> = HINTS
+        .iter()
+        .filter_map(|&(action, label)| {
+            bindings.combo(action).map(|combo| format!("{} {}", combo.name(), label))
+        })
+        .collect();
+    lines.push(hints.join("  "));
+    lines
+}
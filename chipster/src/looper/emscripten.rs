@@ -7,7 +7,6 @@ use super::{Step, Result};
 #[allow(non_camel_case_types)]
 type em_callback_func = unsafe extern "C" fn();
 
-#[link_args = "-s USE_SDL=2"]
 extern "C" {
     pub fn emscripten_set_main_loop(
         func: em_callback_func,
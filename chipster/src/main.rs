@@ -1,27 +1,41 @@
- #![feature(link_args)]
-
 extern crate clap;
 #[macro_use]
 extern crate error_chain;
 extern crate chip8;
-extern crate rand;
 extern crate sdl2;
+extern crate toml;
 extern crate void;
 
 mod beep;
+mod bindings;
+mod config;
+mod debug;
 mod render;
 mod looper;
+mod rewind;
+mod text;
 
+use bindings::{Action, Bindings, Overrides};
+use config::{Config, RomDatabase};
+use debug::Status;
 use looper::Step;
 use render::RenderBuf;
+use rewind::Rewind;
 
 use void::Void;
 
-use chip8::{Vm, Env};
+use chip8::{Vm, Env, Quirks, Watchpoint};
+use chip8::debugger::Debugger;
+use chip8::display::{Palette, DEFAULT_PALETTE};
+use chip8::gdb::GdbStub;
+use chip8::movie::Movie;
+use chip8::random;
+use chip8::trace::{self, TraceWriter};
 
 use std::path::Path;
-use std::io;
+use std::io::BufWriter;
 use std::fs::File;
+use std::net::TcpStream;
 
 use sdl2::keyboard::Keycode;
 use sdl2::rect::Rect;
@@ -30,17 +44,37 @@ use sdl2::pixels::Color;
 use sdl2::render::{Canvas, BlendMode};
 use sdl2::video::Window;
 
-error_chain! {
-    foreign_links {
-        Chip8(chip8::Error);
-        Io(io::Error);
+mod errors {
+    // Code generated by `error_chain!` calls `Error::description` and `Error::cause`.
+    #![allow(deprecated)]
+
+    error_chain! {
+        foreign_links {
+            Chip8(::chip8::Error);
+            Io(::std::io::Error);
+        }
     }
 }
 
+pub use errors::*;
+
 struct CommandArgs {
     rom_file_name: String,
-    cycles_per_second: u32, // default: 500
+    cycles_per_second: Option<u32>, // default: 500
     pixel_decay_time: f32,
+    quirks: Option<Quirks>,
+    random: String,
+    seed: u32,
+    rewind_frames: usize,
+    breakpoints: Vec<u16>,
+    watchpoints: Vec<Watchpoint>,
+    gdb_port: Option<u16>,
+    trace_file_name: Option<String>,
+    trace_format: trace::Format,
+    record_file_name: Option<String>,
+    play_file_name: Option<String>,
+    config_file_name: Option<String>,
+    bindings: Vec<String>,
 }
 
 impl CommandArgs {
@@ -72,33 +106,234 @@ impl CommandArgs {
                     .help("How many seconds takes for pixel from lit to non-lit")
                     .takes_value(true),
             )
+            .arg(
+                Arg::with_name("rewind frames")
+                    .short("r")
+                    .long("rewind-frames")
+                    .value_name("rewind_frames")
+                    .help(
+                        "How many frames of history, 60 per second, to keep for rewinding \
+                       with Backspace. 0 disables rewinding.",
+                    )
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("quirks")
+                    .short("q")
+                    .long("quirks")
+                    .value_name("quirks")
+                    .help("Interpretation of ambiguous instructions")
+                    .possible_values(Quirks::NAMES)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("random")
+                    .long("random")
+                    .value_name("source")
+                    .help(
+                        "Source of random numbers for RND, xorshift by default. vip runs \
+                         the routine of the COSMAC VIP interpreter",
+                    )
+                    .possible_values(random::NAMES)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("seed")
+                    .long("seed")
+                    .value_name("seed")
+                    .help(
+                        "Seed of the random source, taken from the clock by default. \
+                       Runs with the same seed and input are the same.",
+                    )
+                    .takes_value(true)
+                    .validator(|s| validate(&s, |s| s.parse::<u32>().ok(), "seed")),
+            )
+            .arg(
+                Arg::with_name("breakpoint")
+                    .short("b")
+                    .long("break")
+                    .value_name("addr")
+                    .help(
+                        "Pause in the debugger before executing the instruction at the \
+                       given hexadecimal address. Can be specified multiple times.",
+                    )
+                    .takes_value(true)
+                    .validator(|s| validate(&s, parse_addr, "hexadecimal address"))
+                    .multiple(true)
+                    .number_of_values(1),
+            )
+            .arg(
+                Arg::with_name("watchpoint")
+                    .short("w")
+                    .long("watch")
+                    .value_name("range")
+                    .help(
+                        "Pause in the debugger when memory in the given hexadecimal range \
+                       is accessed, e.g. 0x300-0x30F:rw. The range end is inclusive, access \
+                       kinds are any of r (read), w (write) and x (execute), w by default. \
+                       Can be specified multiple times.",
+                    )
+                    .takes_value(true)
+                    .validator(|s| validate(&s, parse_watchpoint, "watchpoint"))
+                    .multiple(true)
+                    .number_of_values(1),
+            )
+            .arg(
+                Arg::with_name("gdb port")
+                    .long("gdb")
+                    .value_name("port")
+                    .help(
+                        "Wait for GDB to connect to the given port on localhost \
+                       before starting the program.",
+                    )
+                    .takes_value(true)
+                    .validator(|s| validate(&s, |s| s.parse::<u16>().ok(), "port")),
+            )
+            .arg(
+                Arg::with_name("trace file")
+                    .long("trace")
+                    .value_name("file")
+                    .help("Write a trace of every executed instruction to the file")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("trace format")
+                    .long("trace-format")
+                    .value_name("format")
+                    .help("Format of the trace written with --trace")
+                    .possible_values(&["text", "binary"])
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("record file")
+                    .long("record")
+                    .value_name("file")
+                    .help(
+                        "Record keypad input into a movie file on exit. Runs a fixed number \
+                       of instructions per frame and disables rewinding and loading states.",
+                    )
+                    .takes_value(true)
+                    .conflicts_with("play file"),
+            )
+            .arg(
+                Arg::with_name("play file")
+                    .long("play")
+                    .value_name("file")
+                    .help(
+                        "Play a movie file recorded with --record, using its quirks, random \
+                       source and speed.",
+                    )
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("config file")
+                    .long("config")
+                    .value_name("file")
+                    .help(
+                        "Read settings of ROMs overriding the bundled ones from the file. \
+                       $XDG_CONFIG_HOME/chipster/config.toml by default.",
+                    )
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("binding")
+                    .long("bind")
+                    .value_name("target=key")
+                    .help(
+                        "Bind a key to a CHIP-8 key or an action, e.g. 5=Up or pause=P, \
+                       replacing its keys from the config. Can be specified multiple times.",
+                    )
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1),
+            )
             .get_matches();
 
         let cycles_per_second = matches
             .value_of("cycles per second")
-            .and_then(|s| s.parse::<u32>().ok())
-            .unwrap_or(500);
+            .and_then(|s| s.parse::<u32>().ok());
 
         let pixel_decay_time = matches
             .value_of("pixel decay time")
             .and_then(|s| s.parse::<f32>().ok())
             .unwrap_or(0.3);
 
+        let rewind_frames = matches
+            .value_of("rewind frames")
+            .and_then(|s| s.parse::<usize>().ok())
+            .unwrap_or(600);
+
+        let quirks = matches.value_of("quirks").and_then(Quirks::from_name);
+
+        let seed = matches
+            .value_of("seed")
+            .map(|s| s.parse::<u32>().expect("checked by validator"))
+            .unwrap_or_else(clock_seed);
+
+        let breakpoints = matches
+            .values_of("breakpoint")
+            .map(|values| {
+                values.map(|s| parse_addr(s).expect("checked by validator")).collect()
+            })
+            .unwrap_or_default();
+
+        let watchpoints = matches
+            .values_of("watchpoint")
+            .map(|values| {
+                values.map(|s| parse_watchpoint(s).expect("checked by validator")).collect()
+            })
+            .unwrap_or_default();
+
+        let gdb_port = matches.value_of("gdb port").map(|s| {
+            s.parse::<u16>().expect("checked by validator")
+        });
+
+        let trace_format = match matches.value_of("trace format") {
+            Some("binary") => trace::Format::Binary,
+            _ => trace::Format::Text,
+        };
+
         CommandArgs {
             rom_file_name: matches.value_of("ROM_FILE").unwrap().to_string(),
             cycles_per_second,
             pixel_decay_time,
+            quirks,
+            random: matches.value_of("random").unwrap_or("xorshift").to_string(),
+            seed,
+            rewind_frames,
+            breakpoints,
+            watchpoints,
+            gdb_port,
+            trace_file_name: matches.value_of("trace file").map(|s| s.to_string()),
+            trace_format,
+            record_file_name: matches.value_of("record file").map(|s| s.to_string()),
+            play_file_name: matches.value_of("play file").map(|s| s.to_string()),
+            config_file_name: matches.value_of("config file").map(|s| s.to_string()),
+            bindings: matches
+                .values_of("binding")
+                .map(|values| values.map(|s| s.to_string()).collect())
+                .unwrap_or_default(),
         }
     }
 }
 
-fn read_rom<P: AsRef<Path>>(path: P) -> Result<Vec<u8>> {
+/// Returns a seed which differs between runs.
+fn clock_seed() -> u32 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.subsec_nanos())
+        .unwrap_or(0)
+}
+
+fn read_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>> {
     use std::io::Read;
 
-    let mut rom_file = File::open(path)?;
-    let mut rom_buffer = Vec::new();
-    rom_file.read_to_end(&mut rom_buffer)?;
-    Ok(rom_buffer)
+    let mut file = File::open(path)?;
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer)?;
+    Ok(buffer)
 }
 
 fn main() {
@@ -115,8 +350,21 @@ fn do_run() -> Result<Void> {
     #[cfg(target_os = "emscripten")]
     let args = CommandArgs {
         rom_file_name: "file.rom".to_string(),
-        cycles_per_second: 15000,
+        cycles_per_second: Some(15000),
         pixel_decay_time: 0.1,
+        quirks: None,
+        random: "xorshift".to_string(),
+        seed: clock_seed(),
+        rewind_frames: 600,
+        breakpoints: Vec::new(),
+        watchpoints: Vec::new(),
+        gdb_port: None,
+        trace_file_name: None,
+        trace_format: trace::Format::Text,
+        record_file_name: None,
+        play_file_name: None,
+        config_file_name: None,
+        bindings: Vec::new(),
     };
 
     let app = App::new(&args)?;
@@ -124,13 +372,45 @@ fn do_run() -> Result<Void> {
     app.run()
 }
 
+const TIMER_TICK_DURATION: f64 = 1.0 / 60.0;
+
+/// How many times faster the emulation runs while fast-forwarding.
+const FAST_FORWARD_SPEED: f64 = 4.0;
+
+/// Movie being recorded or played, see `chip8::movie`.
+enum MovieMode {
+    Recording(Movie),
+    Playing(Movie),
+}
+
 struct App<'a> {
     command_args: &'a CommandArgs,
     render_buf: RenderBuf,
     vm: Vm,
+    title: String,
+    cycles_per_second: u32,
+    palette: Palette,
+    bindings: Bindings,
+    /// The VM as it was loaded, to reset to.
+    initial_vm: Vm,
     passed_dt: f64,
     paused: bool,
+    rewind: Rewind,
+    rewinding: bool,
+    fast_forward: bool,
     keyboard: [u8; 16],
+    debugger: Debugger,
+    /// Set while the debugger is paused.
+    debug_status: Option<Status>,
+    gdb: Option<GdbStub<TcpStream>>,
+    trace: Option<TraceWriter<BufWriter<File>>>,
+    movie: Option<MovieMode>,
+    /// Frames run while recording or playing a movie.
+    frame: u64,
+    /// Instructions of the current frame executed before the debugger paused.
+    frame_cycles: u64,
+    /// Keypad state of the current frame of the movie.
+    frame_keyboard: [u8; 16],
 }
 
 impl<'a> App<'a> {
@@ -138,20 +418,110 @@ impl<'a> App<'a> {
         let render_buf = RenderBuf::new(command_args.pixel_decay_time);
 
         #[cfg(not(target_os = "emscripten"))]
-        let rom_data = read_rom(&command_args.rom_file_name)?;
+        let rom_data = read_file(&command_args.rom_file_name)?;
 
         #[cfg(target_os = "emscripten")]
         let rom_data = include_bytes!("../../roms/f8z.ch8") as &[u8];
 
-        let vm = Vm::with_rom(&rom_data);
+        // Command line options take precedence over the user config and the bundled database.
+        let config = Config::load(command_args.config_file_name.as_deref())?;
+        let settings = config.rom_settings(&RomDatabase::bundled(), &rom_data);
+        match (&settings.title, &settings.author) {
+            (Some(title), Some(author)) => println!("{} by {}", title, author),
+            (Some(title), None) => println!("{}", title),
+            _ => println!("Unknown ROM, CRC-32 {:08x}", chip8::crc::crc32(&rom_data)),
+        }
+        let quirks = command_args.quirks.or(settings.quirks()).unwrap_or_default();
+        let mut bindings = Overrides::default();
+        for binding in &command_args.bindings {
+            bindings.parse_binding(binding)?;
+        }
+        let bindings = Bindings::new(&settings.bindings.merge(&bindings));
+        let cycles_per_second = command_args
+            .cycles_per_second
+            .or_else(|| settings.tickrate.map(|tickrate| tickrate * 60))
+            .unwrap_or(500);
+
+        let (mut vm, movie) = match command_args.play_file_name {
+            Some(ref play_file_name) => {
+                let source = String::from_utf8_lossy(&read_file(play_file_name)?).into_owned();
+                let movie = Movie::parse(&source)
+                    .map_err(|e| format!("{}: {}", play_file_name, e))?;
+                if !movie.matches_rom(&rom_data) {
+                    bail!("{}: recorded with a different ROM", play_file_name);
+                }
+                (movie.vm(&rom_data)?, Some(MovieMode::Playing(movie)))
+            }
+            None => {
+                let mut vm = Vm::with_rom(&rom_data, quirks)?;
+                vm.set_random(
+                    random::from_name(&command_args.random, command_args.seed)
+                        .ok_or("unknown random source")?,
+                );
+                let movie = command_args.record_file_name.as_ref().map(|_| {
+                    let cycles_per_frame = (cycles_per_second as u64 / 60).max(1);
+                    MovieMode::Recording(Movie::new(
+                        &rom_data,
+                        quirks,
+                        &command_args.random,
+                        command_args.seed,
+                        cycles_per_frame,
+                    ))
+                });
+                (vm, movie)
+            }
+        };
+        for watchpoint in &command_args.watchpoints {
+            vm.add_watchpoint(watchpoint.clone());
+        }
+
+        let mut debugger = Debugger::new();
+        for &addr in &command_args.breakpoints {
+            debugger.add_breakpoint(addr);
+        }
+
+        let trace = match command_args.trace_file_name {
+            Some(ref trace_file_name) => {
+                vm.set_tracing(true);
+                let file = BufWriter::new(File::create(trace_file_name)?);
+                Some(TraceWriter::new(file, command_args.trace_format)?)
+            }
+            None => None,
+        };
+
+        let gdb = match command_args.gdb_port {
+            Some(port) => {
+                println!("Waiting for GDB to connect to localhost:{}", port);
+                let stream = chip8::gdb::accept(("127.0.0.1", port))?;
+                debugger.pause();
+                Some(GdbStub::new(stream))
+            }
+            None => None,
+        };
 
         Ok(App {
-            command_args: command_args,
-            render_buf: render_buf,
-            vm: vm,
+            command_args,
+            render_buf,
+            initial_vm: vm.clone(),
+            vm,
+            title: settings.title.unwrap_or_else(|| "chipster".to_string()),
+            cycles_per_second,
+            palette: settings.palette.unwrap_or(DEFAULT_PALETTE),
+            bindings,
             passed_dt: 0f64,
             paused: false,
+            rewind: Rewind::new(command_args.rewind_frames),
+            rewinding: false,
+            fast_forward: false,
             keyboard: [0; 16],
+            debugger,
+            debug_status: None,
+            gdb,
+            trace,
+            movie,
+            frame: 0,
+            frame_cycles: 0,
+            frame_keyboard: [0; 16],
         })
     }
 
@@ -159,7 +529,7 @@ impl<'a> App<'a> {
         let ctx = sdl2::init().unwrap();
         let video_ctx = ctx.video().unwrap();
         let window = video_ctx
-            .window("chipster", 640, 320)
+            .window(&self.title, 640, 320)
             .position_centered()
             .opengl()
             .build()
@@ -178,17 +548,34 @@ impl<'a> App<'a> {
         let main_loop = || {
             for event in events.poll_iter() {
                 match event {
-                    Event::Quit { .. } |
-                    Event::KeyDown { keycode: Some(Keycode::Escape), .. } => return Ok(Step::Done),
-                    Event::KeyUp { keycode: Some(Keycode::Space), .. } => {
-                        self.paused = !self.paused
+                    Event::Quit { .. } => return self.finish(),
+                    Event::KeyDown { keycode: Some(keycode), keymod, repeat, .. } => {
+                        match self.bindings.action(keycode, keymod) {
+                            Some(Action::Quit) => return self.finish(),
+                            Some(action) if action.is_debugger_command() => {
+                                if self.debugger.is_paused() {
+                                    self.start_action(action);
+                                } else {
+                                    self.handle_key(keycode, true);
+                                }
+                            }
+                            Some(action) if !repeat => self.start_action(action),
+                            Some(_) => {}
+                            None => self.handle_key(keycode, true),
+                        }
+                    }
+                    Event::KeyUp { keycode: Some(keycode), .. } => {
+                        for action in self.bindings.held_actions(keycode) {
+                            self.stop_action(action);
+                        }
+                        self.handle_key(keycode, false)
                     }
-                    Event::KeyDown { keycode: Some(keycode), .. } => self.handle_key(keycode, true),
-                    Event::KeyUp { keycode: Some(keycode), .. } => self.handle_key(keycode, false),
                     _ => {}
                 }
             }
 
+            self.poll_gdb();
+
             if self.paused {
                 beeper.set_beeping(false)?;
                 return Ok(Step::Cont);
@@ -198,9 +585,24 @@ impl<'a> App<'a> {
             let dt = (current_ticks - last_ticks) as f64 / 1000.0;
             last_ticks = current_ticks;
 
-            self.update(dt)?;
+            if self.debugger.is_paused() {
+                beeper.set_beeping(false)?;
+                self.render(&mut canvas);
+                return Ok(Step::Cont);
+            }
+
+            if self.rewinding {
+                self.step_back(dt);
+            } else {
+                self.update(if self.fast_forward { dt * FAST_FORWARD_SPEED } else { dt })?;
+            }
             self.render(&mut canvas);
 
+            if self.vm.is_halted() {
+                return self.finish();
+            }
+
+            beeper.set_pattern(self.vm.audio_pattern(), self.vm.audio_sample_rate());
             beeper.set_beeping(self.vm.is_beeping())?;
 
             Ok(Step::Cont)
@@ -209,71 +611,356 @@ impl<'a> App<'a> {
         looper::start_loop(main_loop)
     }
 
+    fn start_action(&mut self, action: Action) {
+        let result = match action {
+            Action::Quit => Ok(()),
+            Action::Pause => {
+                self.paused = !self.paused;
+                Ok(())
+            }
+            Action::Reset => self.reset(),
+            // Rewinding would make the movie differ from what was played.
+            Action::Rewind => {
+                self.rewinding = self.movie.is_none();
+                Ok(())
+            }
+            Action::FastForward => {
+                self.fast_forward = true;
+                Ok(())
+            }
+            Action::Screenshot => self.screenshot(),
+            Action::Debugger => {
+                self.toggle_debugger();
+                Ok(())
+            }
+            Action::StepOver | Action::StepInto | Action::StepOut | Action::ToggleBreakpoint => {
+                self.debugger_command(action);
+                Ok(())
+            }
+            Action::SaveState(slot) => {
+                self.save_state(slot).map_err(|e| format!("Save state slot {}: {}", slot, e).into())
+            }
+            Action::LoadState(slot) => {
+                self.load_state(slot).map_err(|e| format!("Save state slot {}: {}", slot, e).into())
+            }
+        };
+        if let Err(e) = result {
+            println!("{}", e);
+        }
+    }
+
+    fn stop_action(&mut self, action: Action) {
+        match action {
+            Action::Rewind => self.rewinding = false,
+            Action::FastForward => self.fast_forward = false,
+            _ => {}
+        }
+    }
+
+    /// Restarts the ROM with the same settings.
+    fn reset(&mut self) -> Result<()> {
+        if self.movie.is_some() {
+            bail!("Can't reset while recording or playing a movie");
+        }
+        self.vm = self.initial_vm.clone();
+        self.render_buf = RenderBuf::new(self.command_args.pixel_decay_time);
+        self.debugger.resume();
+        self.debug_status = None;
+        Ok(())
+    }
+
+    /// Writes the screen into the first free `ROM_FILE.N.png`, without pixel decay.
+    fn screenshot(&self) -> Result<()> {
+        use std::io::Write;
+
+        let pixels = self.render_buf.display_state().pixels;
+        let rgb: Vec<u8> = pixels
+            .iter()
+            .flat_map(|&pixel| {
+                let (r, g, b) = self.palette[pixel as usize & 3];
+                vec![r, g, b]
+            })
+            .collect();
+        let png = chip8::png::encode_rgb(self.render_buf.width(), self.render_buf.height(), &rgb);
+
+        let file_name = (1..)
+            .map(|n| format!("{}.{}.png", self.command_args.rom_file_name, n))
+            .find(|file_name| !Path::new(file_name).exists())
+            .unwrap();
+        File::create(&file_name)?.write_all(&png)?;
+        println!("Screenshot written to {}", file_name);
+        Ok(())
+    }
+
+    /// Breaks into the debugger, or continues execution if it's already paused.
+    fn toggle_debugger(&mut self) {
+        if self.debugger.is_paused() {
+            self.debugger.resume();
+            self.debug_status = None;
+        } else {
+            self.debugger.pause();
+            self.debug_status = Some(Status::Paused);
+        }
+    }
+
+    /// Processes commands from GDB, if it's attached.
+    fn poll_gdb(&mut self) {
+        let result = match self.gdb {
+            Some(ref mut stub) => stub.poll(&mut self.vm, &mut self.debugger),
+            None => return,
+        };
+        if let Err(e) = result {
+            println!("GDB: {}", e);
+            self.detach_gdb();
+        } else if self.gdb.as_ref().is_some_and(|stub| !stub.is_attached()) {
+            self.detach_gdb();
+        }
+    }
+
+    /// Forwards the reason of the debugger pause to GDB, if it's attached.
+    fn report_to_gdb(&mut self, status: &Status) {
+        let result = match (self.gdb.as_mut(), status) {
+            (Some(stub), Status::Stopped(stop)) => stub.report_stop(*stop),
+            (Some(stub), Status::Fault(error)) => stub.report_fault(error),
+            _ => return,
+        };
+        if let Err(e) = result {
+            println!("GDB: {}", e);
+            self.detach_gdb();
+        }
+    }
+
+    fn detach_gdb(&mut self) {
+        self.gdb = None;
+        if self.debug_status.is_none() {
+            self.debugger.resume();
+        }
+    }
+
+    /// Runs an action of the paused debugger. Unlike other actions, they repeat while their
+    /// key is held.
+    fn debugger_command(&mut self, action: Action) {
+        match action {
+            Action::StepOver => self.debugger.step_over(&self.vm),
+            Action::StepInto => self.debugger.step_into(),
+            Action::StepOut => self.debugger.step_out(&self.vm),
+            Action::ToggleBreakpoint => {
+                self.debugger.toggle_breakpoint(self.vm.pc());
+                return;
+            }
+            _ => return,
+        }
+        self.debug_status = None;
+    }
+
+    /// Restores the machine to recorded frames, one per timer tick of `dt`.
+    fn step_back(&mut self, dt: f64) {
+        self.passed_dt += dt;
+        while self.passed_dt >= TIMER_TICK_DURATION {
+            self.passed_dt -= TIMER_TICK_DURATION;
+            if let Some(state) = self.rewind.pop() {
+                let display_state = self.vm.load_state(&state).expect("rewind state is valid");
+                self.render_buf.restore_display_state(&display_state);
+            }
+        }
+        self.render_buf.update(dt as f32);
+    }
+
+    fn state_file_name(&self, slot: u8) -> String {
+        format!("{}.state{}", self.command_args.rom_file_name, slot)
+    }
+
+    fn save_state(&self, slot: u8) -> Result<()> {
+        use std::io::Write;
+
+        let state = self.vm.save_state(&self.render_buf.display_state());
+        let mut state_file = File::create(self.state_file_name(slot))?;
+        state_file.write_all(&state)?;
+        Ok(())
+    }
+
+    fn load_state(&mut self, slot: u8) -> Result<()> {
+        if self.movie.is_some() {
+            bail!("states can't be loaded while recording or playing a movie");
+        }
+        let state = read_file(self.state_file_name(slot))?;
+        let display_state = self.vm.load_state(&state)?;
+        self.render_buf.restore_display_state(&display_state);
+        Ok(())
+    }
+
     fn handle_key(&mut self, keycode: Keycode, down: bool) {
-        if let Some(pressed_key) = map_keycode(keycode) {
+        if let Some(pressed_key) = self.bindings.key(keycode) {
             self.keyboard[pressed_key] = if down { 1 } else { 0 };
         }
     }
 
     fn update(&mut self, dt: f64) -> Result<()> {
-        const TIMER_TICK_DURATION: f64 = 1.0 / 60.0;
-
-        // See "Secrets of emulation" chapter
-        // in https://github.com/AfBu/haxe-chip-8-emulator/wiki/(Super)CHIP-8-Secrets
-
-        // TODO: Test for low values.
-        let cycles_to_perform = (dt * self.command_args.cycles_per_second as f64).floor() as usize;
-        let dt_per_cycle = dt / cycles_to_perform as f64;
-        // println!("dt={}, dt_per_cycle={}, cycles_to_perform={}",
-        //          dt,
-        //          dt_per_cycle,
-        //          cycles_to_perform);
-
-        for _cycle_number in 0..cycles_to_perform {
-            // println!("{}/{}", _cycle_number, cycles_to_perform);
-
-            let display = self.render_buf.display();
-            self.vm.cycle(&mut Env {
-                display,
-                rng: rand::thread_rng(),
-                keyboard: self.keyboard,
-            })?;
-
-            self.passed_dt += dt_per_cycle;
-            if self.passed_dt > TIMER_TICK_DURATION {
-                let ticks_passed = (self.passed_dt / TIMER_TICK_DURATION) as u8;
-                self.passed_dt -= ticks_passed as f64 * TIMER_TICK_DURATION;
-
-                // println!("updating {} ticks", ticks_passed);
-                self.vm.update_timers(ticks_passed);
+        if self.movie.is_some() {
+            self.update_frames(dt);
+        } else {
+            // See "Secrets of emulation" chapter
+            // in https://github.com/AfBu/haxe-chip-8-emulator/wiki/(Super)CHIP-8-Secrets
+
+            // TODO: Test for low values.
+            let cycles_to_perform = (dt * self.cycles_per_second as f64).floor() as usize;
+            let dt_per_cycle = dt / cycles_to_perform as f64;
+
+            for _cycle_number in 0..cycles_to_perform {
+                let keyboard = self.keyboard;
+                let stopped = self.cycle(keyboard);
+
+                self.passed_dt += dt_per_cycle;
+                if self.passed_dt > TIMER_TICK_DURATION {
+                    let ticks_passed = (self.passed_dt / TIMER_TICK_DURATION) as u8;
+                    self.passed_dt -= ticks_passed as f64 * TIMER_TICK_DURATION;
+                    self.vm.update_timers(ticks_passed);
+                    self.rewind.push(&self.vm, &self.render_buf.display_state());
+                }
+
+                if stopped {
+                    break;
+                }
             }
         }
 
         self.render_buf.update(dt as f32);
 
+        if let Some(ref mut trace) = self.trace {
+            for record in self.vm.take_trace() {
+                trace.write(&record)?;
+            }
+            trace.flush()?;
+        }
+
         Ok(())
     }
 
+    /// Runs whole frames of a fixed number of instructions followed by a timer tick,
+    /// so a movie is played the same way it was recorded.
+    fn update_frames(&mut self, dt: f64) {
+        self.passed_dt += dt;
+        while self.passed_dt >= TIMER_TICK_DURATION {
+            if self.frame_cycles == 0 && !self.start_frame() {
+                return;
+            }
+            let cycles_per_frame = match self.movie {
+                Some(MovieMode::Recording(ref movie)) | Some(MovieMode::Playing(ref movie)) => {
+                    movie.cycles_per_frame
+                }
+                None => return,
+            };
+            while self.frame_cycles < cycles_per_frame {
+                self.frame_cycles += 1;
+                let keyboard = self.frame_keyboard;
+                if self.cycle(keyboard) {
+                    return;
+                }
+            }
+            self.vm.update_timers(1);
+            self.frame += 1;
+            self.frame_cycles = 0;
+            self.passed_dt -= TIMER_TICK_DURATION;
+        }
+    }
+
+    /// Records or plays the keypad state of the frame about to run.
+    ///
+    /// Returns `false` and stops the movie if its playback ended or desynced.
+    fn start_frame(&mut self) -> bool {
+        let display_state = self.render_buf.display_state();
+        let ended = match self.movie {
+            Some(MovieMode::Recording(ref mut movie)) => {
+                movie.record(self.frame, self.keyboard, &self.vm, &display_state);
+                self.frame_keyboard = self.keyboard;
+                false
+            }
+            Some(MovieMode::Playing(ref movie)) => {
+                if self.frame >= movie.frames {
+                    println!("Movie ended at frame {}", self.frame);
+                    true
+                } else if !movie.is_in_sync(self.frame, &self.vm, &display_state) {
+                    println!("Movie desynced at frame {}, playback stopped", self.frame);
+                    true
+                } else {
+                    self.frame_keyboard = movie.keyboard_at(self.frame);
+                    false
+                }
+            }
+            None => false,
+        };
+        if ended {
+            self.movie = None;
+        }
+        !ended
+    }
+
+    /// Executes a single cycle with `keyboard`, returns `true` if the debugger paused.
+    fn cycle(&mut self, keyboard: [u8; 16]) -> bool {
+        let status = match self.debugger.cycle(
+            &mut self.vm,
+            &mut Env {
+                display: self.render_buf.framebuffer_mut(),
+                keyboard,
+            },
+        ) {
+            Ok(stop) => stop.map(Status::Stopped),
+            Err(e) => {
+                println!("Error = {}", e);
+                Some(Status::Fault(e))
+            }
+        };
+
+        match status {
+            Some(status) => {
+                self.report_to_gdb(&status);
+                self.debug_status = Some(status);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Writes the movie being recorded, if any, and exits.
+    fn finish(&mut self) -> Result<Step> {
+        use std::io::Write;
+
+        if let (Some(MovieMode::Recording(ref movie)), Some(ref file_name)) =
+            (&self.movie, &self.command_args.record_file_name)
+        {
+            let mut movie_file = File::create(file_name)?;
+            movie_file.write_all(movie.to_text().as_bytes())?;
+            println!("Movie of {} frames written to {}", movie.frames, file_name);
+        }
+        Ok(Step::Done)
+    }
+
     fn render(&mut self, canvas: &mut Canvas<Window>) {
-        let clear_color = Color::RGB(250, 242, 219);
+        let (r, g, b) = self.palette[0];
+        let clear_color = Color::RGB(r, g, b);
         canvas.set_draw_color(clear_color);
         canvas.clear();
 
-        let (w, h) = match canvas.window().size() {
-            (win_width, win_height) => (
-                (win_width as f64 / 64.0) as u32,
-                (win_height as f64 / 32.0) as u32,
-            ),
-        };
+        let (width, height) = (self.render_buf.width(), self.render_buf.height());
+        let (win_width, win_height) = canvas.window().size();
+        let (w, h) = (
+            (win_width as f64 / width as f64) as u32,
+            (win_height as f64 / height as f64) as u32,
+        );
 
-        for y in 0..32 {
-            for x in 0..64 {
+        for y in 0..height {
+            for x in 0..width {
                 let dx = x as i32 * w as i32;
                 let dy = y as i32 * h as i32;
 
                 match self.render_buf.get_intensity(x, y) {
                     intensity if intensity > 0.0 => {
-                        let solid_color = Color::RGBA(5, 31, 38, (intensity * 255.0) as u8);
+                        let alpha = (intensity * 255.0) as u8;
+                        let (r, g, b) = match self.render_buf.get_color(x, y) {
+                            color @ 2..=3 => self.palette[color as usize],
+                            _ => self.palette[1],
+                        };
+                        let solid_color = Color::RGBA(r, g, b, alpha);
                         canvas.set_draw_color(solid_color);
 
                         let rect = Rect::new(dx, dy, w, h);
@@ -284,42 +971,59 @@ impl<'a> App<'a> {
             }
         }
 
+        if let Some(ref status) = self.debug_status {
+            const SCALE: u32 = 2;
+            const LINE_HEIGHT: i32 = ((text::GLYPH_HEIGHT + 1) * SCALE) as i32;
+
+            let (win_width, win_height) = canvas.window().size();
+            canvas.set_draw_color(Color::RGBA(5, 31, 38, 220));
+            let _ = canvas.fill_rect(Rect::new(0, 0, win_width, win_height));
+
+            canvas.set_draw_color(Color::RGB(250, 242, 219));
+            let lines = debug::panel_lines(&self.vm, &self.debugger, status, &self.bindings);
+            for (index, line) in lines.iter().enumerate() {
+                text::draw_text(canvas, 8, 8 + index as i32 * LINE_HEIGHT, SCALE, line);
+            }
+        }
+
         canvas.present();
     }
 }
 
-fn map_keycode(k: Keycode) -> Option<usize> {
-    // Classical layout, see http://devernay.free.fr/hacks/chip8/C8TECH10.HTM#2.3
-    // +---+---+---+---+
-    // | 1 | 2 | 3 | C |
-    // +---+---+---+---+
-    // | 4 | 5 | 6 | D |
-    // +---+---+---+---+
-    // | 7 | 8 | 9 | E |
-    // +---+---+---+---+
-    // | A | 0 | B | F |
-    // +---+---+---+---+
-
-    match k {
-        Keycode::Num1 => Some(0x1),
-        Keycode::Num2 => Some(0x2),
-        Keycode::Num3 => Some(0x3),
-        Keycode::Num4 => Some(0xC),
-
-        Keycode::Q => Some(0x4),
-        Keycode::W => Some(0x5),
-        Keycode::E => Some(0x6),
-        Keycode::R => Some(0xD),
-
-        Keycode::A => Some(0x7),
-        Keycode::S => Some(0x8),
-        Keycode::D => Some(0x9),
-        Keycode::F => Some(0xE),
-
-        Keycode::Z => Some(0xA),
-        Keycode::X => Some(0x0),
-        Keycode::C => Some(0xB),
-        Keycode::V => Some(0xF),
-        _ => None,
+/// Checks a command line value for clap, `what` names the expected kind of value.
+fn validate<T, F>(s: &str, parse: F, what: &str) -> ::std::result::Result<(), String>
+where
+    F: Fn(&str) -> Option<T>,
+{
+    parse(s).map(|_| ()).ok_or_else(|| format!("'{}' isn't a valid {}", s, what))
+}
+
+/// Parses a hexadecimal address, with or without `0x` prefix.
+fn parse_addr(s: &str) -> Option<u16> {
+    let digits = s.trim_start_matches("0x").trim_start_matches("0X");
+    u16::from_str_radix(digits, 16).ok()
+}
+
+/// Parses a watchpoint in the form `START[-END][:KINDS]`.
+fn parse_watchpoint(s: &str) -> Option<Watchpoint> {
+    let mut parts = s.splitn(2, ':');
+    let range = parts.next().unwrap_or("");
+    let kinds = parts.next().unwrap_or("w");
+
+    let mut bounds = range.splitn(2, '-');
+    let start = parse_addr(bounds.next().unwrap_or(""))?;
+    let end = match bounds.next() {
+        Some(end) => parse_addr(end)?,
+        None => start,
+    };
+    if end < start || kinds.chars().any(|c| !"rwx".contains(c)) {
+        return None;
     }
+
+    Some(Watchpoint {
+        range: start as usize..end as usize + 1,
+        read: kinds.contains('r'),
+        write: kinds.contains('w'),
+        execute: kinds.contains('x'),
+    })
 }
@@ -1,10 +1,4 @@
-
-use std::cell::RefCell;
-use std::rc::Rc;
-use chip8::display as c8_display;
-
-const DISPLAY_WIDTH: usize = 64;
-const DISPLAY_HEIGHT: usize = 32;
+use chip8::display::{DisplayState, Framebuffer, HIGH_RES_WIDTH, HIGH_RES_HEIGHT};
 
 #[derive(Clone, Copy)]
 enum PixelState {
@@ -28,20 +22,21 @@ impl PixelState {
 
 pub struct RenderBuf {
     pixel_decay_time: f32,
-    video_mem: Rc<RefCell<[bool; DISPLAY_WIDTH * DISPLAY_HEIGHT]>>,
-    state: [PixelState; DISPLAY_WIDTH * DISPLAY_HEIGHT],
-    pixel_intensity: [f32; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+    framebuffer: Framebuffer,
+    state: [PixelState; HIGH_RES_WIDTH * HIGH_RES_HEIGHT],
+    pixel_intensity: [f32; HIGH_RES_WIDTH * HIGH_RES_HEIGHT],
+    /// Last lit value of each pixel, so decaying pixels keep their color.
+    pixel_color: [u8; HIGH_RES_WIDTH * HIGH_RES_HEIGHT],
 }
 
 impl RenderBuf {
     pub fn new(pixel_decay_time: f32) -> RenderBuf {
-        let video_mem = Rc::new(RefCell::new([false; DISPLAY_WIDTH * DISPLAY_HEIGHT]));
-
         RenderBuf {
             pixel_decay_time,
-            video_mem,
-            state: [PixelState::Off; DISPLAY_WIDTH * DISPLAY_HEIGHT],
-            pixel_intensity: [0.0; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+            framebuffer: Framebuffer::new(),
+            state: [PixelState::Off; HIGH_RES_WIDTH * HIGH_RES_HEIGHT],
+            pixel_intensity: [0.0; HIGH_RES_WIDTH * HIGH_RES_HEIGHT],
+            pixel_color: [0; HIGH_RES_WIDTH * HIGH_RES_HEIGHT],
         }
     }
 
@@ -51,16 +46,19 @@ impl RenderBuf {
         // delta time represented in pixel age.
         let delta_age = dt / self.pixel_decay_time;
 
-        let new_frame = self.video_mem.borrow_mut();
+        let width = self.framebuffer.width();
 
-        for y in 0..DISPLAY_HEIGHT {
-            for x in 0..DISPLAY_WIDTH {
-                let on = new_frame[y * DISPLAY_WIDTH + x];
-                let prev_state = self.state[y * DISPLAY_WIDTH + x];
+        for (y, row) in self.framebuffer.rows().enumerate() {
+            for (x, &value) in row.iter().enumerate() {
+                let on = value != 0;
+                if on {
+                    self.pixel_color[y * width + x] = value;
+                }
+                let prev_state = self.state[y * width + x];
                 let new_state = prev_state.step(on, delta_age);
 
-                self.state[y * DISPLAY_WIDTH + x] = new_state;
-                self.pixel_intensity[y * DISPLAY_WIDTH + x] = match new_state {
+                self.state[y * width + x] = new_state;
+                self.pixel_intensity[y * width + x] = match new_state {
                     Off => 0.0,
                     On => 1.0,
                     Decaying { age } => Self::pixel_intensity_for_age(age),
@@ -73,50 +71,37 @@ impl RenderBuf {
         // let c = 1.0 - age;
         let c = -(2f32.powf((age - 1.0) * 5.0) + 1.0);
 
-        // clamp
-        f32::min(f32::max(0.0, c), 1.0)
+        c.clamp(0.0, 1.0)
     }
 
-    pub fn get_intensity(&self, x: usize, y: usize) -> f32 {
-        self.pixel_intensity[y * DISPLAY_WIDTH + x]
+    pub fn width(&self) -> usize {
+        self.framebuffer.width()
     }
 
-    pub fn display(&self) -> RenderBufDisplay {
-        RenderBufDisplay { mem: self.video_mem.clone() }
+    pub fn height(&self) -> usize {
+        self.framebuffer.height()
     }
-}
 
-pub struct RenderBufDisplay {
-    mem: Rc<RefCell<[bool; DISPLAY_WIDTH * DISPLAY_HEIGHT]>>,
-}
+    pub fn get_intensity(&self, x: usize, y: usize) -> f32 {
+        self.pixel_intensity[y * self.width() + x]
+    }
 
-impl c8_display::Display for RenderBufDisplay {
-    fn clear(&mut self) {
-        for i in self.mem.borrow_mut().iter_mut() {
-            *i = false;
-        }
+    /// Returns planes which were lit the last time the pixel was on,
+    /// i.e. a value between 1 and 3.
+    pub fn get_color(&self, x: usize, y: usize) -> u8 {
+        self.pixel_color[y * self.width() + x]
     }
 
-    fn draw(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
-        let mut collision_bit = false;
-        let mut mem = self.mem.borrow_mut();
-
-        for (sy, byte) in sprite.iter().enumerate() {
-            let dy = (y + sy) % DISPLAY_HEIGHT;
-            for sx in 0..8 {
-                let bit_mask = 0b1000_0000 >> sx;
-                if (byte & bit_mask) != 0 {
-                    let dx = (x + sx) % DISPLAY_WIDTH;
-                    let index = dy * DISPLAY_WIDTH + dx;
-
-                    if mem[index] {
-                        collision_bit = true;
-                    }
-                    mem[index] ^= true;
-                }
-            }
-        }
+    pub fn display_state(&self) -> DisplayState {
+        self.framebuffer.display_state()
+    }
+
+    pub fn restore_display_state(&mut self, display_state: &DisplayState) {
+        self.framebuffer.restore_display_state(display_state);
+    }
 
-        collision_bit
+    /// Returns the framebuffer to pass to the VM as its display.
+    pub fn framebuffer_mut(&mut self) -> &mut Framebuffer {
+        &mut self.framebuffer
     }
 }
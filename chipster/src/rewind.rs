@@ -0,0 +1,37 @@
+use std::collections::VecDeque;
+
+use chip8::Vm;
+use chip8::display::DisplayState;
+
+/// Ring buffer of per-frame save states of the machine.
+pub struct Rewind {
+    capacity: usize,
+    states: VecDeque<Vec<u8>>,
+}
+
+impl Rewind {
+    /// Creates a buffer holding up to `capacity` frames, zero disables rewinding.
+    pub fn new(capacity: usize) -> Rewind {
+        Rewind {
+            capacity,
+            states: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Records the state of `vm` and `display_state`, discarding the oldest one if the buffer
+    /// is full.
+    pub fn push(&mut self, vm: &Vm, display_state: &DisplayState) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.states.len() == self.capacity {
+            self.states.pop_front();
+        }
+        self.states.push_back(vm.save_state(display_state));
+    }
+
+    /// Takes the most recent state, as saved by `Vm::save_state`, if there is any left.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        self.states.pop_back()
+    }
+}
@@ -2,4 +2,5 @@
 set -e
 
 cargo test -p chip8
+cargo test -p chip8-fuzz
 cargo test -p chipster